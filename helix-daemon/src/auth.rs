@@ -0,0 +1,103 @@
+//! Passphrase hashing for locked sessions (see [`crate::proto::Request::LockSession`]).
+//!
+//! Hashes are self-contained strings of the form `sha256$<hex salt>$<hex
+//! digest>`, so the salt travels with the hash and the daemon never has to
+//! store it separately. Only this string is ever persisted; the plaintext
+//! passphrase is discarded as soon as it's hashed or verified.
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+const SALT_LEN: usize = 16;
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn digest(salt: &[u8], passphrase: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(passphrase.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+/// Hash `passphrase` under a freshly generated random salt, producing a
+/// string suitable for [`crate::proto::Request::LockSession::passphrase_hash`].
+pub fn hash_passphrase(passphrase: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    format!(
+        "sha256${}${}",
+        to_hex(&salt),
+        to_hex(&digest(&salt, passphrase))
+    )
+}
+
+/// Check `attempt` against a hash previously produced by [`hash_passphrase`].
+/// Compares the digest bytes in constant time so a timing side channel can't
+/// be used to guess the passphrase byte by byte. Malformed `hash`es (e.g. one
+/// that wasn't produced by this module) never match.
+pub fn verify_passphrase(hash: &str, attempt: &str) -> bool {
+    let Some((algo, rest)) = hash.split_once('$') else {
+        return false;
+    };
+    let Some((salt_hex, digest_hex)) = rest.split_once('$') else {
+        return false;
+    };
+    if algo != "sha256" {
+        return false;
+    }
+    let (Some(salt), Some(expected)) = (from_hex(salt_hex), from_hex(digest_hex)) else {
+        return false;
+    };
+    constant_time_eq(&digest(&salt, attempt), &expected)
+}
+
+/// Compare two byte slices without short-circuiting on the first mismatch.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_hash_verifies_against_the_passphrase_it_was_made_from() {
+        let hash = hash_passphrase("correct horse battery staple");
+        assert!(verify_passphrase(&hash, "correct horse battery staple"));
+    }
+
+    #[test]
+    fn a_hash_rejects_a_different_passphrase() {
+        let hash = hash_passphrase("correct horse battery staple");
+        assert!(!verify_passphrase(&hash, "wrong passphrase"));
+    }
+
+    #[test]
+    fn two_hashes_of_the_same_passphrase_use_different_salts() {
+        let a = hash_passphrase("hunter2");
+        let b = hash_passphrase("hunter2");
+        assert_ne!(a, b);
+        assert!(verify_passphrase(&a, "hunter2"));
+        assert!(verify_passphrase(&b, "hunter2"));
+    }
+
+    #[test]
+    fn a_malformed_hash_never_matches() {
+        assert!(!verify_passphrase("not a real hash", "anything"));
+    }
+}
@@ -0,0 +1,193 @@
+//! Shell completion scripts for `hxc`, generated on demand via `hxc
+//! --completions bash|zsh|fish` rather than shipped as static files, so they
+//! can be regenerated after a flag is added instead of drifting out of sync
+//! with `hxc.rs`'s own parsing.
+//!
+//! Flags complete statically from a fixed list baked into each script.
+//! Session targets after `-a`/`-k`/`--wait`/`--lock`/`--tag`/`--send`/
+//! `--session-timeout` complete live instead, by shelling back out to `hxc
+//! --list --format ids-and-names` at completion time, so a session created
+//! after the script was generated is still offered.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            other => Err(format!("unknown shell: {other} (expected bash, zsh, or fish)")),
+        }
+    }
+}
+
+/// The completion script for `shell`, as printed verbatim by `hxc
+/// --completions SHELL`.
+pub fn render(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+    }
+}
+
+/// The `hxc --completions SHELL` install one-liner for `shell`, for
+/// `--help` to point at.
+pub fn install_hint(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => "hxc --completions bash >> ~/.bash_completion",
+        Shell::Zsh => "hxc --completions zsh > \"${fpath[1]}/_hxc\"",
+        Shell::Fish => "hxc --completions fish > ~/.config/fish/completions/hxc.fish",
+    }
+}
+
+const BASH: &str = r#"# hxc bash completion. Install with: hxc --completions bash >> ~/.bash_completion
+_hxc() {
+    local cur prev
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+
+    case "$prev" in
+        -a|--attach|-k|--kill|--wait|--lock|--tag|--send|--session-timeout)
+            local targets
+            targets="$(hxc --list --format ids-and-names 2>/dev/null | tr '\t' '\n' | grep -v '^$')"
+            COMPREPLY=($(compgen -W "$targets" -- "$cur"))
+            return 0
+            ;;
+        --format)
+            COMPREPLY=($(compgen -W "table json ids ids-and-names" -- "$cur"))
+            return 0
+            ;;
+        --completions)
+            COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur"))
+            return 0
+            ;;
+        --sort)
+            COMPREPLY=($(compgen -W "id time name" -- "$cur"))
+            return 0
+            ;;
+    esac
+
+    COMPREPLY=($(compgen -W "-a --attach -k --kill --list --stop --swap --lock --tag --session \
+--attach-or-new --wait --send --metrics --stats --prometheus --log-level --session-timeout --completions --print-socket --sort --all \
+--follow --format --json --socket --timeout --reconnect --detach-key --no-detach-key --takeover \
+--if-exists --dry-run --no-autostart --version-check --strict-version --input-buffer --quiet -v -vv -vvv" -- "$cur"))
+}
+complete -F _hxc hxc
+"#;
+
+const ZSH: &str = r#"#compdef hxc
+# hxc zsh completion. Install with: hxc --completions zsh > "${fpath[1]}/_hxc"
+
+_hxc_targets() {
+    local -a targets
+    targets=(${(f)"$(hxc --list --format ids-and-names 2>/dev/null | tr '\t' '\n')"})
+    targets=(${targets:#})
+    _describe 'session' targets
+}
+
+_hxc() {
+    case "$words[CURRENT-1]" in
+        -a|--attach|-k|--kill|--wait|--lock|--tag|--send|--session-timeout)
+            _hxc_targets
+            return
+            ;;
+        --format)
+            _values 'format' table json ids ids-and-names
+            return
+            ;;
+        --completions)
+            _values 'shell' bash zsh fish
+            return
+            ;;
+        --sort)
+            _values 'sort' id time name
+            return
+            ;;
+    esac
+
+    _values -s ' ' 'flag' -a --attach -k --kill --list --stop --swap --lock --tag --session \
+        --attach-or-new --wait --send --metrics --stats --prometheus --log-level --session-timeout --completions --print-socket --sort \
+        --all --follow --format --json --socket --timeout --reconnect --detach-key \
+        --no-detach-key --takeover --if-exists --dry-run --no-autostart --version-check \
+        --strict-version --input-buffer --quiet -v -vv -vvv
+}
+compdef _hxc hxc
+"#;
+
+const FISH: &str = r#"# hxc fish completion. Install with:
+# hxc --completions fish > ~/.config/fish/completions/hxc.fish
+
+function __hxc_targets
+    hxc --list --format ids-and-names 2>/dev/null | string split -f1,2 \t | string match -rv '^$'
+end
+
+complete -c hxc -f
+complete -c hxc -n '__fish_seen_subcommand_from -a --attach -k --kill --wait --lock --tag --send --session-timeout' \
+    -a '(__hxc_targets)'
+complete -c hxc -n '__fish_seen_argument --format' -a 'table json ids ids-and-names'
+complete -c hxc -n '__fish_seen_argument --completions' -a 'bash zsh fish'
+complete -c hxc -n '__fish_seen_argument --sort' -a 'id time name'
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_from_str_accepts_the_three_known_names() {
+        assert_eq!("bash".parse(), Ok(Shell::Bash));
+        assert_eq!("zsh".parse(), Ok(Shell::Zsh));
+        assert_eq!("fish".parse(), Ok(Shell::Fish));
+        assert!("powershell".parse::<Shell>().is_err());
+    }
+
+    #[test]
+    fn render_returns_a_distinct_script_per_shell() {
+        assert_ne!(render(Shell::Bash), render(Shell::Zsh));
+        assert_ne!(render(Shell::Zsh), render(Shell::Fish));
+    }
+
+    /// Best-effort: only runs when `bash` is actually on `PATH`, since this
+    /// is a source tree check, not something the daemon itself needs at
+    /// runtime.
+    #[test]
+    fn bash_completion_script_parses_under_bash_dash_n() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        if Command::new("bash").arg("--version").output().is_err() {
+            return;
+        }
+
+        let mut child = Command::new("bash")
+            .arg("-n")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(render(Shell::Bash).as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "bash -n rejected the completion script: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
@@ -0,0 +1,2808 @@
+use anyhow::{Context, Result};
+use helix_daemon::client::{
+    exit_code, Client, DetachKey, SessionClient, DEFAULT_CONNECT_TIMEOUT, DEFAULT_KILL_WAIT_TIMEOUT,
+    DEFAULT_RECONNECT_TIMEOUT,
+};
+use helix_daemon::error::{ClientError, Error};
+use helix_daemon::logging::looks_like_a_flag;
+use helix_daemon::proto::{
+    FileSpec, KillResult, SessionId, SessionListDelta, SessionStats, SessionSummary, SortBy,
+    PROTO_VERSION,
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[path = "picker.rs"]
+mod picker;
+#[path = "completions.rs"]
+mod completions;
+
+use completions::Shell;
+
+enum Action {
+    New,
+    Attach(SessionId, bool),
+    /// `-a`/`--attach` with no id. If stdin is a terminal and more than one
+    /// session is detached, offers an interactive picker (see
+    /// [`pick_detached_session`]); otherwise attaches to whichever detached
+    /// session was most recently left (see `Request::AttachLast`).
+    AttachLast(bool),
+    /// `--lock ID`: prompts for a passphrase and locks the session behind
+    /// it (see `Request::LockSession`).
+    Lock(SessionId),
+    /// `--session NAME` (aliased `--attach-or-new NAME`): attach to the
+    /// session aliased `NAME`, creating one if it doesn't exist yet (see
+    /// `Request::AttachOrCreate`).
+    NewOrAttach(String),
+    /// `--list`. `follow` streams live updates instead of printing once (see
+    /// `Request::WatchSessions`).
+    List(SortBy, bool, bool),
+    /// `--tag ID +foo -bar`: add/remove tags on a session (see
+    /// `Request::TagSession`).
+    Tag(SessionId, Vec<String>, Vec<String>),
+    /// `--kill TARGET [TARGET...]`, where a `TARGET` is a session id or an
+    /// alias (see [`KillTarget`]), and `-k a,b,c` is shorthand for `-k a -k
+    /// b -k c`. The third field is `--if-exists`: a missing session is
+    /// treated as success instead of [`exit_code::SESSION_NOT_FOUND`], so
+    /// cleanup scripts can kill a session without caring whether it already
+    /// exited. More than one target kills in a single round trip via
+    /// `Request::KillSessions`, after resolving aliases and deduplicating
+    /// locally (see [`plan_kill_targets`]). The last field is `--wait`: don't
+    /// return until the session(s) have actually finished shutting down
+    /// (see `Client::kill_session_wait`). A single-target kill is already
+    /// answered only once its session has terminated, but
+    /// `Request::KillSessions` acknowledges each id as soon as its
+    /// termination is enqueued, before the session task unwinds. A following bare
+    /// `--wait` is parsed as this modifier rather than the standalone
+    /// `Action::Wait` below — see its parsing in `parse_args`.
+    Kill(Vec<KillTarget>, bool, bool, bool),
+    Stop,
+    Swap(SessionId, SessionId),
+    /// `--wait TARGET`, where `TARGET` is a session id or an alias (see
+    /// [`KillTarget`]). Blocks until the session terminates (see
+    /// `Request::WaitSession`) and exits with its exit code, for scripting
+    /// "start a session, block until it ends, then continue". Not reached
+    /// when `--wait` instead modifies a preceding `--kill` (see
+    /// [`Action::Kill`]).
+    Wait(KillTarget),
+    /// `--send TARGET PAYLOAD`, where `TARGET` is a session id or an alias,
+    /// resolved daemon-side (see `Request::SendToSession`). Forwards `PAYLOAD`
+    /// to the target's attached client without attaching itself.
+    Send(String, String),
+    /// `--metrics`: print the daemon's running counters (see
+    /// `Request::Metrics`), one `key value` per line. The field is
+    /// `--prometheus`, rendering in Prometheus's text exposition format
+    /// instead (see [`render_metrics_prometheus`]). `--stats` is an alias
+    /// for `--metrics --prometheus`, for the Prometheus textfile collector.
+    Metrics(bool),
+    /// `--log-level N`: change the running daemon's log level without
+    /// restarting it (see `Request::SetLogLevel`).
+    SetLogLevel(u8),
+    /// `--session-timeout ID DURATION`: override the idle-reap sweep for a
+    /// single session (see `Request::SetSessionTimeout` and
+    /// [`parse_session_timeout`]). `DURATION` of `never` clears the override
+    /// so the session is never reaped.
+    SetTimeout(SessionId, Option<Duration>),
+    /// `--completions bash|zsh|fish`: print a completion script to stdout
+    /// (see the `completions` module). Doesn't touch the daemon at all —
+    /// the script itself shells back out to `hxc --list --format
+    /// ids-and-names` for live session targets when a user presses tab.
+    Completions(Shell),
+    /// `--print-socket`: print the fully resolved socket path (see
+    /// [`display_socket_path`]) and exit, without connecting to anything.
+    /// Lets a script do `HELIX_DAEMON_SOCKET=$(hxc --print-socket)`.
+    PrintSocket,
+    /// `-h`/`--help`: print a short usage summary, including the
+    /// `--completions` install one-liner, and exit.
+    Help,
+}
+
+/// How `Action::List` renders its output. `Table` is the default
+/// human-readable listing; `Json`/`Ids` are for scripts (see `--json`,
+/// `--format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Table,
+    Json,
+    Ids,
+    /// One `id\talias` per line, for shell completion to consume (see
+    /// [`format_sessions_ids_and_names`]). Not otherwise documented as a
+    /// stable scripting format; `json`/`ids` are the supported ones for that.
+    IdsAndNames,
+}
+
+fn parse_format(value: &str) -> Result<Format> {
+    match value {
+        "table" => Ok(Format::Table),
+        "json" => Ok(Format::Json),
+        "ids" => Ok(Format::Ids),
+        "ids-and-names" => Ok(Format::IdsAndNames),
+        other => anyhow::bail!("invalid --format value: {other} (expected table, json, or ids)"),
+    }
+}
+
+/// Render `sessions` as a JSON array of objects with stable field names, for
+/// `--format json`.
+fn format_sessions_json(sessions: &[SessionSummary]) -> String {
+    let values: Vec<serde_json::Value> = sessions.iter().map(session_to_json).collect();
+    serde_json::Value::Array(values).to_string()
+}
+
+/// Render a single session as a JSON object with stable field names,
+/// independent of whatever the human-readable table happens to show.
+fn session_to_json(session: &SessionSummary) -> serde_json::Value {
+    let created_unix_ms = session
+        .created_at
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    serde_json::json!({
+        "id": session.id,
+        "alias": session.alias,
+        "created_unix_ms": created_unix_ms,
+        "attached": session.attached,
+        "cwd": session.cwd,
+        "tags": session.tags,
+        "size": session.size,
+    })
+}
+
+/// Render `sessions` as one id per line, for `--format ids` (shell loops).
+fn format_sessions_ids(sessions: &[SessionSummary]) -> String {
+    sessions
+        .iter()
+        .map(|s| s.id.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Render `sessions` as one `id\talias` per line (alias blank if unset),
+/// for `--format ids-and-names`. Minimal on purpose: it exists for shell
+/// completion (see `completions::bash`/`zsh`/`fish`) to offer live targets
+/// for `-a`/`-k` without shelling out to the much heavier `--format json`.
+fn format_sessions_ids_and_names(sessions: &[SessionSummary]) -> String {
+    sessions
+        .iter()
+        .map(|s| format!("{}\t{}", s.id, s.alias.as_deref().unwrap_or("")))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Render an error as a JSON object, for stderr in JSON/ids mode so scripts
+/// can rely on stdout being pure data even when the command fails.
+fn format_json_error(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+/// Render one `SessionListDelta` as a human-readable line, for `--list --follow`.
+fn describe_delta(delta: &SessionListDelta) -> String {
+    match delta {
+        SessionListDelta::Created { id } => format!("+ session {id} created"),
+        SessionListDelta::Detached { id } => format!("  session {id} detached"),
+        SessionListDelta::Terminated { id } => format!("- session {id} terminated"),
+        SessionListDelta::Aliased { id, alias } => format!("  session {id} renamed to {alias}"),
+    }
+}
+
+/// Render how long a session has sat detached, as a coarse humanized
+/// duration (e.g. "3h", "2d"). Attached sessions, and ones that have never
+/// been detached, show "-".
+fn idle_column(attached: bool, last_detached: Option<SystemTime>) -> String {
+    if attached {
+        return "-".to_string();
+    }
+    let Some(last_detached) = last_detached else {
+        return "-".to_string();
+    };
+    let Ok(elapsed) = SystemTime::now().duration_since(last_detached) else {
+        return "-".to_string();
+    };
+
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{secs}s")
+    } else if secs < 60 * 60 {
+        format!("{}m", secs / 60)
+    } else if secs < 60 * 60 * 24 {
+        format!("{}h", secs / (60 * 60))
+    } else {
+        format!("{}d", secs / (60 * 60 * 24))
+    }
+}
+
+/// Render the `--list` marker for a session's output capture state, e.g.
+/// " [capturing: /var/log/hxd/session-3.log]", or "" if capture is off.
+fn capture_marker(capturing: Option<&str>) -> String {
+    match capturing {
+        Some(path) => format!(" [capturing: {path}]"),
+        None => String::new(),
+    }
+}
+
+/// Render the `--list` marker for a session's dedicated log file (see
+/// `ServerConfig::per_session_logs`), e.g.
+/// " [log: /home/alice/.cache/helix/sessions/3.log]", or "" if per-session
+/// logging is off (or the file failed to open).
+fn log_marker(log_path: Option<&str>) -> String {
+    match log_path {
+        Some(path) => format!(" [log: {path}]"),
+        None => String::new(),
+    }
+}
+
+/// Render a session's tags for `--list`'s table, e.g. `" {foo, wip}"`, or
+/// nothing if it has none.
+fn tags_marker(tags: &[String]) -> String {
+    if tags.is_empty() {
+        String::new()
+    } else {
+        format!(" {{{}}}", tags.join(", "))
+    }
+}
+
+/// Abbreviate `path` to `~` when it falls under `home` (e.g.
+/// "/home/alice/project" with home "/home/alice" becomes "~/project"), for
+/// the cwd column in `--list`. Split out so the abbreviation is testable
+/// without depending on this process's real `$HOME`.
+fn abbreviate_home(path: &str, home: Option<&str>) -> String {
+    let Some(home) = home.filter(|h| !h.is_empty()) else {
+        return path.to_string();
+    };
+    match path.strip_prefix(home) {
+        Some("") => "~".to_string(),
+        Some(rest) => match rest.strip_prefix('/') {
+            Some(rest) => format!("~/{rest}"),
+            None => path.to_string(),
+        },
+        None => path.to_string(),
+    }
+}
+
+/// Parse a single `file[:row[:col]]` positional argument. Only a trailing
+/// run of one or two `:`-separated numeric groups is treated as a position;
+/// anything else (a path that merely contains colons, like `foo:bar`, or a
+/// trailing empty group, like `foo.rs:`) is kept as part of the path
+/// unchanged.
+fn parse_file_spec(arg: &str) -> FileSpec {
+    let segments: Vec<&str> = arg.split(':').collect();
+    if segments.len() >= 3 {
+        let tail = &segments[segments.len() - 2..];
+        if let (Ok(row), Ok(col)) = (tail[0].parse(), tail[1].parse()) {
+            return FileSpec {
+                path: segments[..segments.len() - 2].join(":"),
+                row: Some(row),
+                col: Some(col),
+            };
+        }
+    }
+    if segments.len() >= 2 {
+        if let Ok(row) = segments[segments.len() - 1].parse() {
+            return FileSpec {
+                path: segments[..segments.len() - 1].join(":"),
+                row: Some(row),
+                col: None,
+            };
+        }
+    }
+    FileSpec { path: arg.to_string(), row: None, col: None }
+}
+
+/// Push a positional argument onto `files`, applying (and then clearing) a
+/// pending `+N` line override from a preceding bare `+N` argument if the
+/// spec didn't already name its own row via `:row[:col]`.
+fn push_file_spec(files: &mut Vec<FileSpec>, pending_row: &mut Option<u32>, arg: &str) {
+    let mut spec = parse_file_spec(arg);
+    if spec.row.is_none() {
+        spec.row = pending_row.take();
+    } else {
+        *pending_row = None;
+    }
+    files.push(spec);
+}
+
+/// Whether `token` is a `+add`/`-remove` tag edit rather than the next
+/// top-level flag, so `--tag ID`'s parsing loop knows where to stop
+/// consuming arguments (e.g. at `--socket`). A bare `-` doesn't count, since
+/// it names no tag.
+fn is_tag_edit_token(token: &str) -> bool {
+    token.starts_with('+') && token.len() > 1
+        || token.starts_with('-') && !token.starts_with("--") && token.len() > 1
+}
+
+/// Split `+add`/`-remove` tokens following `--tag ID` into add/remove lists,
+/// e.g. `["+foo", "-bar", "+baz"]` -> (`["foo", "baz"]`, `["bar"]`). Split out
+/// from `parse_args` for testability.
+fn parse_tag_edits(tokens: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut add = Vec::new();
+    let mut remove = Vec::new();
+    for token in tokens {
+        if let Some(tag) = token.strip_prefix('+') {
+            add.push(tag.to_string());
+        } else if let Some(tag) = token.strip_prefix('-') {
+            remove.push(tag.to_string());
+        }
+    }
+    (add, remove)
+}
+
+/// Describe, as lines of output, what `--kill <id> --dry-run` would do given
+/// the current session listing, without actually killing anything. Kept
+/// separate from the client call so the resolution logic can be tested
+/// without a live daemon connection.
+fn describe_kill_dry_run(sessions: &[SessionSummary], id: SessionId) -> Vec<String> {
+    match sessions.iter().find(|s| s.id == id) {
+        Some(session) => vec![format!(
+            "would kill session {} ({})",
+            session.id,
+            session.alias.as_deref().unwrap_or("-")
+        )],
+        None => vec![format!("no such session: {id}")],
+    }
+}
+
+/// One thing `-k`/`--kill` can name: either a session id directly, or an
+/// alias to resolve against the current listing first (see
+/// [`resolve_kill_target`]). Parsed by [`parse_kill_targets`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum KillTarget {
+    Id(SessionId),
+    Alias(String),
+}
+
+impl std::fmt::Display for KillTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KillTarget::Id(id) => write!(f, "{id}"),
+            KillTarget::Alias(alias) => write!(f, "{alias}"),
+        }
+    }
+}
+
+/// Parse one `--kill` argument into its targets, splitting on commas so `-k
+/// 2,3,work` is shorthand for `-k 2 -k 3 -k work`. Anything that doesn't
+/// parse as a [`SessionId`] is treated as an alias rather than rejected.
+fn parse_kill_targets(raw: &str) -> Vec<KillTarget> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| match s.parse::<SessionId>() {
+            Ok(id) => KillTarget::Id(id),
+            Err(_) => KillTarget::Alias(s.to_string()),
+        })
+        .collect()
+}
+
+/// Resolve `target` against the current session listing. A bare id is
+/// returned as-is, even if it names no session — the ordinary "no such
+/// session" handling already covers that once it reaches the daemon.
+fn resolve_kill_target(sessions: &[SessionSummary], target: &KillTarget) -> Option<SessionId> {
+    match target {
+        KillTarget::Id(id) => Some(*id),
+        KillTarget::Alias(alias) => sessions
+            .iter()
+            .find(|s| s.alias.as_deref() == Some(alias.as_str()))
+            .map(|s| s.id),
+    }
+}
+
+/// What [`plan_kill_targets`] decided for one `--kill` target, without
+/// having contacted the daemon yet.
+enum KillPlan {
+    /// Resolved to a session id not already targeted earlier in this
+    /// invocation; needs a real `Request::KillSessions` round trip.
+    Pending(SessionId),
+    /// Resolved to a session id that an earlier target in the same
+    /// invocation already covers (the same id twice, an id and its alias,
+    /// or two aliases for the same session). Not sent to the daemon again,
+    /// and not treated as a failure.
+    AlreadyTargeted(SessionId),
+    /// An alias that matched no session in the current listing.
+    NotFound(String),
+}
+
+/// Resolve every `--kill` target against `sessions` and decide which ones
+/// actually need a round trip: duplicates (by resolved id) and unresolved
+/// aliases are both decided locally instead of being sent to the daemon,
+/// one of which twice.
+fn plan_kill_targets(sessions: &[SessionSummary], targets: &[KillTarget]) -> Vec<KillPlan> {
+    let mut seen = std::collections::HashSet::new();
+    targets
+        .iter()
+        .map(|target| match resolve_kill_target(sessions, target) {
+            None => KillPlan::NotFound(target.to_string()),
+            Some(id) if seen.insert(id) => KillPlan::Pending(id),
+            Some(id) => KillPlan::AlreadyTargeted(id),
+        })
+        .collect()
+}
+
+/// Reassemble a [`plan_kill_targets`] plan with the daemon's actual
+/// [`KillResult`]s for whichever ids were actually sent (in the same order
+/// [`plan_kill_targets`] decided them), producing one final [`KillResult`]
+/// per target plus any purely informational lines (e.g. "already gone")
+/// that aren't failures and shouldn't affect the exit code.
+fn merge_kill_plan(plan: Vec<KillPlan>, sent_results: Vec<KillResult>) -> (Vec<KillResult>, Vec<String>) {
+    let mut sent_results = sent_results.into_iter();
+    let mut notes = Vec::new();
+    let results = plan
+        .into_iter()
+        .map(|entry| match entry {
+            KillPlan::Pending(id) => sent_results.next().unwrap_or(KillResult {
+                id,
+                error: Some("no response from daemon".into()),
+            }),
+            KillPlan::AlreadyTargeted(id) => {
+                notes.push(format!("session {id}: already gone (duplicate target)"));
+                KillResult { id, error: None }
+            }
+            KillPlan::NotFound(name) => KillResult {
+                id: 0,
+                error: Some(format!("no such session: {name}")),
+            },
+        })
+        .collect();
+    (results, notes)
+}
+
+/// Describe, as lines of output, what `--stop --dry-run` would do: every
+/// session the daemon currently knows about, since stopping it terminates
+/// all of them.
+fn describe_stop_dry_run(sessions: &[SessionSummary]) -> Vec<String> {
+    if sessions.is_empty() {
+        return vec!["would stop the daemon (no sessions running)".to_string()];
+    }
+    let mut lines = vec![format!(
+        "would stop the daemon, terminating {} session(s):",
+        sessions.len()
+    )];
+    lines.extend(
+        sessions
+            .iter()
+            .map(|s| format!("  {} ({})", s.id, s.alias.as_deref().unwrap_or("-"))),
+    );
+    lines
+}
+
+fn parse_sort(value: &str) -> Result<SortBy> {
+    match value {
+        "id" => Ok(SortBy::Id),
+        "time" => Ok(SortBy::CreatedAt),
+        "name" => Ok(SortBy::Alias),
+        other => anyhow::bail!("invalid --sort value: {other} (expected id, time, or name)"),
+    }
+}
+
+/// A `--session-timeout` value: `"never"` clears a session's override (see
+/// `Request::SetSessionTimeout`'s `None`), otherwise a duration parsed by
+/// [`helix_daemon::duration::parse_duration`] (e.g. `30m` or `2h`).
+fn parse_session_timeout(value: &str) -> Result<Option<Duration>> {
+    if value == "never" {
+        return Ok(None);
+    }
+    helix_daemon::duration::parse_duration(value)
+        .map(Some)
+        .with_context(|| format!("invalid --session-timeout value: {value}"))
+}
+
+/// Connect to the daemon at `socket`: a filesystem path, `@name` for the
+/// Linux abstract namespace, or the default location when unset. When
+/// `version_check` is set, the daemon's reported version is compared against
+/// this binary's own; a mismatch is a warning, or (with `strict_version`) a
+/// refusal to proceed at all. When `autostart` is set (the default), a
+/// connection failure that looks like "no daemon is listening" triggers
+/// spawning one and retrying instead of failing outright.
+///
+/// `timeout` bounds how long a transient failure (e.g. `hxd` is mid-startup
+/// and its socket doesn't exist or refuses connections yet) is retried
+/// before giving up (see [`Client::connect_with_retry`]), and, once
+/// connected, how long each subsequent request/response exchange is allowed
+/// to take (see [`Client::with_timeout`]). See `--timeout`.
+async fn connect(
+    socket: &Option<String>,
+    version_check: bool,
+    strict_version: bool,
+    autostart: bool,
+    timeout: Duration,
+) -> Result<Client> {
+    let client = match try_connect(socket, timeout).await {
+        Ok(client) => client,
+        Err(err) if autostart && is_daemon_unreachable(&err) => {
+            autostart_and_connect(socket).await?
+        }
+        Err(err) => {
+            return Err(
+                anyhow::Error::from(err).context(format!("socket: {}", display_socket_path(socket)))
+            )
+        }
+    };
+    let mut client = client.with_timeout(timeout);
+    if version_check {
+        check_version(&mut client, strict_version).await?;
+    }
+    Ok(client)
+}
+
+/// Apply `--input-buffer` to a connection about to become a
+/// [`SessionClient`], raising its channel's cap via
+/// [`Client::with_max_message_size`]. A no-op when the flag wasn't given,
+/// leaving the channel at [`helix_daemon::channel::DEFAULT_MAX_MESSAGE_SIZE`].
+fn with_input_buffer(client: Client, input_buffer: Option<usize>) -> Client {
+    match input_buffer {
+        Some(max_message_size) => client.with_max_message_size(max_message_size),
+        None => client,
+    }
+}
+
+async fn try_connect(socket: &Option<String>, timeout: Duration) -> helix_daemon::error::Result<Client> {
+    match socket.as_deref().and_then(|s| s.strip_prefix('@')) {
+        Some(name) => Client::connect_abstract(name).await,
+        None => Client::connect_with_retry(socket.clone().map(PathBuf::from), timeout).await,
+    }
+}
+
+/// The socket path an invocation with `--socket` value `socket` will
+/// actually use, for display in error messages. Mirrors
+/// `Client`/`Server`'s own precedence (explicit value, then
+/// `$HELIX_DAEMON_SOCKET`, then the version-derived default) so a failure
+/// message is never misleading about where it looked.
+fn display_socket_path(socket: &Option<String>) -> String {
+    match socket.as_deref() {
+        Some(explicit) => explicit.to_string(),
+        None => helix_daemon::proto::resolve_socket_path(None)
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+/// Whether `err` looks like "nothing is listening on the socket" rather than
+/// some other connection failure (e.g. a permissions error), so a fresh
+/// `hxd` is actually likely to fix it before we bother spawning one.
+/// [`Client::connect_with_retry`] reports a socket that stayed missing or
+/// refusing until its deadline as [`Error::Timeout`], so that counts too.
+fn is_daemon_unreachable(err: &Error) -> bool {
+    match err {
+        Error::Timeout => true,
+        Error::IO(io) => matches!(
+            io.kind(),
+            std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+        ),
+        _ => false,
+    }
+}
+
+/// How long [`autostart_and_connect`] retries connecting to a freshly
+/// spawned daemon before giving up.
+const AUTOSTART_RETRY_TIMEOUT: Duration = Duration::from_secs(2);
+const AUTOSTART_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Locate and spawn an `hxd` binary, then retry connecting to `socket` until
+/// it comes up or [`AUTOSTART_RETRY_TIMEOUT`] elapses.
+async fn autostart_and_connect(socket: &Option<String>) -> Result<Client> {
+    let binary = locate_hxd_binary().ok_or_else(|| {
+        anyhow::anyhow!(
+            "no daemon at {}, and could not find an hxd binary to start one",
+            display_socket_path(socket)
+        )
+    })?;
+    spawn_hxd_detached(&binary, socket)
+        .with_context(|| format!("failed to start {}", binary.display()))?;
+
+    let deadline = tokio::time::Instant::now() + AUTOSTART_RETRY_TIMEOUT;
+    loop {
+        // One attempt per iteration: this loop does its own retrying.
+        match try_connect(socket, Duration::ZERO).await {
+            Ok(client) => return Ok(client),
+            Err(err) if tokio::time::Instant::now() >= deadline => {
+                return Err(anyhow::anyhow!(
+                    "started {} but still could not connect to {}: {err}",
+                    binary.display(),
+                    display_socket_path(socket)
+                ))
+            }
+            Err(_) => tokio::time::sleep(AUTOSTART_RETRY_INTERVAL).await,
+        }
+    }
+}
+
+/// Where an `hxd` binary might live, in priority order: alongside this
+/// process's own executable, then each directory on `$PATH`. Split from
+/// [`locate_hxd_binary`] so the search order is testable without touching
+/// the real filesystem.
+fn hxd_candidate_paths(current_exe: Option<&Path>, path_env: Option<&str>) -> Vec<PathBuf> {
+    let exe_name = if cfg!(windows) { "hxd.exe" } else { "hxd" };
+    let mut candidates = Vec::new();
+    if let Some(dir) = current_exe.and_then(Path::parent) {
+        candidates.push(dir.join(exe_name));
+    }
+    if let Some(path_env) = path_env {
+        candidates.extend(std::env::split_paths(path_env).map(|dir| dir.join(exe_name)));
+    }
+    candidates
+}
+
+/// Find an `hxd` binary to auto-start, preferring one next to this process's
+/// own executable (the common case: both were installed together) over
+/// whatever happens to be on `$PATH`.
+fn locate_hxd_binary() -> Option<PathBuf> {
+    let current_exe = std::env::current_exe().ok();
+    let path_env = std::env::var("PATH").ok();
+    hxd_candidate_paths(current_exe.as_deref(), path_env.as_deref())
+        .into_iter()
+        .find(|path| path.exists())
+}
+
+/// Spawn `binary` as a detached `hxd`, passing through `socket` so the two
+/// agree on which one to use, plus `--daemonize` so `hxd` itself does the
+/// double-fork/`setsid`/`chdir`/stdio-to-`/dev/null` dance (see
+/// `helix_daemon`'s `hxd.rs`) instead of this process only half-detaching it
+/// with a single `setsid`. stdout/stderr are still redirected to a log file
+/// here, for the narrow window before `hxd` reaches its own daemonizing
+/// (e.g. an `--daemonize`/`--foreground` parse error).
+#[cfg(not(windows))]
+fn spawn_hxd_detached(binary: &Path, socket: &Option<String>) -> std::io::Result<()> {
+    use std::process::Stdio;
+
+    let log = autostart_log_file()?;
+    let mut command = std::process::Command::new(binary);
+    command.arg("--daemonize");
+    if let Some(socket) = socket {
+        command.arg("--socket").arg(socket);
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    command.spawn()?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn spawn_hxd_detached(binary: &Path, socket: &Option<String>) -> std::io::Result<()> {
+    use std::os::windows::process::CommandExt;
+    use std::process::Stdio;
+
+    // `hxd --daemonize` isn't supported on Windows (no fork), so this keeps
+    // detaching it the Windows-native way instead: its own console-free
+    // process group.
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+    const DETACHED_PROCESS: u32 = 0x0000_0008;
+
+    let log = autostart_log_file()?;
+    let mut command = std::process::Command::new(binary);
+    if let Some(socket) = socket {
+        command.arg("--socket").arg(socket);
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .creation_flags(CREATE_NEW_PROCESS_GROUP | DETACHED_PROCESS);
+    command.spawn()?;
+    Ok(())
+}
+
+/// Open (creating if needed) the log file a spawned daemon's stdout/stderr
+/// are redirected to.
+fn autostart_log_file() -> std::io::Result<std::fs::File> {
+    let log_path = helix_loader::cache_dir().join("hxd-autostart.log");
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::OpenOptions::new().create(true).append(true).open(log_path)
+}
+
+/// Compare the daemon's reported version against this binary's own,
+/// warning (or, with `strict`, refusing) on a mismatch. The default socket
+/// path already embeds the client's version, so in practice this mostly
+/// matters for `--socket`/abstract-namespace connections that bypass that
+/// check, or simply to surface the daemon's version for diagnosis.
+///
+/// A `PROTO_VERSION` mismatch is rejected unconditionally, regardless of
+/// `strict`: unlike `CARGO_PKG_VERSION`, which can differ across otherwise
+/// wire-compatible builds, a different `PROTO_VERSION` means the two sides
+/// can't reliably decode each other's messages at all.
+async fn check_version(client: &mut Client, strict: bool) -> Result<()> {
+    let (daemon_version, daemon_proto_version) = client.version().await?;
+    if daemon_proto_version != PROTO_VERSION {
+        return Err(VersionMismatch(proto_mismatch_message(
+            daemon_proto_version,
+            PROTO_VERSION,
+            &daemon_version,
+            env!("CARGO_PKG_VERSION"),
+        ))
+        .into());
+    }
+    if let Some(message) = version_mismatch_message(&daemon_version, env!("CARGO_PKG_VERSION")) {
+        if strict {
+            return Err(VersionMismatch(message).into());
+        }
+        eprintln!("warning: {message}");
+    }
+    Ok(())
+}
+
+/// A version rejection from [`check_version`] (any `PROTO_VERSION`
+/// mismatch, or a package version mismatch under `--strict-version`), kept
+/// as its own type (rather than an
+/// `anyhow::bail!` string) so [`describe_failure`] can recognize it and
+/// report [`exit_code::PROTOCOL_MISMATCH`] instead of the generic fallback.
+#[derive(Debug)]
+struct VersionMismatch(String);
+
+impl std::fmt::Display for VersionMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for VersionMismatch {}
+
+/// `Some(message)` describing the mismatch if `daemon_version` and
+/// `client_version` differ, `None` if they match. Split out from
+/// [`check_version`] so the comparison itself is testable without a live
+/// daemon connection.
+fn version_mismatch_message(daemon_version: &str, client_version: &str) -> Option<String> {
+    if daemon_version == client_version {
+        None
+    } else {
+        Some(format!(
+            "version mismatch: hxc is v{client_version}, daemon is v{daemon_version}"
+        ))
+    }
+}
+
+/// The message for a [`PROTO_VERSION`] mismatch, naming both the protocol
+/// and crate versions on each side so the report is useful even when
+/// they've drifted independently (e.g. a hotfix release that didn't touch
+/// the wire format still bumps `CARGO_PKG_VERSION`). Split out from
+/// [`check_version`] so it's testable without a live daemon connection, like
+/// [`version_mismatch_message`].
+fn proto_mismatch_message(
+    daemon_proto_version: u32,
+    client_proto_version: u32,
+    daemon_version: &str,
+    client_version: &str,
+) -> String {
+    format!(
+        "protocol mismatch: hxc speaks proto v{client_proto_version} (v{client_version}), \
+         daemon speaks proto v{daemon_proto_version} (v{daemon_version})"
+    )
+}
+
+/// Print `prompt`, read a line from stdin with terminal echo disabled (so
+/// the passphrase never lands in a scrollback or a screen-recording), and
+/// return it with the trailing newline stripped.
+#[cfg(not(windows))]
+fn read_passphrase(prompt: &str) -> Result<String> {
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+
+    print!("{prompt}");
+    std::io::stdout().flush()?;
+
+    let stdin_fd = std::io::stdin().as_raw_fd();
+    // Safety: `term` is fully initialized by `tcgetattr` before it's read.
+    let original = unsafe {
+        let mut term: libc::termios = std::mem::zeroed();
+        libc::tcgetattr(stdin_fd, &mut term);
+        term
+    };
+    let mut term = original;
+    term.c_lflag &= !libc::ECHO;
+    unsafe { libc::tcsetattr(stdin_fd, libc::TCSANOW, &term) };
+
+    let mut line = String::new();
+    let read_result = std::io::stdin().read_line(&mut line);
+
+    unsafe { libc::tcsetattr(stdin_fd, libc::TCSANOW, &original) };
+    println!();
+    read_result.context("failed to read passphrase")?;
+
+    Ok(line.trim_end_matches('\n').to_string())
+}
+
+#[cfg(windows)]
+fn read_passphrase(prompt: &str) -> Result<String> {
+    use std::io::Write;
+
+    print!("{prompt}");
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .context("failed to read passphrase")?;
+    Ok(line.trim_end_matches('\n').to_string())
+}
+
+struct Args {
+    action: Action,
+    socket: Option<String>,
+    version_check: bool,
+    strict_version: bool,
+    dry_run: bool,
+    /// `file[:row[:col]]` positional arguments, only meaningful alongside
+    /// `Action::New` (see the check in `parse_args`).
+    files: Vec<FileSpec>,
+    /// Whether `connect` may spawn a daemon on demand when none is running.
+    /// See `--no-autostart`.
+    autostart: bool,
+    /// How to render `Action::List`'s output. See `--json`, `--format`.
+    format: Format,
+    /// How long `connect` retries a transient connection failure (and later
+    /// bounds each request/response exchange), see `--timeout`.
+    timeout: Duration,
+    /// Whether an attached session should try to reconnect and reattach on
+    /// an unexpected disconnect instead of exiting immediately. See
+    /// `--reconnect`.
+    reconnect: bool,
+    /// Local detach hotkey an attached session watches raw stdin for, see
+    /// `--detach-key`/`--no-detach-key`/`$HELIX_DAEMON_DETACH_KEY`. `None`
+    /// means `--no-detach-key` was given; otherwise always set, defaulting
+    /// to `DetachKey::DEFAULT`.
+    detach_key: Option<DetachKey>,
+    /// `-v`/`-vv`/`-vvv` count, 0 if unset. Unlike `hxd`, no logging is
+    /// configured at all when this is 0, since a one-shot client has
+    /// nowhere sensible to always log warnings to.
+    verbosity: u64,
+    /// The filename following `-v`/`-vv`/`-vvv`, if one was given. `None`
+    /// with `verbosity > 0` means stderr instead of a file.
+    log_file: Option<PathBuf>,
+    /// `--input-buffer` value in bytes, raising the attached session's
+    /// channel above `helix_daemon::channel::DEFAULT_MAX_MESSAGE_SIZE` for a
+    /// client expecting unusually large output chunks (e.g. a big paste
+    /// echoed back). `None` (the default) leaves the channel at its normal
+    /// cap.
+    input_buffer: Option<usize>,
+    /// `--quiet`: suppress informational status lines (see [`status_line`])
+    /// so stdout carries only the data a command was actually asked for
+    /// (e.g. `--list`'s table/`--json`).
+    quiet: bool,
+}
+
+/// Consume the value for `flag`: `inline` if `--flag=value` supplied one,
+/// otherwise the next token. Rejects a following token that
+/// [`looks_like_a_flag`] instead of silently swallowing it.
+fn take_value(
+    inline: Option<String>,
+    args: &mut std::iter::Peekable<impl Iterator<Item = String>>,
+    flag: &str,
+) -> Result<String> {
+    if let Some(value) = inline {
+        return Ok(value);
+    }
+    match args.peek() {
+        Some(next) if looks_like_a_flag(next) => {
+            anyhow::bail!("{flag} requires a value, got {next:?}")
+        }
+        _ => args.next().with_context(|| format!("{flag} requires a value")),
+    }
+}
+
+/// The line [`status_line`] would print, or `None` if `quiet` suppresses it
+/// entirely. Split out from the `eprintln!` itself so the suppression logic
+/// is testable without capturing real stderr.
+fn status_line_text(quiet: bool, message: impl std::fmt::Display) -> Option<String> {
+    if quiet {
+        None
+    } else {
+        Some(message.to_string())
+    }
+}
+
+/// Human-readable summary of a `Client::stop_server` outcome for
+/// `Action::Stop`, e.g. "stopped 3 sessions (1 forced)", or "stopped 2
+/// sessions (1 forced), 1 still shutting down: 5" if the daemon gave up on
+/// one before it actually finished.
+fn describe_stopped(clean: u64, forced: u64, failed: &[SessionId]) -> String {
+    let stopped = clean + forced;
+    let mut line = format!("stopped {stopped} session{}", if stopped == 1 { "" } else { "s" });
+    if forced > 0 {
+        line.push_str(&format!(" ({forced} forced)"));
+    }
+    if !failed.is_empty() {
+        let ids = failed.iter().map(SessionId::to_string).collect::<Vec<_>>().join(", ");
+        line.push_str(&format!(", {} still shutting down: {ids}", failed.len()));
+    }
+    line
+}
+
+/// Print an informational line that isn't the data a command was asked
+/// for (e.g. "session 3 locked", a `--dry-run` note) to stderr rather than
+/// stdout, so stdout stays safe to pipe (see `--json`/`--format`). Dropped
+/// entirely under `--quiet`.
+fn status_line(quiet: bool, message: impl std::fmt::Display) {
+    if let Some(line) = status_line_text(quiet, message) {
+        eprintln!("{line}");
+    }
+}
+
+/// Bail if `flag` was given a `--flag=value` it doesn't take, e.g.
+/// `--follow=true`.
+fn reject_inline(inline: &Option<String>, flag: &str) -> Result<()> {
+    if inline.is_some() {
+        anyhow::bail!("{flag} takes no value");
+    }
+    Ok(())
+}
+
+/// Record that `flag` wants to set the command's action, bailing with a
+/// conflict error if a *different* action flag already claimed it. Passing
+/// the same `flag` again (e.g. repeated `-k`) is allowed, since those are
+/// accumulating onto the existing action rather than conflicting with it.
+fn claim_action(action_flag: &mut Option<&'static str>, flag: &'static str) -> Result<()> {
+    if let Some(prev) = *action_flag {
+        if prev != flag {
+            anyhow::bail!("--{flag} cannot be combined with --{prev}");
+        }
+    }
+    *action_flag = Some(flag);
+    Ok(())
+}
+
+fn parse_args() -> Result<Args> {
+    parse_args_from(std::env::args().skip(1))
+}
+
+fn parse_args_from(argv: impl Iterator<Item = String>) -> Result<Args> {
+    let mut args = argv.peekable();
+    let mut action = None;
+    // Which action flag (by its canonical long name) is responsible for
+    // `action`, so a later conflicting one can be reported by name instead
+    // of just overwriting it silently. Kept separate from `action` itself
+    // since `-k`/`--kill` share one ("kill"), and a bare `--wait` modifying
+    // an existing `--kill` doesn't claim a new one at all.
+    let mut action_flag: Option<&'static str> = None;
+    let mut takeover = false;
+    let mut sort = SortBy::Id;
+    let mut all = false;
+    let mut socket = None;
+    let mut version_check = false;
+    let mut strict_version = false;
+    let mut dry_run = false;
+    let mut autostart = true;
+    let mut if_exists = false;
+    let mut timeout = DEFAULT_CONNECT_TIMEOUT;
+    let mut files = Vec::new();
+    let mut pending_row = None;
+    let mut positional_only = false;
+    let mut format = Format::Table;
+    let mut follow = false;
+    let mut reconnect = false;
+    let mut verbosity = 0u64;
+    let mut log_file = None;
+    let mut detach_key = None;
+    let mut no_detach_key = false;
+    let mut input_buffer = None;
+    let mut quiet = false;
+
+    while let Some(arg) = args.next() {
+        if positional_only {
+            push_file_spec(&mut files, &mut pending_row, &arg);
+            continue;
+        }
+        // `--flag=value` is split up front so every arm below can treat it
+        // exactly like `--flag value`; short flags (`-a`, `-k`) don't
+        // support the `=` form.
+        let (flag, inline) = match arg.strip_prefix("--").and_then(|rest| rest.split_once('=')) {
+            Some((name, value)) => (format!("--{name}"), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        match flag.as_str() {
+            "--" => positional_only = true,
+            "--list" => {
+                reject_inline(&inline, "--list")?;
+                claim_action(&mut action_flag, "list")?;
+                action = Some(Action::List(SortBy::Id, false, false));
+            }
+            "--sort" => {
+                sort = parse_sort(&take_value(inline, &mut args, "--sort")?)?;
+            }
+            "--all" => {
+                reject_inline(&inline, "--all")?;
+                all = true;
+            }
+            "--follow" => {
+                reject_inline(&inline, "--follow")?;
+                follow = true;
+            }
+            "--json" => {
+                reject_inline(&inline, "--json")?;
+                format = Format::Json;
+            }
+            "--format" => {
+                format = parse_format(&take_value(inline, &mut args, "--format")?)?;
+            }
+            "--takeover" => {
+                reject_inline(&inline, "--takeover")?;
+                takeover = true;
+            }
+            "--reconnect" => {
+                reject_inline(&inline, "--reconnect")?;
+                reconnect = true;
+            }
+            "--detach-key" => {
+                let spec = take_value(inline, &mut args, "--detach-key")?;
+                detach_key = Some(spec.parse::<DetachKey>().map_err(|err: String| anyhow::anyhow!(err))?);
+            }
+            "--no-detach-key" => {
+                reject_inline(&inline, "--no-detach-key")?;
+                no_detach_key = true;
+            }
+            "-v" | "-vv" | "-vvv" => {
+                reject_inline(&inline, &flag)?;
+                verbosity += helix_daemon::logging::verbosity_for_flag(&flag);
+                if let Some(next) = args.peek() {
+                    if !looks_like_a_flag(next) {
+                        log_file = Some(PathBuf::from(args.next().unwrap()));
+                    }
+                }
+            }
+            "--socket" => {
+                socket = Some(take_value(inline, &mut args, "--socket")?);
+            }
+            "--input-buffer" => {
+                let value = take_value(inline, &mut args, "--input-buffer")?;
+                input_buffer = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid --input-buffer value: {value}"))?,
+                );
+            }
+            "--version-check" => {
+                reject_inline(&inline, "--version-check")?;
+                version_check = true;
+            }
+            "--dry-run" => {
+                reject_inline(&inline, "--dry-run")?;
+                dry_run = true;
+            }
+            "--no-autostart" => {
+                reject_inline(&inline, "--no-autostart")?;
+                autostart = false;
+            }
+            "--quiet" => {
+                reject_inline(&inline, "--quiet")?;
+                quiet = true;
+            }
+            "--if-exists" => {
+                reject_inline(&inline, "--if-exists")?;
+                if_exists = true;
+            }
+            "--timeout" => {
+                let secs: u64 = take_value(inline, &mut args, "--timeout")?
+                    .parse()
+                    .context("invalid --timeout value")?;
+                timeout = Duration::from_secs(secs);
+            }
+            "--strict-version" => {
+                reject_inline(&inline, "--strict-version")?;
+                version_check = true;
+                strict_version = true;
+            }
+            "-a" | "--attach" => {
+                claim_action(&mut action_flag, "attach")?;
+                // The session id is optional (like `tmux attach`): only
+                // consume the next argument if it actually parses as one,
+                // so `-a` followed by another flag (or nothing) falls back
+                // to `AttachLast`.
+                let id = match inline {
+                    Some(value) => Some(value.parse().context("invalid session id")?),
+                    None => match args.peek().and_then(|v| v.parse::<SessionId>().ok()) {
+                        Some(id) => {
+                            args.next();
+                            Some(id)
+                        }
+                        None => None,
+                    },
+                };
+                action = Some(match id {
+                    Some(id) => Action::Attach(id, false),
+                    None => Action::AttachLast(false),
+                });
+            }
+            "-k" | "--kill" => {
+                claim_action(&mut action_flag, "kill")?;
+                let first = take_value(inline, &mut args, "--kill")?;
+                // Repeating `-k` accumulates onto the same target list
+                // rather than replacing it, so `-k 1 -k 2` behaves like
+                // `-k 1,2`.
+                let mut targets = match action {
+                    Some(Action::Kill(ref mut existing, ..)) => std::mem::take(existing),
+                    _ => Vec::new(),
+                };
+                targets.extend(parse_kill_targets(&first));
+                while let Some(next) = args.peek() {
+                    if looks_like_a_flag(next) {
+                        break;
+                    }
+                    targets.extend(parse_kill_targets(&args.next().unwrap()));
+                }
+                action = Some(Action::Kill(targets, false, false, false));
+            }
+            "--wait" if matches!(action, Some(Action::Kill(..))) => {
+                if inline.is_some() {
+                    anyhow::bail!("--wait takes no value here; it modifies the preceding --kill");
+                }
+                if let Some(Action::Kill(_, _, _, wait)) = &mut action {
+                    *wait = true;
+                }
+            }
+            "--wait" => {
+                claim_action(&mut action_flag, "wait")?;
+                let raw = take_value(inline, &mut args, "--wait")?;
+                let mut targets = parse_kill_targets(&raw);
+                if targets.len() != 1 {
+                    anyhow::bail!("--wait takes exactly one session id or alias, got {raw:?}");
+                }
+                action = Some(Action::Wait(targets.remove(0)));
+            }
+            "--send" => {
+                claim_action(&mut action_flag, "send")?;
+                let target = take_value(inline, &mut args, "--send")?;
+                let payload = args.next().context("--send requires a payload")?;
+                action = Some(Action::Send(target, payload));
+            }
+            "--lock" => {
+                claim_action(&mut action_flag, "lock")?;
+                let id: SessionId = take_value(inline, &mut args, "--lock")?
+                    .parse()
+                    .context("invalid session id")?;
+                action = Some(Action::Lock(id));
+            }
+            "--tag" => {
+                claim_action(&mut action_flag, "tag")?;
+                let id: SessionId = take_value(inline, &mut args, "--tag")?
+                    .parse()
+                    .context("invalid session id")?;
+                let mut tokens = Vec::new();
+                while let Some(next) = args.peek() {
+                    if !is_tag_edit_token(next) {
+                        break;
+                    }
+                    tokens.push(args.next().unwrap());
+                }
+                if tokens.is_empty() {
+                    anyhow::bail!("--tag requires at least one +add or -remove tag");
+                }
+                let (add, remove) = parse_tag_edits(&tokens);
+                action = Some(Action::Tag(id, add, remove));
+            }
+            "--session" | "--attach-or-new" => {
+                claim_action(&mut action_flag, "session")?;
+                let alias = take_value(inline, &mut args, &flag)?;
+                action = Some(Action::NewOrAttach(alias));
+            }
+            "--stop" => {
+                reject_inline(&inline, "--stop")?;
+                claim_action(&mut action_flag, "stop")?;
+                action = Some(Action::Stop);
+            }
+            "--swap" => {
+                claim_action(&mut action_flag, "swap")?;
+                let a: SessionId = take_value(inline, &mut args, "--swap")?
+                    .parse()
+                    .context("invalid session id")?;
+                let b: SessionId = take_value(None, &mut args, "--swap (second id)")?
+                    .parse()
+                    .context("invalid session id")?;
+                action = Some(Action::Swap(a, b));
+            }
+            "--metrics" => {
+                reject_inline(&inline, "--metrics")?;
+                claim_action(&mut action_flag, "metrics")?;
+                action = Some(Action::Metrics(false));
+            }
+            "--prometheus" if matches!(action, Some(Action::Metrics(_))) => {
+                reject_inline(&inline, "--prometheus")?;
+                if let Some(Action::Metrics(prometheus)) = &mut action {
+                    *prometheus = true;
+                }
+            }
+            "--stats" => {
+                // Same counters as `--metrics --prometheus`: both render
+                // `Server::metrics()` in the Prometheus textfile collector's
+                // `name value` format, which is exactly what `--stats` was
+                // asked for. Kept as an alias rather than a separate
+                // `Request::Stats` on the wire, since `Request::Metrics`
+                // already is the versioned, freely-extensible facility that
+                // request wanted; see `zen3ger/helix#synth-100`.
+                reject_inline(&inline, "--stats")?;
+                claim_action(&mut action_flag, "stats")?;
+                action = Some(Action::Metrics(true));
+            }
+            "--log-level" => {
+                claim_action(&mut action_flag, "log-level")?;
+                let verbosity: u8 = take_value(inline, &mut args, "--log-level")?
+                    .parse()
+                    .context("invalid --log-level value")?;
+                action = Some(Action::SetLogLevel(verbosity));
+            }
+            "--session-timeout" => {
+                claim_action(&mut action_flag, "session-timeout")?;
+                let id: SessionId = take_value(inline, &mut args, "--session-timeout")?
+                    .parse()
+                    .context("invalid session id")?;
+                let raw = args
+                    .next()
+                    .context("--session-timeout requires a duration (e.g. 30m, 2h, or never)")?;
+                let timeout = parse_session_timeout(&raw)?;
+                action = Some(Action::SetTimeout(id, timeout));
+            }
+            "--completions" => {
+                claim_action(&mut action_flag, "completions")?;
+                let shell: Shell = take_value(inline, &mut args, "--completions")?
+                    .parse()
+                    .map_err(|err: String| anyhow::anyhow!(err))?;
+                action = Some(Action::Completions(shell));
+            }
+            "--print-socket" => {
+                reject_inline(&inline, &flag)?;
+                claim_action(&mut action_flag, "print-socket")?;
+                action = Some(Action::PrintSocket);
+            }
+            "-h" | "--help" => {
+                reject_inline(&inline, &flag)?;
+                claim_action(&mut action_flag, "help")?;
+                action = Some(Action::Help);
+            }
+            other if other.starts_with('+') && other[1..].parse::<u32>().is_ok() => {
+                pending_row = other[1..].parse().ok();
+            }
+            other if !other.starts_with('-') => {
+                push_file_spec(&mut files, &mut pending_row, other);
+            }
+            other => anyhow::bail!("unexpected argument: {other}"),
+        }
+    }
+
+    // `--takeover`/`--sort`/`--all` only make sense alongside `--attach`/`--list`;
+    // apply them once parsing is done so the flags can appear in either order.
+    match &mut action {
+        Some(Action::Attach(_, t)) => *t = takeover,
+        Some(Action::AttachLast(t)) => *t = takeover,
+        Some(Action::List(s, a, f)) => {
+            *s = sort;
+            *a = all;
+            *f = follow;
+        }
+        Some(Action::Kill(_, _, e, _)) => *e = if_exists,
+        _ => {}
+    }
+
+    if if_exists && !matches!(action, Some(Action::Kill(..))) {
+        anyhow::bail!("--if-exists only applies to --kill");
+    }
+
+    if !files.is_empty() {
+        if let Some(
+            Action::List(..)
+            | Action::Kill(..)
+            | Action::Stop
+            | Action::Swap(..)
+            | Action::Lock(..)
+            | Action::Tag(..)
+            | Action::Wait(..)
+            | Action::Send(..)
+            | Action::Metrics(..)
+            | Action::SetLogLevel(..)
+            | Action::SetTimeout(..)
+            | Action::Completions(..)
+            | Action::PrintSocket
+            | Action::Help,
+        ) = action
+        {
+            anyhow::bail!(
+                "file arguments cannot be combined with --list, --kill, --stop, --swap, --lock, --tag, --wait, --send, --metrics, --log-level, --session-timeout, --completions, --print-socket, or --help"
+            );
+        }
+    }
+
+    if format != Format::Table && !matches!(action, Some(Action::List(..))) {
+        anyhow::bail!("--json/--format only apply to --list");
+    }
+
+    if follow && !matches!(action, Some(Action::List(..))) {
+        anyhow::bail!("--follow only applies to --list");
+    }
+
+    if no_detach_key && detach_key.is_some() {
+        anyhow::bail!("--detach-key and --no-detach-key are mutually exclusive");
+    }
+    let detach_key = resolve_detach_key(detach_key, no_detach_key)?;
+
+    Ok(Args {
+        action: action.unwrap_or(Action::New),
+        socket,
+        version_check,
+        strict_version,
+        dry_run,
+        files,
+        autostart,
+        format,
+        timeout,
+        reconnect,
+        detach_key,
+        verbosity,
+        log_file,
+        input_buffer,
+        quiet,
+    })
+}
+
+/// Resolve `--detach-key`/`--no-detach-key` into the `Option<DetachKey>`
+/// `Args` actually carries: an explicit `--detach-key` wins, `--no-detach-key`
+/// disables it outright, and otherwise `$HELIX_DAEMON_DETACH_KEY` (if set and
+/// non-empty) or [`DetachKey::DEFAULT`] applies.
+fn resolve_detach_key(explicit: Option<DetachKey>, disabled: bool) -> Result<Option<DetachKey>> {
+    if disabled {
+        return Ok(None);
+    }
+    if let Some(key) = explicit {
+        return Ok(Some(key));
+    }
+    match std::env::var("HELIX_DAEMON_DETACH_KEY") {
+        Ok(spec) if !spec.is_empty() => Ok(Some(
+            spec.parse::<DetachKey>().map_err(|err: String| anyhow::anyhow!(err))?,
+        )),
+        _ => Ok(Some(DetachKey::DEFAULT)),
+    }
+}
+
+/// Connect (independently of any already-open connection) and attach to
+/// `id`, retrying once with a prompted passphrase if the session is locked.
+/// Shared by `Action::Attach` and `Action::AttachLast`'s interactive picker,
+/// so both go through the same locked-session handling.
+async fn attach_by_id(
+    socket: &Option<String>,
+    version_check: bool,
+    strict_version: bool,
+    autostart: bool,
+    timeout: Duration,
+    id: SessionId,
+    takeover: bool,
+    reconnect: bool,
+    detach_key: Option<DetachKey>,
+    input_buffer: Option<usize>,
+) -> Result<i32> {
+    let client = connect(socket, version_check, strict_version, autostart, timeout).await?;
+    let client = with_input_buffer(client, input_buffer);
+    match client.attach_session(id, takeover, None).await {
+        Ok(mut session) => {
+            session.post_attach().await?;
+            let mut session = apply_reconnect(session, socket, reconnect, None).with_detach_key(detach_key);
+            Ok(session.run().await?)
+        }
+        // The session is locked and we didn't send a passphrase yet (we
+        // don't know it's locked until we try); prompt once and retry on a
+        // fresh connection, since the failed attempt consumed the first one.
+        Err(Error::Session(ClientError::WrongPassphrase)) => {
+            let passphrase = read_passphrase("passphrase: ")?;
+            let client = connect(socket, version_check, strict_version, autostart, timeout).await?;
+            let client = with_input_buffer(client, input_buffer);
+            match client.attach_session(id, takeover, Some(passphrase.clone())).await {
+                Ok(mut session) => {
+                    session.post_attach().await?;
+                    let mut session =
+                        apply_reconnect(session, socket, reconnect, Some(passphrase)).with_detach_key(detach_key);
+                    Ok(session.run().await?)
+                }
+                Err(err) => map_session_not_found(err),
+            }
+        }
+        Err(err) => map_session_not_found(err),
+    }
+}
+
+/// When `-a`/`--attach` is given with no target and stdin is a terminal,
+/// offer an interactive picker instead of silently attaching to whichever
+/// session was detached most recently: `None` if there are zero or one
+/// detached sessions (the existing `Request::AttachLast` behavior already
+/// does the right thing in both cases), `Some` with the chosen session's id
+/// once the picker has one.
+///
+/// Returns `Ok(None)` rather than erroring when the user declines a pick
+/// (blank input, EOF, or an unrecognized selection), falling back to the
+/// same `AttachLast` behavior a non-interactive invocation would get.
+async fn pick_detached_session(client: &mut Client) -> Result<Option<SessionId>> {
+    let sessions = client.list_sessions(SortBy::Id, false).await?;
+    let detached: Vec<&SessionSummary> = sessions.iter().filter(|s| !s.attached).collect();
+    if detached.len() < 2 {
+        return Ok(None);
+    }
+    let candidates: Vec<picker::Candidate> = detached
+        .iter()
+        .map(|s| picker::Candidate {
+            id: s.id,
+            alias: s.alias.clone(),
+            idle: idle_column(s.attached, s.last_detached),
+            cwd: s.cwd.clone().unwrap_or_else(|| "-".to_string()),
+        })
+        .collect();
+    Ok(picker::prompt(&candidates)?.map(|index| candidates[index].id))
+}
+
+/// Apply `--reconnect` to `session` if enabled, so `SessionClient::run`
+/// attempts to rejoin the daemon on an unexpected disconnect instead of
+/// immediately reporting `exit_code::DAEMON_LOST`. A no-op for an
+/// abstract-namespace `socket` (`@name`), since `Client::connect_with_retry`
+/// only knows how to dial a filesystem path.
+fn apply_reconnect(
+    session: SessionClient,
+    socket: &Option<String>,
+    reconnect: bool,
+    passphrase: Option<String>,
+) -> SessionClient {
+    if !reconnect || socket.as_deref().map_or(false, |s| s.starts_with('@')) {
+        return session;
+    }
+    session.with_reconnect(socket.clone().map(PathBuf::from), passphrase, DEFAULT_RECONNECT_TIMEOUT)
+}
+
+/// Turn a failed `Client`/`SessionClient` call into an exit code: a
+/// [`ClientError::SessionNotFound`] becomes [`exit_code::SESSION_NOT_FOUND`]
+/// instead of an opaque failure, so scripts can distinguish "no such
+/// session" from other errors without parsing the message. Anything else is
+/// propagated as-is.
+fn map_session_not_found(err: Error) -> Result<i32> {
+    match err {
+        Error::Session(ClientError::SessionNotFound) => Ok(exit_code::SESSION_NOT_FOUND),
+        other => Err(other.into()),
+    }
+}
+
+/// Turn the result of `Client::kill_session` into an exit code. With
+/// `--if-exists`, a missing session is treated as success instead of
+/// [`exit_code::SESSION_NOT_FOUND`], so cleanup scripts don't have to check
+/// whether the session was already gone before killing it.
+fn kill_exit_code(result: std::result::Result<(), Error>, if_exists: bool) -> Result<i32> {
+    match result {
+        Ok(()) => Ok(0),
+        Err(Error::Session(ClientError::SessionNotFound)) if if_exists => Ok(0),
+        Err(err) => map_session_not_found(err),
+    }
+}
+
+/// Turn a `Client::kill_sessions` result into a single exit code: every id
+/// is attempted regardless, but a failure not covered by `--if-exists`
+/// makes the overall command report [`exit_code::SESSION_NOT_FOUND`]. Each
+/// failure is also printed to stderr, since a partial failure would
+/// otherwise be silent.
+fn kill_sessions_exit_code(results: &[KillResult], if_exists: bool) -> i32 {
+    let mut code = 0;
+    for result in results {
+        if let Some(err) = &result.error {
+            eprintln!("session {}: {err}", result.id);
+            if !if_exists {
+                code = exit_code::SESSION_NOT_FOUND;
+            }
+        }
+    }
+    code
+}
+
+/// Turn a `main_impl` failure into an exit code and the message to print to
+/// stderr in its place, so scripts can tell "no such session", "occupied",
+/// "daemon unreachable" and "protocol mismatch" apart without parsing text.
+/// Walks the anyhow chain (rather than matching only the outermost error)
+/// since most call sites attach context, e.g. [`connect`]'s
+/// `.context(format!("socket: ..."))`. Anything unrecognized falls back to
+/// exit code 1 with the error's default chain formatting, matching the
+/// historical behavior of letting it bubble out of `main`.
+fn describe_failure(err: &anyhow::Error) -> (i32, String) {
+    if let Some(err) = err.chain().find_map(|cause| cause.downcast_ref::<Error>()) {
+        match err {
+            Error::Session(ClientError::SessionNotFound) => {
+                return (exit_code::SESSION_NOT_FOUND, err.to_string())
+            }
+            Error::Session(ClientError::Occupied) => {
+                return (exit_code::OCCUPIED, err.to_string())
+            }
+            Error::IO(io)
+                if matches!(
+                    io.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                return (exit_code::DAEMON_LOST, format!("daemon unreachable: {err}"))
+            }
+            Error::Timeout => {
+                return (exit_code::DAEMON_LOST, format!("daemon unreachable: {err}"))
+            }
+            _ => {}
+        }
+    }
+    if err.chain().any(|cause| cause.is::<VersionMismatch>()) {
+        return (exit_code::PROTOCOL_MISMATCH, err.to_string());
+    }
+    (1, format!("{err:#}"))
+}
+
+fn main() -> Result<()> {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err:#}");
+            std::process::exit(exit_code::USAGE_ERROR);
+        }
+    };
+    if args.verbosity > 0 {
+        if let Err(err) = helix_daemon::logging::setup(
+            args.verbosity,
+            args.log_file.as_deref(),
+            helix_daemon::logging::DEFAULT_MAX_LOG_BYTES,
+            false,
+        ) {
+            eprintln!("failed to set up logging: {err:#}");
+        }
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let exit_code = match runtime.block_on(main_impl(args)) {
+        Ok(code) => code,
+        Err(err) => {
+            let (code, message) = describe_failure(&err);
+            eprintln!("{message}");
+            code
+        }
+    };
+    std::process::exit(exit_code);
+}
+
+async fn main_impl(args: Args) -> Result<i32> {
+    let Args {
+        action,
+        socket,
+        version_check,
+        strict_version,
+        dry_run,
+        files,
+        autostart,
+        format,
+        timeout,
+        reconnect,
+        detach_key,
+        verbosity: _,
+        log_file: _,
+        input_buffer,
+        quiet,
+    } = args;
+
+    match action {
+        Action::New => {
+            let client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            let client = with_input_buffer(client, input_buffer);
+            let session = client.new_session_with_files(files).await?;
+            let mut session = apply_reconnect(session, &socket, reconnect, None).with_detach_key(detach_key);
+            Ok(session.run().await?)
+        }
+        Action::Attach(id, takeover) => {
+            attach_by_id(
+                &socket,
+                version_check,
+                strict_version,
+                autostart,
+                timeout,
+                id,
+                takeover,
+                reconnect,
+                detach_key,
+                input_buffer,
+            )
+            .await
+        }
+        Action::AttachLast(takeover) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            let picked = if picker::stdin_is_tty() {
+                pick_detached_session(&mut client).await?
+            } else {
+                None
+            };
+            match picked {
+                Some(id) => {
+                    drop(client);
+                    attach_by_id(
+                        &socket,
+                        version_check,
+                        strict_version,
+                        autostart,
+                        timeout,
+                        id,
+                        takeover,
+                        reconnect,
+                        detach_key,
+                        input_buffer,
+                    )
+                    .await
+                }
+                None => match with_input_buffer(client, input_buffer).attach_last(takeover).await {
+                    Ok((mut session, alias)) => {
+                        status_line(
+                            quiet,
+                            format!(
+                                "attaching to session {}{}",
+                                session.id,
+                                alias.map(|a| format!(" ({a})")).unwrap_or_default()
+                            ),
+                        );
+                        session.post_attach().await?;
+                        let mut session =
+                            apply_reconnect(session, &socket, reconnect, None).with_detach_key(detach_key);
+                        Ok(session.run().await?)
+                    }
+                    Err(err) => Err(err.into()),
+                },
+            }
+        }
+        Action::Lock(id) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            let passphrase = read_passphrase(&format!("passphrase for session {id}: "))?;
+            let confirm = read_passphrase("confirm passphrase: ")?;
+            if passphrase != confirm {
+                anyhow::bail!("passphrases did not match");
+            }
+            let hash = helix_daemon::auth::hash_passphrase(&passphrase);
+            client.lock_session(id, Some(hash)).await?;
+            status_line(quiet, format!("session {id} locked"));
+            Ok(0)
+        }
+        Action::Tag(id, add, remove) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            client.tag_session(id, add, remove).await?;
+            status_line(quiet, format!("session {id} tags updated"));
+            Ok(0)
+        }
+        Action::NewOrAttach(alias) => {
+            let client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            let client = with_input_buffer(client, input_buffer);
+            let (mut session, created) = client.attach_or_create_with_files(alias, files).await?;
+            status_line(
+                quiet,
+                format!(
+                    "{} session {}",
+                    if created { "created" } else { "attached to" },
+                    session.id
+                ),
+            );
+            if !created {
+                session.post_attach().await?;
+            }
+            let mut session = apply_reconnect(session, &socket, reconnect, None).with_detach_key(detach_key);
+            Ok(session.run().await?)
+        }
+        Action::List(sort, all, follow) => {
+            let result: std::result::Result<_, anyhow::Error> = async {
+                let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+                let sessions = client.list_sessions(sort, all).await?;
+                Ok((client, sessions))
+            }
+            .await;
+            let code = match result {
+                Ok((mut client, sessions)) => match format {
+                    Format::Table => {
+                        println!("daemon v{}", client.version().await?.0);
+                        let home = std::env::var("HOME").ok();
+                        for session in sessions {
+                            let marker = format!(
+                                "{}{}{}{}",
+                                if session.stale { " (lost in daemon restart)" } else { "" },
+                                capture_marker(session.capturing.as_deref()),
+                                log_marker(session.log_path.as_deref()),
+                                tags_marker(&session.tags),
+                            );
+                            let files = if session.files.is_empty() {
+                                "-".to_string()
+                            } else {
+                                session.files.join(",")
+                            };
+                            let idle = idle_column(session.attached, session.last_detached);
+                            let cwd = match session.cwd.as_deref() {
+                                Some(cwd) => abbreviate_home(cwd, home.as_deref()),
+                                None => "-".to_string(),
+                            };
+                            println!(
+                                "{}\t{}\t{cwd}\t{idle}\t{files}{marker}",
+                                session.id,
+                                session.alias.unwrap_or_default(),
+                            );
+                        }
+                        Ok(0)
+                    }
+                    Format::Json => {
+                        println!("{}", format_sessions_json(&sessions));
+                        Ok(0)
+                    }
+                    Format::Ids => {
+                        let ids = format_sessions_ids(&sessions);
+                        if !ids.is_empty() {
+                            println!("{ids}");
+                        }
+                        Ok(0)
+                    }
+                    Format::IdsAndNames => {
+                        let lines = format_sessions_ids_and_names(&sessions);
+                        if !lines.is_empty() {
+                            println!("{lines}");
+                        }
+                        Ok(0)
+                    }
+                },
+                Err(err) if format != Format::Table => {
+                    eprintln!("{}", format_json_error(&err.to_string()));
+                    Ok(1)
+                }
+                Err(err) => Err(err),
+            };
+            match code {
+                Ok(0) if follow => {
+                    let client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+                    let mut watcher = client.watch_sessions().await?;
+                    while let Some(delta) = watcher.next().await? {
+                        println!("{}", describe_delta(&delta));
+                    }
+                    Ok(0)
+                }
+                other => other,
+            }
+        }
+        Action::Kill(targets, force, if_exists, wait) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            let sessions = client.list_sessions(SortBy::Id, false).await?;
+            let plan = plan_kill_targets(&sessions, &targets);
+            if dry_run {
+                let mut missing = false;
+                for entry in &plan {
+                    match entry {
+                        KillPlan::Pending(id) => {
+                            for line in describe_kill_dry_run(&sessions, *id) {
+                                status_line(quiet, line);
+                            }
+                        }
+                        KillPlan::AlreadyTargeted(id) => {
+                            status_line(quiet, format!("session {id}: already gone (duplicate target)"));
+                        }
+                        KillPlan::NotFound(name) => {
+                            status_line(quiet, format!("no such session: {name}"));
+                            missing = true;
+                        }
+                    }
+                }
+                return Ok(if !missing || if_exists {
+                    0
+                } else {
+                    exit_code::SESSION_NOT_FOUND
+                });
+            }
+            let single_pending = match plan.as_slice() {
+                [KillPlan::Pending(id)] => Some(*id),
+                _ => None,
+            };
+            match single_pending {
+                Some(id) => {
+                    let result = if wait {
+                        client.kill_session_wait(id, force, DEFAULT_KILL_WAIT_TIMEOUT).await
+                    } else {
+                        client.kill_session(id, force).await
+                    };
+                    kill_exit_code(result, if_exists)
+                }
+                None => {
+                    let to_kill: Vec<SessionId> = plan
+                        .iter()
+                        .filter_map(|entry| match entry {
+                            KillPlan::Pending(id) => Some(*id),
+                            _ => None,
+                        })
+                        .collect();
+                    let sent_results = if to_kill.is_empty() {
+                        Vec::new()
+                    } else {
+                        client.kill_sessions(to_kill, force).await?
+                    };
+                    if wait {
+                        for result in &sent_results {
+                            if result.error.is_none() {
+                                client
+                                    .wait_for_session_gone(result.id, DEFAULT_KILL_WAIT_TIMEOUT)
+                                    .await?;
+                            }
+                        }
+                    }
+                    let (results, notes) = merge_kill_plan(plan, sent_results);
+                    for note in notes {
+                        status_line(quiet, note);
+                    }
+                    Ok(kill_sessions_exit_code(&results, if_exists))
+                }
+            }
+        }
+        Action::Stop => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            if dry_run {
+                let sessions = client.list_sessions(SortBy::Id, false).await?;
+                for line in describe_stop_dry_run(&sessions) {
+                    status_line(quiet, line);
+                }
+                return Ok(0);
+            }
+            let (clean, forced, failed) = client.stop_server().await?;
+            status_line(quiet, describe_stopped(clean, forced, &failed));
+            Ok(0)
+        }
+        Action::Swap(a, b) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            match client.swap_sessions(a, b).await {
+                Ok(()) => Ok(0),
+                Err(err) => map_session_not_found(err),
+            }
+        }
+        Action::Wait(target) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            let sessions = client.list_sessions(SortBy::Id, false).await?;
+            let id = resolve_kill_target(&sessions, &target)
+                .ok_or_else(|| anyhow::anyhow!("no such session: {target}"))?;
+            let (code, forced) = client.wait_session(id).await?;
+            if forced {
+                status_line(quiet, format!("session {id} was forcibly terminated"));
+            }
+            Ok(code)
+        }
+        Action::Send(target, payload) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            match client.send_to_session(target, payload).await {
+                Ok(()) => Ok(0),
+                Err(err) => map_session_not_found(err),
+            }
+        }
+        Action::Metrics(prometheus) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            let metrics = client.metrics().await?;
+            if prometheus {
+                for line in render_metrics_prometheus(&metrics) {
+                    println!("{line}");
+                }
+            } else {
+                for (key, value) in &metrics {
+                    println!("{key} {value}");
+                }
+            }
+            Ok(0)
+        }
+        Action::SetLogLevel(verbosity) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            client.set_log_level(verbosity).await?;
+            Ok(0)
+        }
+        Action::SetTimeout(id, session_timeout) => {
+            let mut client = connect(&socket, version_check, strict_version, autostart, timeout).await?;
+            client.set_session_timeout(id, session_timeout).await?;
+            status_line(quiet, format!("session {id} timeout updated"));
+            Ok(0)
+        }
+        Action::Completions(shell) => {
+            println!("{}", completions::render(shell));
+            Ok(0)
+        }
+        Action::PrintSocket => {
+            println!("{}", display_socket_path(&socket));
+            Ok(0)
+        }
+        Action::Help => {
+            print!("{}", help_text());
+            Ok(0)
+        }
+    }
+}
+
+/// `-h`/`--help`'s output. Kept to the handful of things a user can't
+/// easily rediscover by reading `hxc --list --help`-less error messages:
+/// the shell completion install one-liners, since there's nowhere else in
+/// this binary that documents them.
+fn help_text() -> String {
+    format!(
+        "hxc [FLAGS] [FILE[:ROW[:COL]]...]\n\n\
+         Shell completions:\n  \
+         bash   {}\n  \
+         zsh    {}\n  \
+         fish   {}\n",
+        completions::install_hint(Shell::Bash),
+        completions::install_hint(Shell::Zsh),
+        completions::install_hint(Shell::Fish),
+    )
+}
+
+/// Render `metrics` in Prometheus's plain text exposition format: a `# TYPE`
+/// line followed by a sample, per key. Every key `Server::metrics` produces
+/// is a running total or a point-in-time count, never a histogram, so
+/// `counter` vs. `gauge` is picked by the `_total` suffix convention
+/// Prometheus itself recommends for counters.
+fn render_metrics_prometheus(metrics: &BTreeMap<String, u64>) -> Vec<String> {
+    metrics
+        .iter()
+        .flat_map(|(key, value)| {
+            let kind = if key.ends_with("_total") { "counter" } else { "gauge" };
+            vec![format!("# TYPE {key} {kind}"), format!("{key} {value}")]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_session(id: SessionId, alias: Option<&str>) -> SessionSummary {
+        SessionSummary {
+            id,
+            alias: alias.map(String::from),
+            created_at: SystemTime::now(),
+            attached: false,
+            cwd: None,
+            files: Vec::new(),
+            last_detached: None,
+            stale: false,
+            env: Vec::new(),
+            capturing: None,
+            locked: false,
+            tags: Vec::new(),
+            size: None,
+            stats: SessionStats::default(),
+            log_path: None,
+        }
+    }
+
+    fn parse(argv: &[&str]) -> Result<Args> {
+        parse_args_from(argv.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parse_args_accepts_the_flag_equals_value_form() {
+        let args = parse(&["--socket=/tmp/x.sock", "--timeout=3"]).unwrap();
+        assert_eq!(args.socket.as_deref(), Some("/tmp/x.sock"));
+        assert_eq!(args.timeout, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn parse_args_accepts_kill_and_sort_in_the_flag_equals_value_form() {
+        let args = parse(&["--kill=3", "--sort=name"]).unwrap();
+        let Action::Kill(targets, ..) = args.action else {
+            panic!("expected Action::Kill");
+        };
+        assert_eq!(targets, vec![KillTarget::Id(3)]);
+    }
+
+    #[test]
+    fn parse_args_rejects_a_value_on_a_flag_that_takes_none() {
+        assert!(parse(&["--follow=true"]).is_err());
+        assert!(parse(&["--list", "--all=yes"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_rejects_combining_distinct_action_flags() {
+        assert!(parse(&["--list", "--stop"]).is_err());
+        assert!(parse(&["--kill", "3", "--stop"]).is_err());
+        assert!(parse(&["--stop", "--kill", "3"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_allows_repeating_the_same_action_flag() {
+        let args = parse(&["-k", "1", "-k", "2"]).unwrap();
+        let Action::Kill(targets, ..) = args.action else {
+            panic!("expected Action::Kill");
+        };
+        assert_eq!(targets, vec![KillTarget::Id(1), KillTarget::Id(2)]);
+    }
+
+    #[test]
+    fn parse_args_attach_or_new_is_an_alias_for_session() {
+        let args = parse(&["--attach-or-new", "work"]).unwrap();
+        let Action::NewOrAttach(alias) = args.action else {
+            panic!("expected Action::NewOrAttach");
+        };
+        assert_eq!(alias, "work");
+    }
+
+    #[test]
+    fn parse_args_does_not_let_a_value_consuming_flag_swallow_a_following_flag() {
+        assert!(parse(&["--session", "--stop"]).is_err());
+        assert!(parse(&["--lock", "--stop"]).is_err());
+        assert!(parse(&["--socket", "--stop"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_still_treats_a_flag_like_attach_target_as_attach_last() {
+        // `-a` followed by a flag (not a number) means "no id given",
+        // falling back to `AttachLast`, not an error.
+        let args = parse(&["-a", "--takeover"]).unwrap();
+        assert!(matches!(args.action, Action::AttachLast(true)));
+    }
+
+    #[test]
+    fn parse_args_accepts_kill_wait_as_a_modifier_not_a_new_action() {
+        let args = parse(&["--kill", "3", "--wait"]).unwrap();
+        let Action::Kill(targets, _, _, wait) = args.action else {
+            panic!("expected Action::Kill");
+        };
+        assert_eq!(targets, vec![KillTarget::Id(3)]);
+        assert!(wait);
+    }
+
+    #[test]
+    fn parse_args_still_treats_standalone_wait_as_its_own_action() {
+        let args = parse(&["--wait", "3"]).unwrap();
+        assert!(matches!(args.action, Action::Wait(KillTarget::Id(3))));
+    }
+
+    #[test]
+    fn parse_args_accepts_metrics_and_prometheus() {
+        let args = parse(&["--metrics", "--prometheus"]).unwrap();
+        assert!(matches!(args.action, Action::Metrics(true)));
+    }
+
+    #[test]
+    fn parse_args_rejects_file_arguments_alongside_metrics() {
+        assert!(parse(&["--metrics", "foo.rs"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_treats_stats_as_metrics_prometheus() {
+        let args = parse(&["--stats"]).unwrap();
+        assert!(matches!(args.action, Action::Metrics(true)));
+    }
+
+    #[test]
+    fn parse_args_rejects_stats_combined_with_metrics() {
+        assert!(parse(&["--stats", "--metrics"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_accepts_log_level() {
+        let args = parse(&["--log-level", "2"]).unwrap();
+        assert!(matches!(args.action, Action::SetLogLevel(2)));
+    }
+
+    #[test]
+    fn parse_args_rejects_an_invalid_log_level() {
+        assert!(parse(&["--log-level", "not-a-number"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_rejects_file_arguments_alongside_log_level() {
+        assert!(parse(&["--log-level", "1", "foo.rs"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_accepts_session_timeout() {
+        let args = parse(&["--session-timeout", "3", "30m"]).unwrap();
+        assert!(matches!(
+            args.action,
+            Action::SetTimeout(3, Some(d)) if d == Duration::from_secs(30 * 60)
+        ));
+    }
+
+    #[test]
+    fn parse_args_accepts_session_timeout_never() {
+        let args = parse(&["--session-timeout", "3", "never"]).unwrap();
+        assert!(matches!(args.action, Action::SetTimeout(3, None)));
+    }
+
+    #[test]
+    fn parse_args_rejects_file_arguments_alongside_session_timeout() {
+        assert!(parse(&["--session-timeout", "3", "30m", "foo.rs"]).is_err());
+    }
+
+    #[test]
+    fn parse_session_timeout_accepts_each_unit() {
+        assert_eq!(
+            parse_session_timeout("45s").unwrap(),
+            Some(Duration::from_secs(45))
+        );
+        assert_eq!(
+            parse_session_timeout("2h").unwrap(),
+            Some(Duration::from_secs(2 * 60 * 60))
+        );
+        assert_eq!(
+            parse_session_timeout("1d").unwrap(),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn parse_session_timeout_never_clears_the_override() {
+        assert_eq!(parse_session_timeout("never").unwrap(), None);
+    }
+
+    #[test]
+    fn parse_session_timeout_rejects_an_unknown_unit() {
+        assert!(parse_session_timeout("30x").is_err());
+    }
+
+    #[test]
+    fn parse_args_defaults_to_the_tmux_style_detach_key() {
+        std::env::remove_var("HELIX_DAEMON_DETACH_KEY");
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.detach_key, Some(DetachKey::DEFAULT));
+    }
+
+    #[test]
+    fn parse_args_accepts_a_custom_detach_key() {
+        let args = parse(&["--detach-key", "C-a x"]).unwrap();
+        assert_eq!(args.detach_key, "C-a x".parse().ok());
+    }
+
+    #[test]
+    fn parse_args_rejects_a_malformed_detach_key() {
+        assert!(parse(&["--detach-key", "nonsense"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_no_detach_key_disables_it() {
+        let args = parse(&["--no-detach-key"]).unwrap();
+        assert_eq!(args.detach_key, None);
+    }
+
+    #[test]
+    fn parse_args_rejects_detach_key_and_no_detach_key_together() {
+        assert!(parse(&["--detach-key", "C-a x", "--no-detach-key"]).is_err());
+        assert!(parse(&["--no-detach-key", "--detach-key", "C-a x"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_accepts_completions_for_each_known_shell() {
+        let args = parse(&["--completions", "bash"]).unwrap();
+        assert!(matches!(args.action, Action::Completions(Shell::Bash)));
+        let args = parse(&["--completions", "fish"]).unwrap();
+        assert!(matches!(args.action, Action::Completions(Shell::Fish)));
+    }
+
+    #[test]
+    fn parse_args_rejects_an_unknown_shell() {
+        assert!(parse(&["--completions", "powershell"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_accepts_help() {
+        let args = parse(&["--help"]).unwrap();
+        assert!(matches!(args.action, Action::Help));
+        let args = parse(&["-h"]).unwrap();
+        assert!(matches!(args.action, Action::Help));
+    }
+
+    #[test]
+    fn parse_args_accepts_print_socket() {
+        let args = parse(&["--print-socket"]).unwrap();
+        assert!(matches!(args.action, Action::PrintSocket));
+    }
+
+    #[test]
+    fn parse_args_rejects_files_combined_with_print_socket() {
+        assert!(parse(&["--print-socket", "foo.rs"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_quiet_defaults_to_off() {
+        let args = parse(&["--list"]).unwrap();
+        assert!(!args.quiet);
+    }
+
+    #[test]
+    fn parse_args_accepts_quiet() {
+        let args = parse(&["--quiet", "--list"]).unwrap();
+        assert!(args.quiet);
+    }
+
+    #[test]
+    fn status_line_text_passes_the_message_through_when_not_quiet() {
+        assert_eq!(
+            status_line_text(false, "session 3 locked"),
+            Some("session 3 locked".to_string())
+        );
+    }
+
+    #[test]
+    fn status_line_text_is_suppressed_by_quiet() {
+        assert_eq!(status_line_text(true, "session 3 locked"), None);
+    }
+
+    #[test]
+    fn parse_args_counts_repeated_v_flags_without_a_filename() {
+        let args = parse(&["-v"]).unwrap();
+        assert_eq!(args.verbosity, 1);
+        assert_eq!(args.log_file, None);
+
+        let args = parse(&["-vvv"]).unwrap();
+        assert_eq!(args.verbosity, 3);
+    }
+
+    #[test]
+    fn parse_args_treats_a_non_flag_token_after_v_as_its_log_file() {
+        let args = parse(&["-v", "/tmp/hxc.log"]).unwrap();
+        assert_eq!(args.verbosity, 1);
+        assert_eq!(args.log_file, Some(PathBuf::from("/tmp/hxc.log")));
+    }
+
+    #[test]
+    fn parse_args_does_not_let_v_swallow_a_following_flag_as_its_filename() {
+        let args = parse(&["-v", "--list"]).unwrap();
+        assert_eq!(args.log_file, None);
+        assert!(matches!(args.action, Action::List(..)));
+    }
+
+    #[test]
+    fn parse_args_input_buffer_defaults_to_unset() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.input_buffer, None);
+    }
+
+    #[test]
+    fn parse_args_input_buffer_parses_a_byte_count() {
+        let args = parse(&["--input-buffer", "1048576"]).unwrap();
+        assert_eq!(args.input_buffer, Some(1048576));
+    }
+
+    #[test]
+    fn parse_args_input_buffer_rejects_a_non_numeric_value() {
+        assert!(parse(&["--input-buffer", "huge"]).is_err());
+    }
+
+    #[test]
+    fn render_metrics_prometheus_labels_total_suffixed_keys_as_counters() {
+        let metrics = BTreeMap::from([
+            ("sessions_created_total".to_string(), 3),
+            ("sessions_live".to_string(), 1),
+        ]);
+        let lines = render_metrics_prometheus(&metrics);
+        assert_eq!(
+            lines,
+            vec![
+                "# TYPE sessions_created_total counter".to_string(),
+                "sessions_created_total 3".to_string(),
+                "# TYPE sessions_live gauge".to_string(),
+                "sessions_live 1".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn abbreviate_home_replaces_the_home_prefix_with_a_tilde() {
+        assert_eq!(
+            abbreviate_home("/home/alice/project", Some("/home/alice")),
+            "~/project"
+        );
+        assert_eq!(abbreviate_home("/home/alice", Some("/home/alice")), "~");
+        assert_eq!(
+            abbreviate_home("/var/log", Some("/home/alice")),
+            "/var/log"
+        );
+        assert_eq!(abbreviate_home("/home/alice/project", None), "/home/alice/project");
+        assert_eq!(
+            // A prefix match that isn't actually a path boundary shouldn't
+            // be abbreviated (e.g. "/home/alice2" is not under "/home/alice").
+            abbreviate_home("/home/alice2/project", Some("/home/alice")),
+            "/home/alice2/project"
+        );
+    }
+
+    #[test]
+    fn capture_marker_reports_the_file_path_when_active() {
+        assert_eq!(capture_marker(None), "");
+        assert_eq!(
+            capture_marker(Some("/var/log/hxd/session-3.log")),
+            " [capturing: /var/log/hxd/session-3.log]"
+        );
+    }
+
+    #[test]
+    fn log_marker_reports_the_file_path_when_per_session_logs_is_on() {
+        assert_eq!(log_marker(None), "");
+        assert_eq!(
+            log_marker(Some("/home/alice/.cache/helix/sessions/3.log")),
+            " [log: /home/alice/.cache/helix/sessions/3.log]"
+        );
+    }
+
+    #[test]
+    fn tags_marker_reports_no_tags_as_empty() {
+        assert_eq!(tags_marker(&[]), "");
+    }
+
+    #[test]
+    fn tags_marker_joins_multiple_tags() {
+        assert_eq!(
+            tags_marker(&["project:foo".to_string(), "wip".to_string()]),
+            " {project:foo, wip}"
+        );
+    }
+
+    #[test]
+    fn is_tag_edit_token_recognizes_add_and_remove_tokens() {
+        assert!(is_tag_edit_token("+foo"));
+        assert!(is_tag_edit_token("-bar"));
+        assert!(!is_tag_edit_token("--socket"));
+        assert!(!is_tag_edit_token("-"));
+        assert!(!is_tag_edit_token("+"));
+        assert!(!is_tag_edit_token("plain"));
+    }
+
+    #[test]
+    fn parse_tag_edits_splits_add_and_remove_tokens() {
+        let tokens = vec!["+foo".to_string(), "-bar".to_string(), "+baz".to_string()];
+        let (add, remove) = parse_tag_edits(&tokens);
+        assert_eq!(add, vec!["foo".to_string(), "baz".to_string()]);
+        assert_eq!(remove, vec!["bar".to_string()]);
+    }
+
+    #[test]
+    fn dry_run_kill_reports_the_matching_session() {
+        let sessions = vec![fake_session(1, Some("scratch")), fake_session(2, None)];
+        let lines = describe_kill_dry_run(&sessions, 1);
+        assert_eq!(lines, vec!["would kill session 1 (scratch)".to_string()]);
+    }
+
+    #[test]
+    fn dry_run_kill_reports_a_missing_session() {
+        let sessions = vec![fake_session(1, None)];
+        let lines = describe_kill_dry_run(&sessions, 99);
+        assert_eq!(lines, vec!["no such session: 99".to_string()]);
+    }
+
+    #[test]
+    fn parse_kill_targets_splits_a_comma_separated_list() {
+        assert_eq!(
+            parse_kill_targets("2,3,work"),
+            vec![
+                KillTarget::Id(2),
+                KillTarget::Id(3),
+                KillTarget::Alias("work".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_kill_targets_trims_whitespace_and_skips_empty_entries() {
+        assert_eq!(
+            parse_kill_targets(" 2 , , work "),
+            vec![KillTarget::Id(2), KillTarget::Alias("work".to_string())]
+        );
+    }
+
+    #[test]
+    fn resolve_kill_target_matches_an_alias_against_the_listing() {
+        let sessions = vec![fake_session(1, Some("work")), fake_session(2, None)];
+        assert_eq!(
+            resolve_kill_target(&sessions, &KillTarget::Alias("work".to_string())),
+            Some(1)
+        );
+        assert_eq!(
+            resolve_kill_target(&sessions, &KillTarget::Alias("missing".to_string())),
+            None
+        );
+        assert_eq!(resolve_kill_target(&sessions, &KillTarget::Id(2)), Some(2));
+    }
+
+    #[test]
+    fn plan_kill_targets_flags_duplicates_and_unresolved_aliases() {
+        let sessions = vec![fake_session(1, Some("work"))];
+        let targets = vec![
+            KillTarget::Id(1),
+            KillTarget::Alias("work".to_string()),
+            KillTarget::Alias("missing".to_string()),
+        ];
+        let plan = plan_kill_targets(&sessions, &targets);
+        assert!(matches!(plan[0], KillPlan::Pending(1)));
+        assert!(matches!(plan[1], KillPlan::AlreadyTargeted(1)));
+        assert!(matches!(plan[2], KillPlan::NotFound(ref name) if name == "missing"));
+    }
+
+    #[test]
+    fn merge_kill_plan_pairs_pending_entries_with_daemon_results_in_order() {
+        let plan = vec![
+            KillPlan::Pending(1),
+            KillPlan::AlreadyTargeted(1),
+            KillPlan::NotFound("missing".to_string()),
+            KillPlan::Pending(2),
+        ];
+        let sent_results = vec![
+            KillResult { id: 1, error: None },
+            KillResult { id: 2, error: Some("no such session".into()) },
+        ];
+        let (results, notes) = merge_kill_plan(plan, sent_results);
+        assert_eq!(results[0], KillResult { id: 1, error: None });
+        assert_eq!(results[1], KillResult { id: 1, error: None });
+        assert_eq!(
+            results[2],
+            KillResult { id: 0, error: Some("no such session: missing".into()) }
+        );
+        assert_eq!(results[3], KillResult { id: 2, error: Some("no such session".into()) });
+        assert_eq!(notes, vec!["session 1: already gone (duplicate target)".to_string()]);
+    }
+
+    #[test]
+    fn dry_run_stop_lists_every_session() {
+        let sessions = vec![fake_session(1, Some("a")), fake_session(2, None)];
+        let lines = describe_stop_dry_run(&sessions);
+        assert_eq!(
+            lines,
+            vec![
+                "would stop the daemon, terminating 2 session(s):".to_string(),
+                "  1 (a)".to_string(),
+                "  2 (-)".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn dry_run_stop_reports_no_sessions() {
+        assert_eq!(
+            describe_stop_dry_run(&[]),
+            vec!["would stop the daemon (no sessions running)".to_string()]
+        );
+    }
+
+    #[test]
+    fn describe_stopped_reports_a_clean_stop() {
+        assert_eq!(describe_stopped(3, 0, &[]), "stopped 3 sessions");
+    }
+
+    #[test]
+    fn describe_stopped_mentions_a_single_session_without_pluralizing() {
+        assert_eq!(describe_stopped(1, 0, &[]), "stopped 1 session");
+    }
+
+    #[test]
+    fn describe_stopped_calls_out_forced_terminations() {
+        assert_eq!(describe_stopped(2, 1, &[]), "stopped 3 sessions (1 forced)");
+    }
+
+    #[test]
+    fn describe_stopped_reports_sessions_still_shutting_down() {
+        assert_eq!(
+            describe_stopped(2, 1, &[5]),
+            "stopped 3 sessions (1 forced), 1 still shutting down: 5"
+        );
+    }
+
+    #[test]
+    fn matching_versions_produce_no_mismatch_message() {
+        assert!(version_mismatch_message("0.1.0", "0.1.0").is_none());
+    }
+
+    #[test]
+    fn differing_versions_name_both_sides_in_the_message() {
+        let message = version_mismatch_message("0.2.0", "0.1.0").unwrap();
+        assert!(message.contains("0.2.0"));
+        assert!(message.contains("0.1.0"));
+    }
+
+    #[test]
+    fn proto_mismatch_message_names_both_proto_and_crate_versions() {
+        let message = proto_mismatch_message(2, 1, "0.2.0", "0.1.0");
+        assert!(message.contains("proto v1"));
+        assert!(message.contains("proto v2"));
+        assert!(message.contains("0.1.0"));
+        assert!(message.contains("0.2.0"));
+    }
+
+    #[tokio::test]
+    async fn check_version_rejects_a_mismatched_proto_version_even_without_strict() {
+        use helix_daemon::channel::Channel;
+        use helix_daemon::proto::Response;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("proto-mismatch.sock");
+        let listener = tokio_seqpacket::UnixSeqpacketListener::bind(&socket).unwrap();
+
+        tokio::spawn(async move {
+            let conn = listener.accept().await.unwrap();
+            let mut channel = Channel::new(conn);
+            let _ = channel.recv::<helix_daemon::proto::Request>().await;
+            let _ = channel
+                .send(&Response::Version {
+                    crate_version: env!("CARGO_PKG_VERSION").to_string(),
+                    proto_version: PROTO_VERSION + 1,
+                })
+                .await;
+        });
+
+        let mut client = Client::connect(Some(socket)).await.unwrap();
+        let err = check_version(&mut client, false).await.unwrap_err();
+        assert!(err.downcast_ref::<VersionMismatch>().is_some());
+    }
+
+    #[test]
+    fn session_not_found_maps_to_its_own_exit_code() {
+        let code = map_session_not_found(Error::Session(ClientError::SessionNotFound)).unwrap();
+        assert_eq!(code, exit_code::SESSION_NOT_FOUND);
+    }
+
+    #[test]
+    fn other_client_errors_are_propagated_rather_than_mapped() {
+        assert!(map_session_not_found(Error::Session(ClientError::Occupied)).is_err());
+        assert!(map_session_not_found(Error::Closed).is_err());
+    }
+
+    #[test]
+    fn describe_failure_maps_session_not_found() {
+        let err = anyhow::Error::from(Error::Session(ClientError::SessionNotFound))
+            .context("socket: /tmp/hxd.sock");
+        let (code, message) = describe_failure(&err);
+        assert_eq!(code, exit_code::SESSION_NOT_FOUND);
+        assert_eq!(message, "no such session");
+    }
+
+    #[test]
+    fn describe_failure_maps_occupied() {
+        let err = anyhow::Error::from(Error::Session(ClientError::Occupied));
+        let (code, _) = describe_failure(&err);
+        assert_eq!(code, exit_code::OCCUPIED);
+    }
+
+    #[test]
+    fn describe_failure_maps_connect_errors_to_daemon_lost() {
+        let io = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let err = anyhow::Error::from(Error::IO(io)).context("socket: /tmp/hxd.sock");
+        let (code, _) = describe_failure(&err);
+        assert_eq!(code, exit_code::DAEMON_LOST);
+    }
+
+    #[test]
+    fn describe_failure_maps_strict_version_mismatch() {
+        let err = anyhow::Error::new(VersionMismatch("version mismatch".into()));
+        let (code, message) = describe_failure(&err);
+        assert_eq!(code, exit_code::PROTOCOL_MISMATCH);
+        assert_eq!(message, "version mismatch");
+    }
+
+    #[test]
+    fn describe_failure_falls_back_to_exit_code_one_for_unrecognized_errors() {
+        let err = anyhow::anyhow!("something unrelated went wrong");
+        let (code, _) = describe_failure(&err);
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn kill_exit_code_succeeds_when_the_session_is_present() {
+        assert_eq!(kill_exit_code(Ok(()), false).unwrap(), 0);
+        assert_eq!(kill_exit_code(Ok(()), true).unwrap(), 0);
+    }
+
+    #[test]
+    fn kill_exit_code_treats_a_missing_session_as_success_with_if_exists() {
+        let result = Err(Error::Session(ClientError::SessionNotFound));
+        assert_eq!(kill_exit_code(result, true).unwrap(), 0);
+    }
+
+    #[test]
+    fn kill_exit_code_reports_a_missing_session_without_if_exists() {
+        let result = Err(Error::Session(ClientError::SessionNotFound));
+        assert_eq!(
+            kill_exit_code(result, false).unwrap(),
+            exit_code::SESSION_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn kill_sessions_exit_code_succeeds_when_every_id_is_present() {
+        let results = vec![
+            KillResult { id: 1, error: None },
+            KillResult { id: 2, error: None },
+        ];
+        assert_eq!(kill_sessions_exit_code(&results, false), 0);
+    }
+
+    #[test]
+    fn kill_sessions_exit_code_reports_a_missing_id_without_if_exists() {
+        let results = vec![
+            KillResult { id: 1, error: None },
+            KillResult { id: 2, error: Some("no such session".into()) },
+        ];
+        assert_eq!(
+            kill_sessions_exit_code(&results, false),
+            exit_code::SESSION_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn kill_sessions_exit_code_ignores_a_missing_id_with_if_exists() {
+        let results = vec![
+            KillResult { id: 1, error: None },
+            KillResult { id: 2, error: Some("no such session".into()) },
+        ];
+        assert_eq!(kill_sessions_exit_code(&results, true), 0);
+    }
+
+    #[test]
+    fn parse_file_spec_splits_a_trailing_row_and_col() {
+        let spec = parse_file_spec("foo.rs:12:3");
+        assert_eq!(spec.path, "foo.rs");
+        assert_eq!(spec.row, Some(12));
+        assert_eq!(spec.col, Some(3));
+    }
+
+    #[test]
+    fn parse_file_spec_splits_a_trailing_row_only() {
+        let spec = parse_file_spec("foo.rs:12");
+        assert_eq!(spec.path, "foo.rs");
+        assert_eq!(spec.row, Some(12));
+        assert_eq!(spec.col, None);
+    }
+
+    #[test]
+    fn parse_file_spec_leaves_a_non_numeric_suffix_alone() {
+        let spec = parse_file_spec("foo:bar");
+        assert_eq!(spec.path, "foo:bar");
+        assert_eq!(spec.row, None);
+        assert_eq!(spec.col, None);
+    }
+
+    #[test]
+    fn parse_file_spec_leaves_a_trailing_empty_group_alone() {
+        let spec = parse_file_spec("foo.rs:");
+        assert_eq!(spec.path, "foo.rs:");
+        assert_eq!(spec.row, None);
+        assert_eq!(spec.col, None);
+    }
+
+    #[test]
+    fn parse_file_spec_leaves_a_bare_path_alone() {
+        let spec = parse_file_spec("foo.rs");
+        assert_eq!(spec.path, "foo.rs");
+        assert_eq!(spec.row, None);
+        assert_eq!(spec.col, None);
+    }
+
+    #[test]
+    fn push_file_spec_applies_a_pending_row_to_the_next_file() {
+        let mut files = Vec::new();
+        let mut pending_row = Some(42);
+        push_file_spec(&mut files, &mut pending_row, "foo.rs");
+        assert_eq!(files[0].row, Some(42));
+        assert_eq!(pending_row, None);
+    }
+
+    #[test]
+    fn push_file_spec_prefers_an_explicit_row_over_a_pending_one() {
+        let mut files = Vec::new();
+        let mut pending_row = Some(42);
+        push_file_spec(&mut files, &mut pending_row, "foo.rs:7");
+        assert_eq!(files[0].row, Some(7));
+        assert_eq!(pending_row, None);
+    }
+
+    #[test]
+    fn is_daemon_unreachable_recognizes_a_missing_or_refused_socket() {
+        let not_found = Error::IO(std::io::Error::from(std::io::ErrorKind::NotFound));
+        let refused = Error::IO(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+        assert!(is_daemon_unreachable(&not_found));
+        assert!(is_daemon_unreachable(&refused));
+    }
+
+    #[test]
+    fn is_daemon_unreachable_ignores_other_errors() {
+        let permission_denied = Error::IO(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert!(!is_daemon_unreachable(&permission_denied));
+        assert!(!is_daemon_unreachable(&Error::Closed));
+    }
+
+    #[test]
+    fn hxd_candidate_paths_prefers_the_exe_dir_then_each_path_entry() {
+        let exe_name = if cfg!(windows) { "hxd.exe" } else { "hxd" };
+        let candidates = hxd_candidate_paths(
+            Some(Path::new("/opt/helix/bin/hxc")),
+            Some("/usr/local/bin:/usr/bin"),
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                PathBuf::from(format!("/opt/helix/bin/{exe_name}")),
+                PathBuf::from(format!("/usr/local/bin/{exe_name}")),
+                PathBuf::from(format!("/usr/bin/{exe_name}")),
+            ]
+        );
+    }
+
+    #[test]
+    fn hxd_candidate_paths_handles_missing_exe_and_path() {
+        assert!(hxd_candidate_paths(None, None).is_empty());
+    }
+
+    #[test]
+    fn display_socket_path_echoes_an_explicit_socket() {
+        assert_eq!(
+            display_socket_path(&Some("/tmp/custom.sock".to_string())),
+            "/tmp/custom.sock"
+        );
+    }
+
+    #[test]
+    fn display_socket_path_prefers_explicit_over_the_env_var() {
+        std::env::set_var("HELIX_DAEMON_SOCKET", "/tmp/env.sock");
+        assert_eq!(
+            display_socket_path(&Some("/tmp/explicit.sock".to_string())),
+            "/tmp/explicit.sock"
+        );
+        std::env::remove_var("HELIX_DAEMON_SOCKET");
+    }
+
+    #[test]
+    fn display_socket_path_falls_back_to_the_env_var_then_the_default() {
+        std::env::set_var("HELIX_DAEMON_SOCKET", "/tmp/env.sock");
+        assert_eq!(display_socket_path(&None), "/tmp/env.sock");
+
+        std::env::remove_var("HELIX_DAEMON_SOCKET");
+        assert_eq!(
+            display_socket_path(&None),
+            helix_daemon::proto::resolve_socket_path(None).to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn parse_format_accepts_the_three_known_values() {
+        assert_eq!(parse_format("table").unwrap(), Format::Table);
+        assert_eq!(parse_format("json").unwrap(), Format::Json);
+        assert_eq!(parse_format("ids").unwrap(), Format::Ids);
+        assert_eq!(parse_format("ids-and-names").unwrap(), Format::IdsAndNames);
+        assert!(parse_format("yaml").is_err());
+    }
+
+    #[test]
+    fn session_to_json_uses_stable_field_names() {
+        let value = session_to_json(&fake_session(7, Some("scratch")));
+        assert_eq!(value["id"], 7);
+        assert_eq!(value["alias"], "scratch");
+        assert_eq!(value["attached"], false);
+        assert_eq!(value["cwd"], serde_json::Value::Null);
+        assert!(value["created_unix_ms"].is_u64());
+    }
+
+    #[test]
+    fn format_sessions_json_produces_an_array_with_one_entry_per_session() {
+        let sessions = vec![fake_session(1, Some("a")), fake_session(2, None)];
+        let rendered = format_sessions_json(&sessions);
+        let parsed: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(parsed.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn format_sessions_ids_prints_one_id_per_line() {
+        let sessions = vec![fake_session(1, None), fake_session(2, None)];
+        assert_eq!(format_sessions_ids(&sessions), "1\n2");
+        assert_eq!(format_sessions_ids(&[]), "");
+    }
+
+    #[test]
+    fn format_sessions_ids_and_names_prints_a_blank_alias_column_when_unset() {
+        let sessions = vec![fake_session(1, Some("work")), fake_session(2, None)];
+        assert_eq!(format_sessions_ids_and_names(&sessions), "1\twork\n2\t");
+    }
+
+    #[test]
+    fn format_json_error_wraps_the_message_in_an_error_field() {
+        let rendered = format_json_error("no such session: 9");
+        let parsed: serde_json::Value = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(parsed["error"], "no such session: 9");
+    }
+
+    #[test]
+    fn describe_delta_renders_each_kind_of_change() {
+        assert_eq!(
+            describe_delta(&SessionListDelta::Created { id: 3 }),
+            "+ session 3 created"
+        );
+        assert_eq!(
+            describe_delta(&SessionListDelta::Detached { id: 3 }),
+            "  session 3 detached"
+        );
+        assert_eq!(
+            describe_delta(&SessionListDelta::Terminated { id: 3 }),
+            "- session 3 terminated"
+        );
+        assert_eq!(
+            describe_delta(&SessionListDelta::Aliased {
+                id: 3,
+                alias: "scratch".to_string()
+            }),
+            "  session 3 renamed to scratch"
+        );
+    }
+}
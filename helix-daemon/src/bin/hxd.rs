@@ -0,0 +1,730 @@
+use anyhow::Result;
+use helix_daemon::config::FileConfig;
+use helix_daemon::server::{Server, ServerConfig};
+
+/// If systemd started us via socket activation (`Type=notify` + a paired
+/// `.socket` unit), take over the listener it already bound on fd 3 instead
+/// of binding our own. Returns `None` for a normal, non-activated start.
+#[cfg(not(windows))]
+fn socket_activation_listener() -> Option<tokio_seqpacket::UnixSeqpacketListener> {
+    use std::os::unix::io::FromRawFd;
+
+    let listen_pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    if listen_pid != std::process::id() {
+        return None;
+    }
+    let listen_fds: u32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if listen_fds < 1 {
+        return None;
+    }
+
+    // systemd numbers activation fds starting at 3, and guarantees fd 3 is
+    // already bound and listening for a single-socket unit.
+    // Safety: systemd owns and validates this fd for the lifetime of the process.
+    unsafe { tokio_seqpacket::UnixSeqpacketListener::from_raw_fd(3) }.ok()
+}
+
+#[cfg(windows)]
+fn socket_activation_listener() -> Option<tokio_seqpacket::UnixSeqpacketListener> {
+    None
+}
+
+/// Tell systemd (via `$NOTIFY_SOCKET`) that the daemon is ready to accept
+/// connections, for `Type=notify` units. A no-op outside of systemd.
+#[cfg(not(windows))]
+fn sd_notify_ready() {
+    use std::os::unix::net::UnixDatagram;
+
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Ok(socket) = UnixDatagram::unbound() {
+        let _ = socket.send_to(b"READY=1", &path);
+    }
+}
+
+#[cfg(windows)]
+fn sd_notify_ready() {}
+
+/// Where the daemon logs when started with no `-v` at all: the same fixed
+/// path as before a log filename could be given, so a daemon started
+/// without one (e.g. under systemd) keeps logging warnings there exactly as
+/// it always has. A `-v`/`-vv`/`-vvv` without a filename logs to stderr
+/// instead.
+fn default_log_file() -> std::path::PathBuf {
+    helix_loader::cache_dir().join("helix-daemon.log")
+}
+
+/// Where `daemon.toml` lives absent an explicit `--config`: alongside the
+/// rest of Helix's config, not cache, since unlike the log file this is
+/// something a person is expected to hand-edit.
+fn default_config_path() -> std::path::PathBuf {
+    helix_loader::config_dir().join("daemon.toml")
+}
+
+/// Resolve which config file (if any) `hxd` should load: the explicit
+/// `--config PATH` if given, else [`default_config_path`] but only if it
+/// actually exists. An absent default file is normal and silently skipped,
+/// the same way an absent explicit path is instead a hard error (surfaced
+/// by [`FileConfig::load`] itself, via `std::fs::read_to_string`).
+fn resolve_config_path(args: &Args) -> Option<std::path::PathBuf> {
+    match &args.config {
+        Some(path) => Some(path.clone()),
+        None => {
+            let path = default_config_path();
+            path.exists().then_some(path)
+        }
+    }
+}
+
+/// `default < file < flag`: apply whichever of `daemon.toml`'s startup-only
+/// settings were actually set, over [`ServerConfig::default()`]. There's no
+/// CLI flag for any of these yet, so "flag wins" is vacuously true today —
+/// once one exists for a given field, it belongs ahead of this call.
+fn apply_file_config(config: &mut ServerConfig, file: &FileConfig) {
+    if let Some(max_sessions) = file.max_sessions {
+        config.max_sessions = Some(max_sessions);
+    }
+    if let Some(max_attached_peers) = file.max_attached_peers {
+        config.max_attached_peers = max_attached_peers;
+    }
+    if let Some(mirror_queue_capacity) = file.mirror_queue_capacity {
+        config.mirror_queue_capacity = mirror_queue_capacity;
+    }
+    if let Some(on_create) = &file.on_create {
+        config.on_create = Some(on_create.clone());
+    }
+}
+
+/// `default < file < flag` for the socket path specifically, since unlike
+/// the fields [`apply_file_config`] covers, `--socket` already exists.
+fn effective_socket(args: &Args, file: Option<&FileConfig>) -> Option<String> {
+    args.socket
+        .clone()
+        .or_else(|| file.and_then(|file| file.socket.clone()))
+}
+
+/// `default < file < flag` for verbosity: an explicit `-v` always wins,
+/// otherwise fall back to `daemon.toml`'s `log_level`, otherwise the
+/// historical default of `0` (warnings only).
+fn effective_verbosity(args: &Args, file: Option<&FileConfig>) -> u64 {
+    if args.verbosity > 0 {
+        return args.verbosity;
+    }
+    file.and_then(FileConfig::verbosity).unwrap_or(0)
+}
+
+/// `hxd --check`'s entire output: the config that would actually be used,
+/// without binding a socket or starting anything.
+fn print_effective_config(socket: &Option<String>, verbosity: u64, config: &ServerConfig) {
+    println!("socket: {}", socket.as_deref().unwrap_or("(default)"));
+    println!("verbosity: {verbosity}");
+    println!(
+        "max_sessions: {}",
+        config
+            .max_sessions
+            .map_or_else(|| "unlimited".to_string(), |n| n.to_string())
+    );
+    println!("max_attached_peers: {}", config.max_attached_peers);
+    println!("mirror_queue_capacity: {}", config.mirror_queue_capacity);
+}
+
+/// `hxd --status`'s entire output for a reachable daemon: version, socket,
+/// uptime, and session counts from [`Server::metrics`], without creating any
+/// session.
+fn print_status(
+    socket: &Option<String>,
+    version: &str,
+    proto_version: u32,
+    metrics: &std::collections::BTreeMap<String, u64>,
+) {
+    println!("status: running");
+    println!("socket: {}", socket.as_deref().unwrap_or("(default)"));
+    println!("version: {version}");
+    println!("proto_version: {proto_version}");
+    println!("uptime_seconds: {}", metrics.get("uptime_seconds").copied().unwrap_or(0));
+    println!("sessions_live: {}", metrics.get("sessions_live").copied().unwrap_or(0));
+    println!("sessions_attached: {}", metrics.get("sessions_attached").copied().unwrap_or(0));
+    println!("sessions_detached: {}", metrics.get("sessions_detached").copied().unwrap_or(0));
+}
+
+/// Exit code `hxd --status` reports when the daemon isn't reachable at all,
+/// distinct from `0` (running) so a service wrapper can tell "down" apart
+/// from a protocol-level error it should instead fail loudly on.
+const STATUS_NOT_RUNNING_EXIT: i32 = 3;
+
+/// `hxd --status`: connect to `socket` as an ordinary client, ask for its
+/// version and metrics, and report them — without starting a server or
+/// creating any session. Returns the process exit code to use.
+fn run_status(socket: Option<String>) -> Result<i32> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    runtime.block_on(async {
+        let connected = match socket.as_deref().and_then(|s| s.strip_prefix('@')) {
+            Some(name) => helix_daemon::client::Client::connect_abstract(name).await,
+            None => helix_daemon::client::Client::connect(socket.clone().map(std::path::PathBuf::from)).await,
+        };
+        let mut client = match connected {
+            Ok(client) => client,
+            Err(_) => {
+                println!("not running (socket: {})", socket.as_deref().unwrap_or("(default)"));
+                return Ok(STATUS_NOT_RUNNING_EXIT);
+            }
+        };
+        let (version, proto_version) = client.version().await?;
+        let metrics = client.metrics().await?;
+        print_status(&socket, &version, proto_version, &metrics);
+        Ok(0)
+    })
+}
+
+struct Args {
+    verbosity: u64,
+    /// The filename following `-v`/`-vv`/`-vvv`, if one was given. `None`
+    /// either means no `-v` was given at all (see [`default_log_file`]) or
+    /// one was, without a filename, meaning stderr instead of a file.
+    log_file: Option<std::path::PathBuf>,
+    /// Raw `--socket` value: a filesystem path, or `@name` for the Linux
+    /// abstract namespace.
+    socket: Option<String>,
+    /// `--config` value; re-read on `SIGHUP` (see `helix_daemon::config`).
+    config: Option<std::path::PathBuf>,
+    /// `--capture-dir` value; see `ServerConfig::capture_dir`.
+    capture_dir: Option<std::path::PathBuf>,
+    /// `--per-session-logs`; see `ServerConfig::per_session_logs`.
+    per_session_logs: bool,
+    /// `--daemonize`; see [`daemonize`]. Off by default to preserve the
+    /// always-foreground behavior this had before the flag existed.
+    /// `--foreground` sets this back to `false` explicitly, for supervisors
+    /// that always pass one or the other.
+    daemonize: bool,
+    /// `--max-log-size` value in bytes, past which the log file rotates.
+    /// `None` means `helix_daemon::logging::DEFAULT_MAX_LOG_BYTES`.
+    max_log_size: Option<u64>,
+    /// `--quiet`: suppress the extra stdout sink [`main_impl`] otherwise
+    /// chains on in foreground mode (see [`Args::daemonize`]), for people
+    /// who liked the old log-file-only behavior.
+    quiet: bool,
+    /// `--check`: print the effective config (defaults, `daemon.toml`, and
+    /// flags all merged) and exit without binding a socket.
+    check: bool,
+    /// `--status`: connect to the resolved socket as a client and print a
+    /// health summary instead of starting the daemon. See [`print_status`].
+    status: bool,
+    /// `--print-socket`: print the socket path this invocation would bind
+    /// and exit, without binding it or connecting to anything. See
+    /// [`resolved_socket_display`].
+    print_socket: bool,
+}
+
+fn parse_args() -> Result<Args> {
+    parse_args_from(std::env::args().skip(1).collect())
+}
+
+fn parse_args_from(argv: Vec<String>) -> Result<Args> {
+    use anyhow::Context;
+
+    let mut args = Args {
+        verbosity: 0,
+        log_file: None,
+        socket: None,
+        config: None,
+        capture_dir: None,
+        per_session_logs: false,
+        daemonize: false,
+        max_log_size: None,
+        quiet: false,
+        check: false,
+        status: false,
+        print_socket: false,
+    };
+    let mut saw_foreground = false;
+    let mut it = argv.into_iter().peekable();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-v" | "-vv" | "-vvv" => {
+                args.verbosity += helix_daemon::logging::verbosity_for_flag(&arg);
+                if let Some(next) = it.peek() {
+                    if !helix_daemon::logging::looks_like_a_flag(next) {
+                        args.log_file = Some(it.next().unwrap().into());
+                    }
+                }
+            }
+            "--socket" => {
+                args.socket = Some(it.next().context("--socket requires a value")?);
+            }
+            "--config" => {
+                args.config = Some(it.next().context("--config requires a value")?.into());
+            }
+            "--capture-dir" => {
+                args.capture_dir =
+                    Some(it.next().context("--capture-dir requires a value")?.into());
+            }
+            "--per-session-logs" => {
+                args.per_session_logs = true;
+            }
+            "--daemonize" => {
+                if saw_foreground {
+                    anyhow::bail!("--daemonize and --foreground are mutually exclusive");
+                }
+                args.daemonize = true;
+            }
+            "--foreground" => {
+                if args.daemonize {
+                    anyhow::bail!("--daemonize and --foreground are mutually exclusive");
+                }
+                saw_foreground = true;
+            }
+            "--quiet" => {
+                args.quiet = true;
+            }
+            "--check" => {
+                args.check = true;
+            }
+            "--status" => {
+                args.status = true;
+            }
+            "--print-socket" => {
+                args.print_socket = true;
+            }
+            "--max-log-size" => {
+                let value = it.next().context("--max-log-size requires a value")?;
+                args.max_log_size = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid --max-log-size value: {value}"))?,
+                );
+            }
+            other => anyhow::bail!("unexpected argument: {other}"),
+        }
+    }
+    Ok(args)
+}
+
+/// Where `--daemonize` writes the final daemon process's pid: beside the
+/// resolved socket path, the same way `Server`'s own `state_path` already
+/// sits next to it (see `server::state_path_for`).
+///
+/// An abstract (`@name`) socket has no filesystem path to sit beside, so
+/// this falls back to the same cache directory `hxd.state.json` uses in
+/// that case.
+fn pidfile_path(socket: &Option<String>) -> std::path::PathBuf {
+    match socket.as_deref().and_then(|s| s.strip_prefix('@')) {
+        Some(_) => helix_loader::cache_dir().join("hxd.pid"),
+        None => {
+            let resolved = helix_daemon::proto::resolve_socket_path(
+                socket.as_deref().map(std::path::Path::new),
+            );
+            resolved.with_extension("pid")
+        }
+    }
+}
+
+/// The socket path this invocation would actually bind, for `hxd
+/// --print-socket`. An abstract (`@name`) socket has no filesystem path to
+/// resolve and is printed as-is; otherwise this mirrors `Server::new`'s own
+/// resolution (`--socket`/`daemon.toml`, then `$HELIX_DAEMON_SOCKET`, then
+/// the version-derived default), so the printed path is the one `hxd` would
+/// actually listen on.
+fn resolved_socket_display(socket: &Option<String>) -> String {
+    match socket.as_deref().and_then(|s| s.strip_prefix('@')) {
+        Some(name) => format!("@{name}"),
+        None => helix_daemon::proto::resolve_socket_path(
+            socket.as_deref().map(std::path::Path::new),
+        )
+        .to_string_lossy()
+        .into_owned(),
+    }
+}
+
+/// The classic SysV double-fork dance: fork, `setsid` in the intermediate
+/// child so it can never reacquire a controlling terminal, fork again so the
+/// final process isn't a session leader either (and so can't accidentally
+/// acquire a controlling terminal by opening a tty), `chdir("/")` so it
+/// doesn't pin whatever directory it was started from, and redirect all
+/// three standard streams to `/dev/null` since there's no terminal left to
+/// write to. The final child's pid is written to `pidfile`.
+///
+/// Must run before the tokio runtime is built: forking a multi-threaded
+/// process is unsound, and nothing multi-threaded has started yet this early
+/// in `main`.
+#[cfg(not(windows))]
+fn daemonize(pidfile: &std::path::Path) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // Safety: fork() is safe to call here because nothing multi-threaded
+    // (the tokio runtime, in particular) has started yet.
+    match unsafe { libc::fork() } {
+        -1 => anyhow::bail!("fork failed: {}", std::io::Error::last_os_error()),
+        0 => {} // intermediate child: fall through
+        _ => std::process::exit(0), // original process: handed off, done
+    }
+
+    // Safety: setsid() has no preconditions beyond not already being a
+    // process group leader, which the intermediate child just forked from
+    // one and so isn't.
+    if unsafe { libc::setsid() } == -1 {
+        anyhow::bail!("setsid failed: {}", std::io::Error::last_os_error());
+    }
+
+    // Safety: same as the first fork() above.
+    match unsafe { libc::fork() } {
+        -1 => anyhow::bail!("fork failed: {}", std::io::Error::last_os_error()),
+        0 => {} // final daemon process: fall through
+        _ => std::process::exit(0), // intermediate child: handed off, done
+    }
+
+    std::env::set_current_dir("/")?;
+
+    let devnull = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // Safety: `devnull` stays open for the duration of this loop, and
+        // `fd` is always one of the three standard, already-valid fds.
+        if unsafe { libc::dup2(devnull.as_raw_fd(), fd) } == -1 {
+            anyhow::bail!("dup2 failed: {}", std::io::Error::last_os_error());
+        }
+    }
+
+    if let Some(parent) = pidfile.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(pidfile, format!("{}\n", std::process::id()))?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn daemonize(_pidfile: &std::path::Path) -> Result<()> {
+    anyhow::bail!("--daemonize is not supported on Windows; run hxd under a service manager instead")
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+    if args.daemonize {
+        daemonize(&pidfile_path(&args.socket))?;
+    }
+    main_impl(args)
+}
+
+fn main_impl(args: Args) -> Result<()> {
+    let config_path = resolve_config_path(&args);
+    let file_config = config_path
+        .as_deref()
+        .map(FileConfig::load)
+        .transpose()?;
+
+    let verbosity = effective_verbosity(&args, file_config.as_ref());
+    let socket = effective_socket(&args, file_config.as_ref());
+
+    let mut config = ServerConfig {
+        capture_dir: args.capture_dir.clone(),
+        per_session_logs: args.per_session_logs,
+        ..ServerConfig::default()
+    };
+    if let Some(file_config) = &file_config {
+        apply_file_config(&mut config, file_config);
+    }
+
+    if args.check {
+        print_effective_config(&socket, verbosity, &config);
+        return Ok(());
+    }
+
+    if args.print_socket {
+        println!("{}", resolved_socket_display(&socket));
+        return Ok(());
+    }
+
+    if args.status {
+        let exit_code = run_status(socket)?;
+        std::process::exit(exit_code);
+    }
+
+    // A bare `-v` (no filename) goes to stderr; no `-v` at all keeps logging
+    // to the same fixed file this always used, for unattended/systemd runs.
+    let log_file = match (verbosity, &args.log_file) {
+        (0, _) => Some(default_log_file()),
+        (_, file) => file.clone(),
+    };
+    let max_log_bytes = args
+        .max_log_size
+        .unwrap_or(helix_daemon::logging::DEFAULT_MAX_LOG_BYTES);
+    let also_stdout = !args.daemonize && !args.quiet;
+    helix_daemon::logging::setup(verbosity, log_file.as_deref(), max_log_bytes, also_stdout)?;
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async {
+        let mut server = match socket_activation_listener() {
+            Some(listener) => {
+                log::info!("using socket-activated listener from systemd");
+                Server::from_listener(listener, config)?
+            }
+            None => match socket.as_deref().and_then(|s| s.strip_prefix('@')) {
+                Some(name) => Server::bind_abstract(name, config)?,
+                None => {
+                    let path = socket.map(std::path::PathBuf::from);
+                    Server::new(path, config)?
+                }
+            },
+        };
+        if let Some(config_path) = config_path {
+            server = server.watch_config(config_path);
+        }
+        sd_notify_ready();
+        server.run().await
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> Result<Args> {
+        parse_args_from(argv.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn daemonize_defaults_to_off() {
+        let args = parse(&["--socket", "/tmp/x.sock"]).unwrap();
+        assert!(!args.daemonize);
+    }
+
+    #[test]
+    fn daemonize_flag_turns_it_on() {
+        let args = parse(&["--daemonize"]).unwrap();
+        assert!(args.daemonize);
+    }
+
+    #[test]
+    fn foreground_flag_is_accepted_and_keeps_daemonize_off() {
+        let args = parse(&["--foreground"]).unwrap();
+        assert!(!args.daemonize);
+    }
+
+    #[test]
+    fn daemonize_and_foreground_together_are_rejected_in_either_order() {
+        assert!(parse(&["--daemonize", "--foreground"]).is_err());
+        assert!(parse(&["--foreground", "--daemonize"]).is_err());
+    }
+
+    #[test]
+    fn pidfile_path_sits_beside_a_filesystem_socket() {
+        let socket = Some("/tmp/hxd-test.sock".to_string());
+        assert_eq!(
+            pidfile_path(&socket),
+            std::path::Path::new("/tmp/hxd-test.pid")
+        );
+    }
+
+    #[test]
+    fn pidfile_path_falls_back_to_the_cache_dir_for_an_abstract_socket() {
+        let socket = Some("@my-daemon".to_string());
+        assert_eq!(pidfile_path(&socket), helix_loader::cache_dir().join("hxd.pid"));
+    }
+
+    #[test]
+    fn max_log_size_defaults_to_unset() {
+        let args = parse(&["--socket", "/tmp/x.sock"]).unwrap();
+        assert_eq!(args.max_log_size, None);
+    }
+
+    #[test]
+    fn max_log_size_parses_a_byte_count() {
+        let args = parse(&["--max-log-size", "1048576"]).unwrap();
+        assert_eq!(args.max_log_size, Some(1048576));
+    }
+
+    #[test]
+    fn max_log_size_rejects_a_non_numeric_value() {
+        assert!(parse(&["--max-log-size", "huge"]).is_err());
+    }
+
+    #[test]
+    fn quiet_defaults_to_off() {
+        let args = parse(&["--socket", "/tmp/x.sock"]).unwrap();
+        assert!(!args.quiet);
+    }
+
+    #[test]
+    fn quiet_flag_turns_it_on() {
+        let args = parse(&["--quiet"]).unwrap();
+        assert!(args.quiet);
+    }
+
+    #[test]
+    fn check_defaults_to_off() {
+        let args = parse(&["--socket", "/tmp/x.sock"]).unwrap();
+        assert!(!args.check);
+    }
+
+    #[test]
+    fn check_flag_turns_it_on() {
+        let args = parse(&["--check"]).unwrap();
+        assert!(args.check);
+    }
+
+    #[test]
+    fn status_defaults_to_off() {
+        let args = parse(&["--socket", "/tmp/x.sock"]).unwrap();
+        assert!(!args.status);
+    }
+
+    #[test]
+    fn status_flag_turns_it_on() {
+        let args = parse(&["--status"]).unwrap();
+        assert!(args.status);
+    }
+
+    #[test]
+    fn print_socket_defaults_to_off() {
+        let args = parse(&["--socket", "/tmp/x.sock"]).unwrap();
+        assert!(!args.print_socket);
+    }
+
+    #[test]
+    fn print_socket_flag_turns_it_on() {
+        let args = parse(&["--print-socket"]).unwrap();
+        assert!(args.print_socket);
+    }
+
+    #[test]
+    fn resolved_socket_display_echoes_an_abstract_name_as_is() {
+        assert_eq!(
+            resolved_socket_display(&Some("@my-daemon".to_string())),
+            "@my-daemon"
+        );
+    }
+
+    #[test]
+    fn resolved_socket_display_prefers_the_explicit_value() {
+        std::env::set_var("HELIX_DAEMON_SOCKET", "/tmp/env.sock");
+        assert_eq!(
+            resolved_socket_display(&Some("/tmp/explicit.sock".to_string())),
+            "/tmp/explicit.sock"
+        );
+        std::env::remove_var("HELIX_DAEMON_SOCKET");
+    }
+
+    #[test]
+    fn resolved_socket_display_falls_back_to_the_env_var_then_the_default() {
+        std::env::set_var("HELIX_DAEMON_SOCKET", "/tmp/env.sock");
+        assert_eq!(resolved_socket_display(&None), "/tmp/env.sock");
+
+        std::env::remove_var("HELIX_DAEMON_SOCKET");
+        assert_eq!(
+            resolved_socket_display(&None),
+            helix_daemon::proto::resolve_socket_path(None).to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn apply_file_config_leaves_defaults_when_the_file_sets_nothing() {
+        let mut config = ServerConfig::default();
+        apply_file_config(&mut config, &FileConfig::default());
+        assert_eq!(config.max_sessions, None);
+        assert_eq!(config.max_attached_peers, 1);
+        assert_eq!(config.mirror_queue_capacity, 256);
+        assert_eq!(config.on_create, None);
+    }
+
+    #[test]
+    fn apply_file_config_overrides_defaults_with_file_values() {
+        let mut config = ServerConfig::default();
+        let file = FileConfig {
+            max_sessions: Some(4),
+            max_attached_peers: Some(2),
+            mirror_queue_capacity: Some(16),
+            on_create: Some("echo {sid} >> /tmp/sessions".to_string()),
+            ..FileConfig::default()
+        };
+        apply_file_config(&mut config, &file);
+        assert_eq!(config.max_sessions, Some(4));
+        assert_eq!(config.max_attached_peers, 2);
+        assert_eq!(config.mirror_queue_capacity, 16);
+        assert_eq!(config.on_create.as_deref(), Some("echo {sid} >> /tmp/sessions"));
+    }
+
+    #[test]
+    fn effective_socket_defaults_to_none() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(effective_socket(&args, None), None);
+    }
+
+    #[test]
+    fn effective_socket_falls_back_to_the_file_when_no_flag_is_given() {
+        let args = parse(&[]).unwrap();
+        let file = FileConfig {
+            socket: Some("/tmp/file.sock".to_string()),
+            ..FileConfig::default()
+        };
+        assert_eq!(
+            effective_socket(&args, Some(&file)),
+            Some("/tmp/file.sock".to_string())
+        );
+    }
+
+    #[test]
+    fn effective_socket_prefers_the_flag_over_the_file() {
+        let args = parse(&["--socket", "/tmp/flag.sock"]).unwrap();
+        let file = FileConfig {
+            socket: Some("/tmp/file.sock".to_string()),
+            ..FileConfig::default()
+        };
+        assert_eq!(
+            effective_socket(&args, Some(&file)),
+            Some("/tmp/flag.sock".to_string())
+        );
+    }
+
+    #[test]
+    fn effective_verbosity_defaults_to_zero() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(effective_verbosity(&args, None), 0);
+    }
+
+    #[test]
+    fn effective_verbosity_falls_back_to_the_file_log_level() {
+        let args = parse(&[]).unwrap();
+        let file = FileConfig {
+            log_level: Some("debug".to_string()),
+            ..FileConfig::default()
+        };
+        assert_eq!(effective_verbosity(&args, Some(&file)), 2);
+    }
+
+    #[test]
+    fn effective_verbosity_prefers_the_flag_over_the_file() {
+        let args = parse(&["-vv"]).unwrap();
+        let file = FileConfig {
+            log_level: Some("trace".to_string()),
+            ..FileConfig::default()
+        };
+        assert_eq!(effective_verbosity(&args, Some(&file)), 2);
+    }
+
+    #[test]
+    fn resolve_config_path_uses_the_explicit_flag_even_if_it_does_not_exist() {
+        let args = parse(&["--config", "/no/such/daemon.toml"]).unwrap();
+        assert_eq!(
+            resolve_config_path(&args),
+            Some(std::path::PathBuf::from("/no/such/daemon.toml"))
+        );
+    }
+
+    #[test]
+    fn resolve_config_path_is_none_when_the_default_location_does_not_exist() {
+        // The default location is a real user directory we can't redirect
+        // in a unit test, but it's extremely unlikely to contain this file.
+        let args = parse(&[]).unwrap();
+        if !default_config_path().exists() {
+            assert_eq!(resolve_config_path(&args), None);
+        }
+    }
+}
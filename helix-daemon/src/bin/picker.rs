@@ -0,0 +1,129 @@
+//! Interactive picker for `hxc -a`/`--attach` with no target, used when
+//! stdin is a terminal and more than one detached session could be meant.
+//! Kept separate from `hxc.rs` so the selection logic (today: a 1-based
+//! list number or an alias prefix) can later be swapped for a fuzzy matcher
+//! without touching the surrounding CLI plumbing.
+
+use helix_daemon::proto::SessionId;
+
+/// One line of the picker's numbered listing, already formatted for
+/// display by the caller (see `idle_column` in `hxc.rs`).
+pub struct Candidate {
+    pub id: SessionId,
+    pub alias: Option<String>,
+    pub idle: String,
+    pub cwd: String,
+}
+
+fn format_candidate(index: usize, candidate: &Candidate) -> String {
+    format!(
+        "{:>2}) session {} {}\tidle {}\t{}",
+        index + 1,
+        candidate.id,
+        candidate.alias.as_deref().unwrap_or("-"),
+        candidate.idle,
+        candidate.cwd,
+    )
+}
+
+/// Parse a line typed at the picker prompt into the index of the candidate
+/// it selects: a 1-based list number, or an alias prefix that matches
+/// exactly one candidate. `None` for blank input, an out-of-range number, an
+/// alias prefix matching zero or more than one candidate, or anything else
+/// unrecognized — the caller treats that as "no pick" rather than an error.
+pub fn parse_selection(input: &str, candidates: &[Candidate]) -> Option<usize> {
+    let input = input.trim();
+    if input.is_empty() {
+        return None;
+    }
+    if let Ok(n) = input.parse::<usize>() {
+        return n.checked_sub(1).filter(|&i| i < candidates.len());
+    }
+    let mut matches = candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.alias.as_deref().map_or(false, |a| a.starts_with(input)));
+    let (index, _) = matches.next()?;
+    if matches.next().is_some() {
+        return None;
+    }
+    Some(index)
+}
+
+/// Print the numbered listing and read one line from stdin, returning the
+/// selected candidate's index. `None` on EOF, blank input, or anything
+/// [`parse_selection`] doesn't recognize.
+pub fn prompt(candidates: &[Candidate]) -> std::io::Result<Option<usize>> {
+    use std::io::Write;
+
+    for (i, candidate) in candidates.iter().enumerate() {
+        println!("{}", format_candidate(i, candidate));
+    }
+    print!("attach to: ");
+    std::io::stdout().flush()?;
+
+    let mut line = String::new();
+    if std::io::stdin().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(parse_selection(&line, candidates))
+}
+
+#[cfg(not(windows))]
+pub fn stdin_is_tty() -> bool {
+    use std::os::unix::io::AsRawFd;
+    // Safety: `isatty` only reads the fd's properties; it never touches the
+    // stdin buffer or lifetime.
+    unsafe { libc::isatty(std::io::stdin().as_raw_fd()) != 0 }
+}
+
+#[cfg(windows)]
+pub fn stdin_is_tty() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: SessionId, alias: Option<&str>) -> Candidate {
+        Candidate {
+            id,
+            alias: alias.map(str::to_string),
+            idle: "-".to_string(),
+            cwd: "-".to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_selection_accepts_a_one_based_index() {
+        let candidates = vec![candidate(1, None), candidate(2, None)];
+        assert_eq!(parse_selection("2", &candidates), Some(1));
+    }
+
+    #[test]
+    fn parse_selection_rejects_an_out_of_range_index() {
+        let candidates = vec![candidate(1, None)];
+        assert_eq!(parse_selection("0", &candidates), None);
+        assert_eq!(parse_selection("2", &candidates), None);
+    }
+
+    #[test]
+    fn parse_selection_matches_an_unambiguous_alias_prefix() {
+        let candidates = vec![candidate(1, Some("work")), candidate(2, Some("scratch"))];
+        assert_eq!(parse_selection("wo", &candidates), Some(0));
+    }
+
+    #[test]
+    fn parse_selection_rejects_an_ambiguous_alias_prefix() {
+        let candidates = vec![candidate(1, Some("work")), candidate(2, Some("workshop"))];
+        assert_eq!(parse_selection("work", &candidates), None);
+    }
+
+    #[test]
+    fn parse_selection_rejects_blank_input() {
+        let candidates = vec![candidate(1, None)];
+        assert_eq!(parse_selection("  ", &candidates), None);
+        assert_eq!(parse_selection("", &candidates), None);
+    }
+}
@@ -0,0 +1,2102 @@
+//! The client side of the protocol, used by `hxc` and other embedders.
+
+use crate::channel::Channel;
+use crate::error::{ClientError, Error, Result};
+use crate::proto::{
+    self, FileSpec, KillResult, Request, Response, SessionId, SessionRequest, SessionResponse,
+    SessionSummary, SortBy,
+};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio_seqpacket::UnixSeqpacket;
+
+#[cfg(not(windows))]
+use {signal_hook::consts::signal, signal_hook_tokio::Signals};
+#[cfg(windows)]
+type Signals = futures_util::stream::Empty<i32>;
+
+/// Exit codes [`SessionClient::run`] can hand back to `hxc`'s `main`, letting
+/// scripts distinguish *why* the connection ended instead of just whether it
+/// did.
+pub mod exit_code {
+    /// The session ran to completion or was terminated gracefully.
+    pub const NORMAL: i32 = 0;
+    /// The client was detached (e.g. by a takeover); the session is still
+    /// running.
+    pub const DETACHED: i32 = 2;
+    /// The session was force-killed rather than shut down gracefully.
+    pub const KILLED: i32 = 3;
+    /// The connection to the daemon was lost unexpectedly, rather than
+    /// closed as part of a documented response. Also used when the daemon
+    /// was never reachable in the first place (e.g. the socket doesn't
+    /// exist and autostart was turned off with `--no-autostart`, or the
+    /// daemon didn't come up within `--timeout`).
+    pub const DAEMON_LOST: i32 = 4;
+    /// A [`crate::error::ClientError::SessionNotFound`] was returned instead
+    /// of a generic error, so scripts can tell "no such session" apart from
+    /// other failures without parsing the message.
+    pub const SESSION_NOT_FOUND: i32 = 5;
+    /// The command line itself couldn't be parsed (unknown flag, missing
+    /// argument, conflicting options). Distinct from every other code here:
+    /// nothing was attempted against a daemon at all.
+    pub const USAGE_ERROR: i32 = 6;
+    /// A [`crate::error::ClientError::Occupied`] was returned: the session
+    /// already has an attached client and takeover isn't allowed.
+    pub const OCCUPIED: i32 = 7;
+    /// The daemon speaks a different protocol version than this `hxc`
+    /// build, or `--strict-version` rejected a daemon whose package version
+    /// doesn't match it.
+    pub const PROTOCOL_MISMATCH: i32 = 8;
+    /// The session reported [`crate::client::MAX_CONSECUTIVE_ERRORS`]
+    /// `SessionResponse::Err`s in a row with no successful response in
+    /// between, suggesting the client and the session have fallen out of
+    /// sync rather than hit one-off, individually recoverable rejections.
+    pub const TOO_MANY_ERRORS: i32 = 9;
+}
+
+/// How many consecutive `SessionResponse::Err`s [`SessionClient::run`]
+/// tolerates before giving up and reporting
+/// [`exit_code::TOO_MANY_ERRORS`] instead of looping forever against a
+/// session that keeps rejecting everything it's sent.
+const MAX_CONSECUTIVE_ERRORS: u32 = 5;
+
+/// Environment variables relevant to hosting an editor process, captured at
+/// session creation (see [`Client::new_session`]). Kept to a fixed allowlist
+/// rather than the whole environment: the latter is unbounded and this is
+/// sent as a single datagram.
+const SESSION_ENV_ALLOWLIST: &[&str] = &[
+    "PATH", "TERM", "SHELL", "LANG", "LC_ALL", "COLORTERM", "EDITOR", "VISUAL", "HOME",
+];
+
+/// Filter `vars` down to [`SESSION_ENV_ALLOWLIST`], in allowlist order. Split
+/// out from [`session_env`] so the filtering itself is testable without
+/// depending on the test process's real environment.
+fn filter_session_env<I: IntoIterator<Item = (String, String)>>(vars: I) -> Vec<(String, String)> {
+    let vars: std::collections::HashMap<_, _> = vars.into_iter().collect();
+    SESSION_ENV_ALLOWLIST
+        .iter()
+        .filter_map(|&key| vars.get(key).map(|value| (key.to_string(), value.clone())))
+        .collect()
+}
+
+/// Capture this process's environment, filtered down to
+/// [`SESSION_ENV_ALLOWLIST`].
+fn session_env() -> Vec<(String, String)> {
+    filter_session_env(std::env::vars())
+}
+
+/// This process's current working directory, as a display string, captured
+/// at session creation (see [`Client::new_session`]) so a brand-new session
+/// already knows its cwd instead of waiting for the eventual editor inside
+/// it to report one via [`SessionRequest::SetCwd`]. Falls back to an empty
+/// string if the directory can't be read (e.g. it was removed out from
+/// under this process) rather than failing session creation over it.
+fn session_cwd() -> String {
+    std::env::current_dir()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The default deadline for [`Client::connect_with_retry`], and (once
+/// connected) the default per-request timeout applied via
+/// [`Client::with_timeout`]. See `hxc`'s `--timeout`.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The default deadline for [`Client::kill_session_wait`] between sending
+/// the kill and the session actually disappearing. See `hxc`'s `--kill
+/// --wait`.
+pub const DEFAULT_KILL_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The initial delay between connection attempts in
+/// [`Client::connect_with_retry`], doubling (capped at
+/// [`CONNECT_RETRY_MAX_BACKOFF`]) after each failed attempt.
+const CONNECT_RETRY_INITIAL_BACKOFF: Duration = Duration::from_millis(20);
+/// The most [`Client::connect_with_retry`] will ever wait between attempts.
+const CONNECT_RETRY_MAX_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Whether `err` looks like `hxd` simply isn't listening yet (its socket
+/// doesn't exist, or nothing is accepting on it) rather than a genuine
+/// failure like a permission error, so [`Client::connect_with_retry`] knows
+/// whether retrying could plausibly help.
+fn is_transient_connect_error(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::IO(io)
+            if matches!(
+                io.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+            )
+    )
+}
+
+/// Whether `request` is safe to include in a [`Client::pipeline`] batch:
+/// does it always reply exactly once over the same connection, rather than
+/// handing the connection off to a session/watcher task or a deferred-reply
+/// queue? Mirrors `Server::dispatch_request`'s own classification, which is
+/// what actually decides whether the connection survives past this request.
+fn is_pipeline_safe(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::ListSessions { .. }
+            | Request::LockSession { .. }
+            | Request::TagSession { .. }
+            | Request::KillSessions { .. }
+            | Request::SwapSessions(..)
+            | Request::Version
+            | Request::SendToSession { .. }
+            | Request::Metrics
+            | Request::SetLogLevel(..)
+            | Request::SetSessionTimeout { .. }
+    )
+}
+
+/// A short-lived connection used to issue a single control [`Request`].
+pub struct Client {
+    /// `None` once this connection has been handed off to a
+    /// [`SessionClient`]/[`SessionWatcher`] (via a consuming method like
+    /// [`Self::new_session`]) or explicitly ended via [`Self::close`] —
+    /// checked by [`Drop`] so a `Client` in either state doesn't try to shut
+    /// down a channel it no longer owns.
+    channel: Option<Channel>,
+    /// Applied to each `recv` in [`Self::request`] when set, via
+    /// [`Self::with_timeout`]. `None` (the default) waits indefinitely.
+    timeout: Option<Duration>,
+}
+
+impl Client {
+    pub async fn connect(addr: Option<PathBuf>) -> Result<Self> {
+        let path = proto::resolve_socket_path(addr.as_deref());
+        let conn = UnixSeqpacket::connect(&path).await?;
+        Ok(Self {
+            channel: Some(Channel::new(conn)),
+            timeout: None,
+        })
+    }
+
+    /// Like [`Self::connect`], but retries `ENOENT`/`ECONNREFUSED` with
+    /// exponential backoff until `timeout` elapses, instead of failing on
+    /// the first attempt. Useful right after starting `hxd` (e.g. via
+    /// autostart or systemd activation), when its socket may not exist yet
+    /// or may not be accepting connections for a brief moment. A
+    /// non-transient error (see [`is_transient_connect_error`]) is returned
+    /// immediately without retrying; a deadline exceeded on a transient
+    /// error becomes [`Error::Timeout`].
+    pub async fn connect_with_retry(addr: Option<PathBuf>, timeout: Duration) -> Result<Self> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut backoff = CONNECT_RETRY_INITIAL_BACKOFF;
+        loop {
+            match Self::connect(addr.clone()).await {
+                Ok(client) => return Ok(client),
+                Err(err) if is_transient_connect_error(&err) => {
+                    if tokio::time::Instant::now() >= deadline {
+                        return Err(Error::Timeout);
+                    }
+                    tokio::time::sleep(backoff.min(deadline - tokio::time::Instant::now())).await;
+                    backoff = (backoff * 2).min(CONNECT_RETRY_MAX_BACKOFF);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Connect to a daemon listening in the Linux abstract namespace under
+    /// `name` (see [`crate::server::Server::bind_abstract`]).
+    pub async fn connect_abstract(name: &str) -> Result<Self> {
+        let conn = crate::socket::connect(name).await?;
+        Ok(Self {
+            channel: Some(Channel::new(conn)),
+            timeout: None,
+        })
+    }
+
+    /// Bound each subsequent request/response exchange (see [`Self::request`])
+    /// to `timeout`, returning [`Error::Timeout`] if the daemon doesn't
+    /// respond in time.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Raise (or lower) this connection's message-size cap from
+    /// [`crate::channel::DEFAULT_MAX_MESSAGE_SIZE`] — see
+    /// [`crate::channel::Channel::set_max_message_size`]. Carried over to
+    /// the [`SessionClient`] this connection eventually becomes, via
+    /// [`Self::new_session`]/[`Self::attach_session`], so `hxc
+    /// --input-buffer` can let an attached session exchange output chunks
+    /// larger than the default cap without raising it daemon-wide.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.channel_mut().set_max_message_size(max_message_size);
+        self
+    }
+
+    /// Borrow the channel, panicking if it's already been handed off or
+    /// closed — every call site is reachable only while `self.channel` is
+    /// still `Some`, since the methods that clear it either consume `self`
+    /// (so there's no way to call another method afterwards) or consume it
+    /// themselves ([`Self::close`]).
+    fn channel_mut(&mut self) -> &mut Channel {
+        self.channel.as_mut().expect("Client used after close")
+    }
+
+    /// Take ownership of the channel, e.g. to hand it to a [`SessionClient`]
+    /// or [`SessionWatcher`]. Leaves `self.channel` as `None`, so `Drop`
+    /// knows there's nothing left here for it to shut down.
+    fn take_channel(&mut self) -> Channel {
+        self.channel.take().expect("Client used after close")
+    }
+
+    async fn request(&mut self, request: Request) -> Result<Response> {
+        self.channel_mut().send(&request).await?;
+        let response = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.channel_mut().recv::<Response>())
+                .await
+                .map_err(|_| Error::Timeout)??,
+            None => self.channel_mut().recv::<Response>().await?,
+        };
+        response.ok_or(Error::Closed)
+    }
+
+    /// Classify a `Response::Err` message into a [`ClientError`], so callers
+    /// that care can match `SessionNotFound`/`Occupied` instead of the raw
+    /// string. Anything else is passed through as `ClientError::Server`.
+    fn classify_error(message: String) -> ClientError {
+        match message.as_str() {
+            "no such session" => ClientError::SessionNotFound,
+            "session is occupied" => ClientError::Occupied,
+            "no detached sessions" => ClientError::NoDetachedSessions,
+            "incorrect or missing passphrase" => ClientError::WrongPassphrase,
+            _ => ClientError::Server(message),
+        }
+    }
+
+    /// Explicitly end this connection rather than relying on `Drop` to
+    /// notice it was discarded, so the daemon sees a clean disconnect right
+    /// away instead of only once this `Client`'s channel is actually torn
+    /// down by the runtime. A no-op if the connection was already handed
+    /// off to a [`SessionClient`]/[`SessionWatcher`].
+    pub fn close(mut self) -> Result<()> {
+        if let Some(channel) = self.channel.take() {
+            channel.shutdown();
+        }
+        Ok(())
+    }
+
+    /// Send several pipeline-safe `requests` over this one connection and
+    /// collect their responses in the same order, amortizing the connection
+    /// setup a series of individual round-trips would each pay. Relies on
+    /// `Server::dispatch_request` keeping the connection open between
+    /// requests that only ever reply once (see [`is_pipeline_safe`]).
+    ///
+    /// Every request must be pipeline-safe: one that hands the connection
+    /// off elsewhere instead (`NewSession`, `AttachSession`, ...) would
+    /// leave the rest of the batch with nothing to talk to, so the whole
+    /// batch is rejected up front rather than partially sent.
+    pub async fn pipeline(&mut self, requests: Vec<Request>) -> Result<Vec<Response>> {
+        if let Some(request) = requests.iter().find(|r| !is_pipeline_safe(r)) {
+            return Err(anyhow::anyhow!("request is not pipeline-safe: {request:?}").into());
+        }
+        let mut responses = Vec::with_capacity(requests.len());
+        for request in requests {
+            responses.push(self.request(request).await?);
+        }
+        Ok(responses)
+    }
+
+    /// Create a new session and attach to it, consuming this connection.
+    /// Conveys this process's environment (filtered to
+    /// [`SESSION_ENV_ALLOWLIST`]) so the daemon can report it back via
+    /// [`SessionSummary::env`], and eventually pass it to a hosted editor
+    /// process; also conveys its current working directory so the session
+    /// has a correct one from the start (see [`SessionSummary::cwd`]).
+    pub async fn new_session(self) -> Result<SessionClient> {
+        self.new_session_with_files(Vec::new()).await
+    }
+
+    /// Like [`Self::new_session`], but also conveys `files` — the client's
+    /// `file[:row[:col]]` positional arguments — for the eventual editor
+    /// process hosted in the session to open.
+    pub async fn new_session_with_files(mut self, files: Vec<FileSpec>) -> Result<SessionClient> {
+        match self
+            .request(Request::NewSession {
+                env: session_env(),
+                cwd: session_cwd(),
+                files,
+            })
+            .await?
+        {
+            Response::SessionCreated { id } => Ok(SessionClient::new(id, self.take_channel())),
+            Response::Err(message) => Err(anyhow::anyhow!(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to NewSession").into()),
+        }
+    }
+
+    /// Attach to an existing session, consuming this connection. `takeover`
+    /// requests displacing an already-attached client, which only succeeds if
+    /// the daemon has [`crate::server::ServerConfig::allow_takeover`] set.
+    /// `passphrase` is required, and checked, if the session was locked via
+    /// [`Self::lock_session`]; a missing or incorrect one comes back as
+    /// [`crate::error::ClientError::WrongPassphrase`].
+    pub async fn attach_session(
+        mut self,
+        id: SessionId,
+        takeover: bool,
+        passphrase: Option<String>,
+    ) -> Result<SessionClient> {
+        match self
+            .request(Request::AttachSession {
+                id,
+                takeover,
+                passphrase,
+            })
+            .await?
+        {
+            Response::Attached { id, .. } => Ok(SessionClient::new(id, self.take_channel())),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to AttachSession").into()),
+        }
+    }
+
+    /// Attach to the detached session the daemon last saw a client leave, so
+    /// a caller can pick up where they left off without knowing its id (see
+    /// [`Request::AttachLast`]). Returns the session's alias alongside the
+    /// client so callers can report which one they ended up in. Locked
+    /// sessions are never picked, so there's no passphrase to supply here.
+    pub async fn attach_last(mut self, takeover: bool) -> Result<(SessionClient, Option<String>)> {
+        match self.request(Request::AttachLast { takeover }).await? {
+            Response::Attached { id, alias } => Ok((SessionClient::new(id, self.take_channel()), alias)),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to AttachLast").into()),
+        }
+    }
+
+    /// Attach to the session aliased `alias`, creating one pre-aliased to it
+    /// if none exists yet, as a single atomic request (see
+    /// [`Request::AttachOrCreate`]) so scripted "attach or create" workflows
+    /// don't race a separate list-then-decide against themselves. Returns
+    /// whether a new session had to be created, so the caller can report it.
+    pub async fn attach_or_create(self, alias: String) -> Result<(SessionClient, bool)> {
+        self.attach_or_create_with_files(alias, Vec::new()).await
+    }
+
+    /// Like [`Self::attach_or_create`], but also conveys `files` for the
+    /// eventual editor process to open, if a new session ends up being
+    /// created (ignored when attaching to an existing one).
+    pub async fn attach_or_create_with_files(
+        mut self,
+        alias: String,
+        files: Vec<FileSpec>,
+    ) -> Result<(SessionClient, bool)> {
+        match self
+            .request(Request::AttachOrCreate {
+                alias,
+                env: session_env(),
+                cwd: session_cwd(),
+                files,
+            })
+            .await?
+        {
+            Response::AttachedOrCreated { id, created } => {
+                Ok((SessionClient::new(id, self.take_channel()), created))
+            }
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to AttachOrCreate").into()),
+        }
+    }
+
+    /// Lock a detached session behind a passphrase (`None` unlocks it
+    /// again), so it can't be attached without a matching one — see
+    /// [`crate::auth::hash_passphrase`] for producing `passphrase_hash`.
+    /// Rejected with [`crate::error::ClientError::SessionNotFound`] if the
+    /// session doesn't exist, or a `ClientError::Server` if it's currently
+    /// attached.
+    pub async fn lock_session(
+        &mut self,
+        id: SessionId,
+        passphrase_hash: Option<String>,
+    ) -> Result<()> {
+        match self
+            .request(Request::LockSession {
+                id,
+                passphrase_hash,
+            })
+            .await?
+        {
+            Response::Ok => Ok(()),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to LockSession").into()),
+        }
+    }
+
+    pub async fn list_sessions(&mut self, sort: SortBy, all: bool) -> Result<Vec<SessionSummary>> {
+        self.list_sessions_with_tag(sort, all, None).await
+    }
+
+    /// Like [`Self::list_sessions`], but restricted to sessions carrying
+    /// `tag` (see [`Request::TagSession`]) when it's set.
+    pub async fn list_sessions_with_tag(
+        &mut self,
+        sort: SortBy,
+        all: bool,
+        tag: Option<String>,
+    ) -> Result<Vec<SessionSummary>> {
+        match self.request(Request::ListSessions { sort, all, tag }).await? {
+            Response::Sessions(sessions) => Ok(sessions),
+            Response::Err(message) => Err(anyhow::anyhow!(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to ListSessions").into()),
+        }
+    }
+
+    /// Add or remove tags on a session (see [`Request::TagSession`]).
+    /// `remove` is applied before `add`.
+    pub async fn tag_session(
+        &mut self,
+        id: SessionId,
+        add: Vec<String>,
+        remove: Vec<String>,
+    ) -> Result<()> {
+        match self.request(Request::TagSession { id, add, remove }).await? {
+            Response::Ok => Ok(()),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to TagSession").into()),
+        }
+    }
+
+    /// Terminate a session and wait for it to actually finish dying before
+    /// returning (see `Server::pending_kills`), not just for the daemon to
+    /// acknowledge the request — a `--list` immediately after this returns
+    /// won't show `id` anymore. Times out with a `ClientError::Server`
+    /// naming the session as still shutting down if it takes longer than
+    /// the daemon's `ServerConfig::terminate_confirm_timeout`.
+    pub async fn kill_session(&mut self, id: SessionId, force: bool) -> Result<()> {
+        match self.request(Request::KillSession { id, force }).await? {
+            Response::Ok => Ok(()),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to KillSession").into()),
+        }
+    }
+
+    /// Kill several sessions in one round trip (see [`Request::KillSessions`]).
+    /// A missing id doesn't fail the call outright; it's reported as its own
+    /// [`KillResult`] alongside the rest. Unlike [`Self::kill_session`], the
+    /// daemon acknowledges each id as soon as its termination is enqueued
+    /// rather than waiting for every session in the batch to actually die.
+    pub async fn kill_sessions(&mut self, ids: Vec<SessionId>, force: bool) -> Result<Vec<KillResult>> {
+        match self.request(Request::KillSessions { ids, force }).await? {
+            Response::KillResults(results) => Ok(results),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to KillSessions").into()),
+        }
+    }
+
+    /// Like [`Self::kill_session`], but additionally falls back to polling
+    /// via [`Self::wait_for_session_gone`] if the daemon's own
+    /// confirmation ever regresses to acknowledging before termination
+    /// (e.g. an older daemon). Harmless against a current one: by the time
+    /// `kill_session` returns, `id` is already gone, so this resolves on
+    /// its first check.
+    pub async fn kill_session_wait(
+        &mut self,
+        id: SessionId,
+        force: bool,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.kill_session(id, force).await?;
+        self.wait_for_session_gone(id, timeout).await
+    }
+
+    /// Block until `id` no longer appears among the daemon's active
+    /// sessions, or `timeout` elapses. Doesn't itself request termination;
+    /// used by [`Self::kill_session_wait`] and by callers that already
+    /// triggered it some other way (e.g. a batched [`Self::kill_sessions`])
+    /// and just need to know when it's actually gone.
+    pub async fn wait_for_session_gone(&mut self, id: SessionId, timeout: Duration) -> Result<()> {
+        let outcome = tokio::time::timeout(timeout, async {
+            self.channel_mut().send(&Request::WaitSession(id)).await?;
+            match self.channel_mut().recv::<Response>().await?.ok_or(Error::Closed)? {
+                Response::SessionEnded { .. } => Ok(()),
+                // The session had already finished (and been reaped) by the
+                // time this subscription went in, racing too late to see it
+                // end; that's still "gone", not a failure.
+                Response::Err(message) if message == "no such session" => Ok(()),
+                Response::Err(message) => Err(Self::classify_error(message).into()),
+                _ => Err(anyhow::anyhow!("unexpected response to WaitSession").into()),
+            }
+        })
+        .await;
+        outcome.unwrap_or(Err(Error::Timeout))
+    }
+
+    /// Swap the ids of two sessions (see [`Request::SwapSessions`]).
+    pub async fn swap_sessions(&mut self, a: SessionId, b: SessionId) -> Result<()> {
+        match self.request(Request::SwapSessions(a, b)).await? {
+            Response::Ok => Ok(()),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to SwapSessions").into()),
+        }
+    }
+
+    /// Forward `payload` to the session named or numbered `sid_or_alias`
+    /// without attaching (see [`Request::SendToSession`]). `Ok(())` only
+    /// means the daemon handed the command off to the session; it says
+    /// nothing about whatever eventually acts on it.
+    pub async fn send_to_session(&mut self, sid_or_alias: String, payload: String) -> Result<()> {
+        match self
+            .request(Request::SendToSession { sid_or_alias, payload })
+            .await?
+        {
+            Response::Ok => Ok(()),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to SendToSession").into()),
+        }
+    }
+
+    /// Block until `id` terminates, returning its `(code, forced)` (see
+    /// [`Request::WaitSession`]). Deliberately bypasses [`Self::request`]'s
+    /// timeout: a session can run for an arbitrary length of time, and the
+    /// bound that protects an ordinary request/response round trip would
+    /// otherwise make this time out on anything longer-lived than it.
+    pub async fn wait_session(mut self, id: SessionId) -> Result<(i32, bool)> {
+        self.channel_mut().send(&Request::WaitSession(id)).await?;
+        match self.channel_mut().recv::<Response>().await?.ok_or(Error::Closed)? {
+            Response::SessionEnded { code, forced } => Ok((code, forced)),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to WaitSession").into()),
+        }
+    }
+
+    /// Ask the daemon to shut down, terminating every session first, and
+    /// don't return until it actually has: `Response::Stopped` isn't sent
+    /// until every session has either terminated or been given up on after
+    /// `ServerConfig::shutdown_deadline`, which can take a good deal longer
+    /// than an ordinary request/response round trip, so this bypasses
+    /// [`Self::request`]'s timeout the same way [`Self::wait_session`] does.
+    /// Returns `(clean, forced, failed)` from `Response::Stopped`, so a
+    /// caller can report e.g. "stopped 3 sessions (1 forced)".
+    pub async fn stop_server(mut self) -> Result<(u64, u64, Vec<SessionId>)> {
+        self.channel_mut().send(&Request::StopServer).await?;
+        match self.channel_mut().recv::<Response>().await?.ok_or(Error::Closed)? {
+            Response::Stopped { clean, forced, failed } => Ok((clean, forced, failed)),
+            Response::Err(message) => Err(anyhow::anyhow!(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to StopServer").into()),
+        }
+    }
+
+    /// Ask the daemon what version it's running, e.g. to compare against
+    /// this client's own [`env!("CARGO_PKG_VERSION")`] before trusting a
+    /// connection any further. Returns `(crate_version, proto_version)`; the
+    /// latter should be compared against [`crate::proto::PROTO_VERSION`]
+    /// rather than assumed to match, since it's the wire format itself
+    /// (not just the daemon's build) that determines whether the rest of
+    /// the connection can be trusted.
+    pub async fn version(&mut self) -> Result<(String, u32)> {
+        match self.request(Request::Version).await? {
+            Response::Version { crate_version, proto_version } => Ok((crate_version, proto_version)),
+            Response::Err(message) => Err(anyhow::anyhow!(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to Version").into()),
+        }
+    }
+
+    /// Ask the daemon for its running counters (see [`Request::Metrics`]).
+    pub async fn metrics(&mut self) -> Result<std::collections::BTreeMap<String, u64>> {
+        match self.request(Request::Metrics).await? {
+            Response::Metrics(metrics) => Ok(metrics),
+            Response::Err(message) => Err(anyhow::anyhow!(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to Metrics").into()),
+        }
+    }
+
+    /// Change the running daemon's log level (see [`Request::SetLogLevel`]).
+    pub async fn set_log_level(&mut self, verbosity: u8) -> Result<()> {
+        match self.request(Request::SetLogLevel(verbosity)).await? {
+            Response::Ok => Ok(()),
+            Response::Err(message) => Err(anyhow::anyhow!(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to SetLogLevel").into()),
+        }
+    }
+
+    /// Override a session's idle-reap sweep independently of every other
+    /// session's (see `SessionHandle::idle_timeout` and
+    /// [`Request::SetSessionTimeout`]). `None` means never reap it.
+    /// Rejected with [`crate::error::ClientError::SessionNotFound`] if the
+    /// session doesn't exist.
+    pub async fn set_session_timeout(
+        &mut self,
+        id: SessionId,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        match self.request(Request::SetSessionTimeout { id, timeout }).await? {
+            Response::Ok => Ok(()),
+            Response::Err(message) => Err(Self::classify_error(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to SetSessionTimeout").into()),
+        }
+    }
+
+    /// Subscribe to a live stream of session-list changes, consuming this
+    /// connection (see [`Request::WatchSessions`]). Unlike every other
+    /// `Client` method this doesn't resolve to a single answer; it hands back
+    /// a [`SessionWatcher`] to poll for further deltas as they arrive.
+    pub async fn watch_sessions(mut self) -> Result<SessionWatcher> {
+        match self.request(Request::WatchSessions).await? {
+            Response::Ok => Ok(SessionWatcher {
+                channel: self.take_channel(),
+            }),
+            Response::Err(message) => Err(anyhow::anyhow!(message).into()),
+            _ => Err(anyhow::anyhow!("unexpected response to WatchSessions").into()),
+        }
+    }
+}
+
+/// A `Client` dropped without an explicit [`Client::close`] — e.g. one that
+/// went out of scope on an early return, or was simply never consumed by one
+/// of the methods that hands the channel off elsewhere — still shuts its
+/// channel down, so the daemon sees a clean disconnect instead of only
+/// noticing once the socket itself is torn down by the OS.
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(channel) = self.channel.take() {
+            channel.shutdown();
+        }
+    }
+}
+
+/// A connection subscribed to the live session-list stream via
+/// [`Client::watch_sessions`].
+pub struct SessionWatcher {
+    channel: Channel,
+}
+
+impl SessionWatcher {
+    /// Wait for the next change, or `Ok(None)` once the daemon closes the
+    /// connection (e.g. on shutdown).
+    pub async fn next(&mut self) -> Result<Option<proto::SessionListDelta>> {
+        match self.channel.recv::<Response>().await? {
+            Some(Response::SessionListDelta(delta)) => Ok(Some(delta)),
+            Some(Response::Err(message)) => Err(anyhow::anyhow!(message).into()),
+            Some(_) => Err(anyhow::anyhow!("unexpected response while watching sessions").into()),
+            None => Ok(None),
+        }
+    }
+}
+
+/// How long [`SessionClient::post_attach`] waits for the session's
+/// `AttachAck` before giving up. The handshake is the very first thing the
+/// session sends once `DetachableChannel::attach` hands it a slot, so a
+/// daemon that's actually alive should never take long to produce it.
+const ATTACH_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The terminal escape sequence (OSC 2) that sets the window title to
+/// `alias`. Split out from [`SessionClient::post_attach`] so the sequence
+/// itself is testable without a real terminal attached.
+fn terminal_title_escape(alias: &str) -> String {
+    format!("\x1b]2;{alias}\x07")
+}
+
+/// How long [`SessionClient::run`] waits after the last `SIGWINCH` before
+/// actually querying the terminal size and forwarding it, so a burst of
+/// resizes (e.g. dragging a window's edge) sends one `SessionRequest::Resize`
+/// instead of flooding the channel with one per signal.
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// How long [`SessionClient::run`] waits for `SessionResponse::Terminated`
+/// after a `SIGINT` sends `SessionRequest::Terminate`, before giving up on
+/// the acknowledgment and exiting anyway. Long enough for the daemon to
+/// actually read the request off the wire and tear the session down under
+/// ordinary load, short enough that a genuinely wedged daemon doesn't leave
+/// `Ctrl-C` looking like it did nothing.
+const TERMINATE_ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Read the controlling terminal's current size as `(rows, cols)` via the
+/// `TIOCGWINSZ` ioctl on stdout, or `None` if stdout isn't a terminal (e.g.
+/// the session's output is piped or redirected).
+#[cfg(not(windows))]
+fn terminal_size() -> Option<(u16, u16)> {
+    use std::os::unix::io::AsRawFd;
+
+    // Safety: `size` is fully initialized by `ioctl` before it's read, and
+    // only used if the call reports success.
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(std::io::stdout().as_raw_fd(), libc::TIOCGWINSZ, &mut size) != 0 {
+            return None;
+        }
+        if size.ws_row == 0 && size.ws_col == 0 {
+            return None;
+        }
+        Some((size.ws_row, size.ws_col))
+    }
+}
+
+#[cfg(windows)]
+fn terminal_size() -> Option<(u16, u16)> {
+    None
+}
+
+#[cfg(not(windows))]
+type TerminalState = libc::termios;
+#[cfg(windows)]
+type TerminalState = ();
+
+/// Put stdin into raw mode (no echo, no line buffering) so an attached
+/// session sees every keystroke as it's typed, returning the previous
+/// terminal state so [`leave_raw_mode`] can restore it later, e.g. around a
+/// `SIGTSTP`. `None` if the terminal state couldn't be read (e.g. stdin
+/// isn't a terminal).
+#[cfg(not(windows))]
+fn enter_raw_mode() -> Option<TerminalState> {
+    use std::os::unix::io::AsRawFd;
+    let stdin_fd = std::io::stdin().as_raw_fd();
+    // Safety: `original` is fully initialized by `tcgetattr` before it's
+    // read, and only used if the call reports success.
+    unsafe {
+        let mut original: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(stdin_fd, &mut original) != 0 {
+            return None;
+        }
+        let mut raw = original;
+        libc::cfmakeraw(&mut raw);
+        libc::tcsetattr(stdin_fd, libc::TCSANOW, &raw);
+        Some(original)
+    }
+}
+
+#[cfg(windows)]
+fn enter_raw_mode() -> Option<TerminalState> {
+    None
+}
+
+/// Restore a terminal state captured by [`enter_raw_mode`], so a stopped
+/// client (`SIGTSTP`) leaves the shell's own prompt in cooked mode instead
+/// of the session's raw one.
+#[cfg(not(windows))]
+fn leave_raw_mode(state: &TerminalState) {
+    use std::os::unix::io::AsRawFd;
+    let stdin_fd = std::io::stdin().as_raw_fd();
+    // Safety: `state` was populated by a prior successful `tcgetattr` call.
+    unsafe {
+        libc::tcsetattr(stdin_fd, libc::TCSANOW, state);
+    }
+}
+
+#[cfg(windows)]
+fn leave_raw_mode(_state: &TerminalState) {}
+
+/// Restores the terminal to cooked mode when dropped, so [`SessionClient::run`]
+/// leaves the terminal usable no matter which of its exit paths is taken.
+struct RawModeGuard(Option<TerminalState>);
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        if let Some(state) = &self.0 {
+            leave_raw_mode(state);
+        }
+    }
+}
+
+/// Suppresses verbose (`-v`) stderr logging for as long as it's alive, so it
+/// doesn't interleave with [`SessionClient::run`]'s raw-mode terminal UI. See
+/// `crate::logging::suppress_verbose_stderr`.
+struct SuppressVerboseStderr;
+
+impl SuppressVerboseStderr {
+    fn engage() -> Self {
+        crate::logging::suppress_verbose_stderr(true);
+        Self
+    }
+}
+
+impl Drop for SuppressVerboseStderr {
+    fn drop(&mut self) {
+        crate::logging::suppress_verbose_stderr(false);
+    }
+}
+
+/// Decide whether a freshly observed terminal `size` is worth forwarding as a
+/// `SessionRequest::Resize`, given the last size actually sent (`None` if
+/// none has been sent yet). Split out from [`SessionClient::run`] so the
+/// coalescing decision is testable without a live connection or a real
+/// terminal.
+fn coalesce_resize(last_sent: Option<(u16, u16)>, size: (u16, u16)) -> Option<(u16, u16)> {
+    if last_sent == Some(size) {
+        None
+    } else {
+        Some(size)
+    }
+}
+
+/// Scan `bytes` (a chunk just read from raw stdin) for `key`'s two-byte
+/// prefix/key sequence, returning `true` once the whole sequence has been
+/// seen. `awaiting` carries whether the prefix was already seen by an
+/// earlier call (the sequence can straddle two separate reads), and is
+/// updated in place. Split out from [`SessionClient::run`] so it's testable
+/// without a live stdin.
+fn scan_for_detach_key(bytes: &[u8], key: DetachKey, awaiting: &mut bool) -> bool {
+    for &byte in bytes {
+        if *awaiting {
+            *awaiting = false;
+            if byte == key.key {
+                return true;
+            }
+        } else if byte == key.prefix {
+            *awaiting = true;
+        }
+    }
+    false
+}
+
+/// Format a human-readable one-line summary of an `AttachAck`'s metadata,
+/// e.g. "session 3 (scratch): cwd /home/alice, 2 file(s) open". Split out
+/// from [`SessionClient::post_attach`] so the formatting is testable without
+/// a live connection.
+fn describe_attach(sid: SessionId, alias: Option<&str>, cwd: Option<&str>, files: &[String]) -> String {
+    let mut summary = format!("session {sid}");
+    if let Some(alias) = alias {
+        summary.push_str(&format!(" ({alias})"));
+    }
+    let mut details = Vec::new();
+    if let Some(cwd) = cwd {
+        details.push(format!("cwd {cwd}"));
+    }
+    if !files.is_empty() {
+        details.push(format!("{} file(s) open", files.len()));
+    }
+    if !details.is_empty() {
+        summary.push_str(": ");
+        summary.push_str(&details.join(", "));
+    }
+    summary
+}
+
+/// Default total backoff budget for [`SessionClient::with_reconnect`]
+/// before giving up and reporting [`exit_code::DAEMON_LOST`].
+pub const DEFAULT_RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Configuration for [`SessionClient::with_reconnect`], carrying whatever
+/// [`Self::try_reconnect`] needs to dial the daemon again without the
+/// caller having to thread it through every call.
+struct ReconnectConfig {
+    addr: Option<PathBuf>,
+    passphrase: Option<String>,
+    timeout: Duration,
+}
+
+/// A local "prefix, then key" detach sequence, e.g. tmux's own `C-b d`: hold
+/// `prefix` (a control character) first, then `key`, as two separate bytes
+/// read from the attached terminal's raw input. See
+/// [`SessionClient::with_detach_key`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetachKey {
+    prefix: u8,
+    key: u8,
+}
+
+impl DetachKey {
+    /// tmux's own default prefix/key pair, used unless `--detach-key`/
+    /// `HELIX_DAEMON_DETACH_KEY` says otherwise.
+    pub const DEFAULT: DetachKey = DetachKey { prefix: 0x02, key: b'd' };
+}
+
+impl std::str::FromStr for DetachKey {
+    type Err = String;
+
+    /// Parses `"C-b d"`: a `C-<letter>` control-character prefix, a space,
+    /// then a single plain key. The only grammar accepted for now — this is
+    /// one hardcoded action, not a general keybinding syntax.
+    fn from_str(spec: &str) -> std::result::Result<Self, Self::Err> {
+        let (prefix, key) = spec
+            .split_once(' ')
+            .ok_or_else(|| format!("expected \"C-<letter> <key>\", got {spec:?}"))?;
+        let prefix = prefix
+            .strip_prefix("C-")
+            .filter(|letter| letter.chars().count() == 1)
+            .and_then(|letter| letter.chars().next())
+            .filter(char::is_ascii_alphabetic)
+            .map(|c| (c.to_ascii_uppercase() as u8) & 0x1f)
+            .ok_or_else(|| format!("expected a \"C-<letter>\" prefix, got {prefix:?}"))?;
+        let key = key
+            .chars()
+            .next()
+            .filter(|_| key.chars().count() == 1)
+            .map(|c| c as u8)
+            .ok_or_else(|| format!("expected a single-character key, got {key:?}"))?;
+        Ok(DetachKey { prefix, key })
+    }
+}
+
+/// A connection attached to a running session.
+pub struct SessionClient {
+    pub id: SessionId,
+    channel: Channel,
+    reconnect: Option<ReconnectConfig>,
+    /// The local detach sequence [`Self::run`] watches raw stdin for, if
+    /// any (see [`Self::with_detach_key`]). `None` disables it entirely, so
+    /// `run` never reads stdin at all.
+    detach_key: Option<DetachKey>,
+    /// Consecutive `SessionResponse::Err`s seen with no successful response
+    /// in between (see [`MAX_CONSECUTIVE_ERRORS`]); reset by any other
+    /// response.
+    consecutive_errors: u32,
+}
+
+impl SessionClient {
+    fn new(id: SessionId, channel: Channel) -> Self {
+        Self {
+            id,
+            channel,
+            reconnect: None,
+            detach_key: None,
+            consecutive_errors: 0,
+        }
+    }
+
+    /// Opt in to automatic reconnection: if [`Self::run`] loses the
+    /// connection unexpectedly, it retries [`Client::connect_with_retry`]
+    /// and re-[`Client::attach_session`]s to this same session id, with
+    /// bounded backoff, for up to `timeout` before giving up and reporting
+    /// [`exit_code::DAEMON_LOST`] as it always did. Pairs with the daemon's
+    /// session state persistence (see `Server::persist_state`): a session
+    /// that's still alive on the daemon side after a brief disconnect can be
+    /// rejoined instead of losing the attached client for good. Doesn't
+    /// reach an abstract-namespace daemon, since [`Client::connect_with_retry`]
+    /// only knows how to dial a filesystem path (`addr`, or the default path
+    /// when `None`); callers should check that themselves before opting in.
+    pub fn with_reconnect(mut self, addr: Option<PathBuf>, passphrase: Option<String>, timeout: Duration) -> Self {
+        self.reconnect = Some(ReconnectConfig { addr, passphrase, timeout });
+        self
+    }
+
+    /// Opt in to a local detach hotkey: while attached, [`Self::run`] watches
+    /// raw stdin for `key` and, on seeing it, calls [`Self::detach`] and
+    /// returns [`exit_code::DETACHED`] itself, the same as being detached by
+    /// another client. `None` (the default) leaves stdin untouched, e.g. for
+    /// an embedder that reads it for its own purposes.
+    pub fn with_detach_key(mut self, key: Option<DetachKey>) -> Self {
+        self.detach_key = key;
+        self
+    }
+
+    /// The guts of the reconnect opted into via [`Self::with_reconnect`].
+    /// `false` on any failure along the way (the daemon never came back,
+    /// the attach was rejected, the handshake timed out) since every
+    /// failure mode collapses to the same outcome in [`Self::run`]: give up
+    /// and report [`exit_code::DAEMON_LOST`].
+    async fn try_reconnect(&mut self) -> bool {
+        let Some(cfg) = self.reconnect.as_ref() else {
+            return false;
+        };
+        let (addr, passphrase, timeout) = (cfg.addr.clone(), cfg.passphrase.clone(), cfg.timeout);
+        let Ok(client) = Client::connect_with_retry(addr, timeout).await else {
+            return false;
+        };
+        let Ok(reattached) = client.attach_session(self.id, false, passphrase).await else {
+            return false;
+        };
+        self.channel = reattached.channel;
+        if self.post_attach().await.is_err() {
+            return false;
+        }
+        eprintln!("reconnected to the daemon");
+        true
+    }
+
+    /// Wait for the session's `SessionResponse::AttachAck` handshake,
+    /// surfacing its metadata (setting the terminal title to the alias, and
+    /// printing a one-line summary to stderr) before [`Self::run`] starts
+    /// consuming ordinary session traffic. Callers that skip this (e.g. a
+    /// brand-new session, which has nothing to hand back yet) just go
+    /// straight to `run` instead.
+    ///
+    /// Errs with a clear message rather than sitting silent if the ack
+    /// doesn't arrive within [`ATTACH_ACK_TIMEOUT`], or if some other
+    /// message arrives first.
+    pub async fn post_attach(&mut self) -> Result<()> {
+        self.post_attach_with_timeout(ATTACH_ACK_TIMEOUT).await
+    }
+
+    /// The guts of [`Self::post_attach`], with the timeout broken out so
+    /// tests don't have to wait out the real [`ATTACH_ACK_TIMEOUT`].
+    async fn post_attach_with_timeout(&mut self, timeout: Duration) -> Result<()> {
+        let ack = tokio::time::timeout(timeout, self.channel.recv::<SessionResponse>()).await;
+        match ack {
+            Ok(Ok(Some(SessionResponse::AttachAck { sid, alias, cwd, files, .. }))) => {
+                if let Some(ref alias) = alias {
+                    print!("{}", terminal_title_escape(alias));
+                    let _ = std::io::stdout().flush();
+                }
+                eprintln!(
+                    "attached to {}",
+                    describe_attach(sid, alias.as_deref(), cwd.as_deref(), &files)
+                );
+                Ok(())
+            }
+            Ok(Ok(Some(other))) => Err(anyhow::anyhow!(
+                "expected an attach handshake as the first message, got {other:?} instead"
+            )
+            .into()),
+            Ok(Ok(None)) => Err(Error::Closed),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(anyhow::anyhow!(
+                "timed out after {timeout:?} waiting for the session's attach handshake"
+            )
+            .into()),
+        }
+    }
+
+    pub async fn detach(&mut self) -> Result<()> {
+        self.channel.send(&SessionRequest::Detach).await
+    }
+
+    /// Ask the session to terminate. Callers should keep driving [`Self::run`]
+    /// to observe the resulting `Terminated` response.
+    pub async fn terminate(&mut self) -> Result<()> {
+        self.terminate_with_code(0).await
+    }
+
+    /// Like [`Self::terminate`], but reports `code` as the exit status the
+    /// session should carry back in `SessionResponse::Terminated`, for a
+    /// client (eventually an editor core) that already knows its own exit
+    /// status when it asks to quit.
+    pub async fn terminate_with_code(&mut self, code: i32) -> Result<()> {
+        self.channel.send(&SessionRequest::Terminate { code }).await
+    }
+
+    /// Report the session's current working directory, so it shows up in
+    /// `hxc --list`. Rejected server-side (surfaced as a `Notice`-like
+    /// `Err` on [`Self::run`]) if `path` isn't a non-empty absolute path.
+    pub async fn set_cwd(&mut self, path: String) -> Result<()> {
+        self.channel.send(&SessionRequest::SetCwd(path)).await
+    }
+
+    /// Report the session's current list of open files, so it shows up
+    /// (truncated) in `hxc --list`. An empty list is valid.
+    pub async fn set_files(&mut self, files: Vec<String>) -> Result<()> {
+        self.channel.send(&SessionRequest::FilesChanged(files)).await
+    }
+
+    /// Rename the session, so it shows up under this name in `hxc --list`.
+    /// Rejected server-side (surfaced as an `Err` on [`Self::run`]) if
+    /// `alias` is empty.
+    pub async fn set_alias(&mut self, alias: String) -> Result<()> {
+        self.channel.send(&SessionRequest::SetAlias(alias)).await
+    }
+
+    /// Turn output capture on or off (see
+    /// [`crate::server::ServerConfig::capture_dir`]). Rejected server-side
+    /// (surfaced as an `Err` on [`Self::run`]) if no capture directory is
+    /// configured, or the capture file can't be opened.
+    pub async fn set_capture(&mut self, enable: bool) -> Result<()> {
+        self.channel.send(&SessionRequest::SetCapture(enable)).await
+    }
+
+    /// Report the client terminal's current size, e.g. after a `SIGWINCH`.
+    /// Callers are expected to coalesce bursts of resizes themselves (see
+    /// [`Self::run`]) rather than sending one per event.
+    pub async fn resize(&mut self, rows: u16, cols: u16) -> Result<()> {
+        self.channel.send(&SessionRequest::Resize { rows, cols }).await
+    }
+
+    /// Tell the session the client is about to stop (`SIGTSTP`), so it stops
+    /// forwarding output until [`Self::resume`] arrives. See [`Self::run`].
+    async fn suspend(&mut self) -> Result<()> {
+        self.channel.send(&SessionRequest::Suspended).await
+    }
+
+    /// Tell the session the client has resumed (`SIGCONT`), reporting its
+    /// (possibly changed) terminal size. See [`Self::run`].
+    async fn resume(&mut self, rows: u16, cols: u16) -> Result<()> {
+        self.channel.send(&SessionRequest::Resumed { rows, cols }).await
+    }
+
+    /// Drive the session loop until it terminates, returning one of the
+    /// [`exit_code`] constants for `hxc`'s `main` to propagate.
+    ///
+    /// An unexpected disconnect (the daemon crashing, the socket dropping)
+    /// is reported as [`exit_code::DAEMON_LOST`] rather than an `Err`, so
+    /// scripts can rely on the exit code alone. A single malformed message
+    /// (e.g. from a mismatched client/daemon version) is reported to stderr
+    /// and skipped rather than ending the session outright.
+    ///
+    /// Also watches `SIGWINCH` and forwards the terminal's size via
+    /// [`Self::resize`], debounced by [`RESIZE_DEBOUNCE`] and coalesced (see
+    /// [`coalesce_resize`]) so a burst of resizes sends at most one update.
+    /// The current size, if any, is sent once up front so the session learns
+    /// it right after attach/creation rather than waiting for the first
+    /// actual resize.
+    ///
+    /// Also puts the terminal into raw mode for the duration of the session
+    /// and watches `SIGTSTP`: on receiving it, the terminal is put back into
+    /// cooked mode, the session is told to stop forwarding output (see
+    /// [`Self::suspend`]), and the client actually stops itself. Once
+    /// resumed (`SIGCONT`), the terminal goes back into raw mode and the
+    /// session is told to resume with the (possibly changed) size (see
+    /// [`Self::resume`]).
+    ///
+    /// If [`Self::with_detach_key`] was configured, also watches raw stdin
+    /// for that sequence and detaches locally on seeing it, exactly as if
+    /// another client had taken over. Nothing else is read from stdin yet —
+    /// there's no session input-forwarding to hand it to (see
+    /// [`Self::handle_response`]'s `SessionResponse::Command` arm).
+    ///
+    /// Also watches `SIGINT` (see [`Self::handle_ctrl_c`]): rather than
+    /// letting the default handler kill the process before the daemon has
+    /// even read a request, the first `Ctrl-C` sends
+    /// `SessionRequest::Terminate` and keeps the loop running until either
+    /// `SessionResponse::Terminated` arrives or [`TERMINATE_ACK_TIMEOUT`]
+    /// elapses. A second `Ctrl-C` in the meantime skips the wait.
+    pub async fn run(&mut self) -> Result<i32> {
+        use futures_util::StreamExt;
+        use tokio::io::AsyncReadExt;
+
+        #[cfg(not(windows))]
+        let mut signals =
+            Signals::new([signal::SIGWINCH, signal::SIGTSTP, signal::SIGINT])
+                .map_err(anyhow::Error::from)?;
+        #[cfg(windows)]
+        let mut signals: Signals = futures_util::stream::empty();
+
+        // Set once a `SIGINT` has sent `SessionRequest::Terminate`, so `run`
+        // knows to wait out `TERMINATE_ACK_TIMEOUT` for the acknowledgment
+        // rather than letting the process die mid-request (which the daemon
+        // would otherwise see as an unexpected disconnect and log as one). A
+        // second `SIGINT` while this is set skips the wait, in case the
+        // first one's ack really is never coming.
+        let mut terminate_ack_deadline = None;
+
+        let _suppress_verbose_stderr = SuppressVerboseStderr::engage();
+        let mut terminal = RawModeGuard(enter_raw_mode());
+        let mut last_sent_size = None;
+        if let Some(size) = terminal_size() {
+            if self.resize(size.0, size.1).await.is_ok() {
+                last_sent_size = Some(size);
+            }
+        }
+
+        let mut stdin = tokio::io::stdin();
+        let mut stdin_buf = [0u8; 64];
+        let mut awaiting_detach_key = false;
+
+        let mut resize_deadline = None;
+        loop {
+            tokio::select! {
+                result = stdin.read(&mut stdin_buf), if self.detach_key.is_some() => {
+                    let Some(detach_key) = self.detach_key else { unreachable!() };
+                    match result {
+                        Ok(0) | Err(_) => {}
+                        Ok(n) => {
+                            if scan_for_detach_key(&stdin_buf[..n], detach_key, &mut awaiting_detach_key) {
+                                let _ = self.detach().await;
+                                return Ok(exit_code::DETACHED);
+                            }
+                        }
+                    }
+                }
+                response = self.channel.recv::<SessionResponse>() => {
+                    let response = match response {
+                        Ok(Some(response)) => response,
+                        Ok(None) | Err(Error::Closed) | Err(Error::IO(_)) => {
+                            if self.reconnect.is_some() && self.try_reconnect().await {
+                                if let Some(size) = terminal_size() {
+                                    let _ = self.resize(size.0, size.1).await;
+                                    last_sent_size = Some(size);
+                                }
+                                continue;
+                            }
+                            return Ok(exit_code::DAEMON_LOST);
+                        }
+                        Err(Error::Codec(err)) => {
+                            eprintln!("received a malformed message from the daemon, ignoring: {err}");
+                            continue;
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    if let Some(code) = self.handle_response(response).await {
+                        return Ok(code);
+                    }
+                }
+                Some(signal) = signals.next() => {
+                    #[cfg(not(windows))]
+                    if signal == signal::SIGTSTP {
+                        if let Some(state) = &terminal.0 {
+                            leave_raw_mode(state);
+                        }
+                        let _ = self.suspend().await;
+
+                        let _ = signal_hook::low_level::emulate_default_handler(signal::SIGTSTP);
+
+                        terminal.0 = enter_raw_mode();
+                        let size = terminal_size().or(last_sent_size).unwrap_or((0, 0));
+                        if self.resume(size.0, size.1).await.is_ok() {
+                            last_sent_size = Some(size);
+                        }
+                        continue;
+                    }
+                    #[cfg(not(windows))]
+                    if signal == signal::SIGINT {
+                        if let Some(code) = self.handle_ctrl_c(&mut terminate_ack_deadline).await {
+                            return Ok(code);
+                        }
+                        continue;
+                    }
+                    resize_deadline = Some(tokio::time::Instant::now() + RESIZE_DEBOUNCE);
+                }
+                _ = tokio::time::sleep_until(terminate_ack_deadline.unwrap()), if terminate_ack_deadline.is_some() => {
+                    terminate_ack_deadline = None;
+                    eprintln!(
+                        "timed out after {TERMINATE_ACK_TIMEOUT:?} waiting for the daemon to \
+                         acknowledge termination; exiting anyway"
+                    );
+                    return Ok(exit_code::KILLED);
+                }
+                _ = tokio::time::sleep_until(resize_deadline.unwrap()), if resize_deadline.is_some() => {
+                    resize_deadline = None;
+                    if let Some(size) = terminal_size() {
+                        if let Some(size) = coalesce_resize(last_sent_size, size) {
+                            if self.resize(size.0, size.1).await.is_ok() {
+                                last_sent_size = Some(size);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handle a `SIGINT` seen by [`Self::run`]'s signal loop. The first one
+    /// sends `SessionRequest::Terminate` and arms `ack_deadline` so `run`
+    /// keeps going until either `SessionResponse::Terminated` arrives (via
+    /// the ordinary response arm) or `ack_deadline` elapses, rather than
+    /// exiting immediately and leaving the daemon to discover the
+    /// disconnect on its own. A second `SIGINT` while `ack_deadline` is
+    /// already set means the first one's ack isn't coming soon enough for
+    /// the user's liking, so this skips the wait and returns
+    /// [`exit_code::KILLED`] straight away.
+    async fn handle_ctrl_c(&mut self, ack_deadline: &mut Option<tokio::time::Instant>) -> Option<i32> {
+        if ack_deadline.take().is_some() {
+            return Some(exit_code::KILLED);
+        }
+        let _ = self.terminate().await;
+        *ack_deadline = Some(tokio::time::Instant::now() + TERMINATE_ACK_TIMEOUT);
+        None
+    }
+
+    /// Handle a single response from the session, returning `Some(exit_code)`
+    /// once the client should stop running.
+    async fn handle_response(&mut self, response: SessionResponse) -> Option<i32> {
+        if matches!(response, SessionResponse::Err(_)) {
+            self.consecutive_errors += 1;
+        } else {
+            self.consecutive_errors = 0;
+        }
+
+        match response {
+            SessionResponse::Output(bytes) => {
+                let _ = std::io::stdout().write_all(&bytes);
+                let _ = std::io::stdout().flush();
+                None
+            }
+            SessionResponse::Notice(message) => {
+                eprintln!("{message}");
+                None
+            }
+            SessionResponse::Detached => {
+                eprintln!("detached by another client");
+                Some(exit_code::DETACHED)
+            }
+            // A forced kill always reports `KILLED`, regardless of `code`: it's
+            // a daemon-side sentinel, not the hosted process's own exit
+            // status. A graceful termination's `code` is that status (0 for a
+            // clean exit), once a real editor process is wired up to report
+            // one.
+            SessionResponse::Terminated { forced: true, .. } => Some(exit_code::KILLED),
+            SessionResponse::Terminated { forced: false, code } => {
+                Some(if code != 0 { code } else { exit_code::NORMAL })
+            }
+            SessionResponse::Err(message) => {
+                eprintln!("{message}");
+                if self.consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+                    eprintln!(
+                        "{MAX_CONSECUTIVE_ERRORS} consecutive errors from the session, giving up"
+                    );
+                    Some(exit_code::TOO_MANY_ERRORS)
+                } else {
+                    None
+                }
+            }
+            SessionResponse::ConfirmTerminate => {
+                // TODO: prompt the attached editor to save before replying;
+                // for now just acknowledge without saving.
+                eprintln!("session is terminating; save prompt not implemented yet");
+                let _ = self
+                    .channel
+                    .send(&SessionRequest::TerminateAck { save: false })
+                    .await;
+                None
+            }
+            // Normally consumed by `post_attach` before `run` ever starts; a
+            // stray one showing up here (e.g. a second attach handshake from
+            // a takeover racing with this run loop) is informational, not an
+            // error.
+            SessionResponse::AttachAck { sid, seq, .. } => {
+                eprintln!("received an unexpected attach handshake for session {sid} (seq {seq})");
+                None
+            }
+            SessionResponse::Ping => {
+                let _ = self.channel.send(&SessionRequest::Pong).await;
+                None
+            }
+            // TODO: forward to the editor core once one is wired up; for now
+            // just make it visible (see `Request::SendToSession`/`hxc --send`).
+            SessionResponse::Command(command) => {
+                eprintln!("received command: {command}");
+                None
+            }
+        }
+    }
+}
+
+/// A synchronous wrapper around [`Client`] for embedders that just want to
+/// list or kill a session from a plain script, without pulling in and
+/// managing their own tokio runtime. This crate already depends on tokio
+/// unconditionally (the daemon and the rest of `Client` need it); the
+/// `blocking` feature only gates this extra synchronous surface, not tokio
+/// itself.
+#[cfg(feature = "blocking")]
+pub mod blocking {
+    use super::Client;
+    use crate::error::Result;
+    use crate::proto::{SessionId, SessionSummary, SortBy};
+    use std::path::PathBuf;
+
+    /// A blocking handle to a control connection, driving the async
+    /// [`Client`] on a small internal current-thread runtime.
+    pub struct BlockingClient {
+        runtime: tokio::runtime::Runtime,
+        client: Client,
+    }
+
+    impl BlockingClient {
+        pub fn connect(addr: Option<PathBuf>) -> Result<Self> {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            let client = runtime.block_on(Client::connect(addr))?;
+            Ok(Self { runtime, client })
+        }
+
+        pub fn list_sessions(&mut self, sort: SortBy, all: bool) -> Result<Vec<SessionSummary>> {
+            self.runtime.block_on(self.client.list_sessions(sort, all))
+        }
+
+        pub fn kill_session(&mut self, id: SessionId, force: bool) -> Result<()> {
+            self.runtime.block_on(self.client.kill_session(id, force))
+        }
+
+        pub fn stop_server(self) -> Result<(u64, u64, Vec<SessionId>)> {
+            let Self { runtime, client } = self;
+            runtime.block_on(client.stop_server())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::server::{Server, ServerConfig};
+
+        #[test]
+        fn blocking_client_lists_and_kills_a_session_against_a_running_server() {
+            let dir = tempfile::tempdir().unwrap();
+            let socket = dir.path().join("blocking-test.sock");
+            let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+            std::thread::spawn(move || {
+                tokio::runtime::Runtime::new().unwrap().block_on(server.run());
+            });
+            std::thread::sleep(std::time::Duration::from_millis(20));
+
+            // Create the session with the plain async `Client`; only the
+            // list/kill calls under test go through the blocking wrapper.
+            let setup = tokio::runtime::Runtime::new().unwrap();
+            let id = setup.block_on(async {
+                let client = Client::connect(Some(socket.clone())).await.unwrap();
+                client.new_session().await.unwrap().id
+            });
+
+            let mut blocking = BlockingClient::connect(Some(socket)).unwrap();
+            let sessions = blocking.list_sessions(SortBy::Id, false).unwrap();
+            assert_eq!(sessions.iter().map(|s| s.id).collect::<Vec<_>>(), vec![id]);
+
+            blocking.kill_session(id, true).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            let sessions = blocking.list_sessions(SortBy::Id, false).unwrap();
+            assert!(sessions.is_empty());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_seqpacket::{UnixSeqpacket, UnixSeqpacketListener};
+
+    fn dummy_session_client() -> SessionClient {
+        let (a, _b) = UnixSeqpacket::pair().unwrap();
+        SessionClient::new(1, Channel::new(a))
+    }
+
+    #[tokio::test]
+    async fn detached_and_terminated_map_to_distinct_exit_codes() {
+        let mut client = dummy_session_client();
+        assert_eq!(
+            client.handle_response(SessionResponse::Detached).await,
+            Some(exit_code::DETACHED)
+        );
+        assert_eq!(
+            client
+                .handle_response(SessionResponse::Terminated { forced: false, code: 0 })
+                .await,
+            Some(exit_code::NORMAL)
+        );
+        assert_eq!(
+            client
+                .handle_response(SessionResponse::Terminated { forced: true, code: 1 })
+                .await,
+            Some(exit_code::KILLED)
+        );
+        assert_eq!(
+            client.handle_response(SessionResponse::Notice("hi".into())).await,
+            None
+        );
+        assert_eq!(
+            client.handle_response(SessionResponse::Err("bad cwd".into())).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn repeated_errors_give_up_after_the_consecutive_limit() {
+        let mut client = dummy_session_client();
+        for _ in 0..MAX_CONSECUTIVE_ERRORS - 1 {
+            assert_eq!(
+                client.handle_response(SessionResponse::Err("bad cwd".into())).await,
+                None
+            );
+        }
+        assert_eq!(
+            client.handle_response(SessionResponse::Err("bad cwd".into())).await,
+            Some(exit_code::TOO_MANY_ERRORS)
+        );
+    }
+
+    #[tokio::test]
+    async fn a_successful_response_resets_the_consecutive_error_count() {
+        let mut client = dummy_session_client();
+        for _ in 0..MAX_CONSECUTIVE_ERRORS - 1 {
+            assert_eq!(
+                client.handle_response(SessionResponse::Err("bad cwd".into())).await,
+                None
+            );
+        }
+        assert_eq!(
+            client.handle_response(SessionResponse::Notice("ok".into())).await,
+            None
+        );
+        for _ in 0..MAX_CONSECUTIVE_ERRORS - 1 {
+            assert_eq!(
+                client.handle_response(SessionResponse::Err("bad cwd".into())).await,
+                None
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn confirm_terminate_replies_with_a_terminate_ack() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = SessionClient::new(1, Channel::new(a));
+        let mut daemon_side = Channel::new(b);
+
+        let code = client.handle_response(SessionResponse::ConfirmTerminate).await;
+        assert_eq!(code, None);
+
+        let ack = daemon_side
+            .recv::<SessionRequest>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(ack, SessionRequest::TerminateAck { save: false }));
+    }
+
+    #[tokio::test]
+    async fn ctrl_c_sends_terminate_and_arms_the_ack_deadline() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = SessionClient::new(1, Channel::new(a));
+        let mut daemon_side = Channel::new(b);
+        let mut deadline = None;
+
+        let code = client.handle_ctrl_c(&mut deadline).await;
+        assert_eq!(code, None);
+        assert!(deadline.is_some());
+
+        let request = daemon_side
+            .recv::<SessionRequest>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(request, SessionRequest::Terminate { code: 0 }));
+    }
+
+    #[tokio::test]
+    async fn a_second_ctrl_c_skips_the_ack_wait() {
+        let mut client = dummy_session_client();
+        let mut deadline = Some(tokio::time::Instant::now() + TERMINATE_ACK_TIMEOUT);
+
+        let code = client.handle_ctrl_c(&mut deadline).await;
+        assert_eq!(code, Some(exit_code::KILLED));
+        assert!(deadline.is_none());
+    }
+
+    #[tokio::test]
+    async fn run_exits_once_the_late_terminated_ack_arrives_after_ctrl_c() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = SessionClient::new(1, Channel::new(a));
+        let mut daemon_side = Channel::new(b);
+
+        let mut deadline = None;
+        assert_eq!(client.handle_ctrl_c(&mut deadline).await, None);
+        let _terminate = daemon_side
+            .recv::<SessionRequest>()
+            .await
+            .unwrap()
+            .unwrap();
+
+        // The daemon takes its time acking, but replies well within
+        // `TERMINATE_ACK_TIMEOUT`; `run` should pick up the response and
+        // exit normally rather than timing out and reporting `KILLED`.
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let _ = daemon_side
+                .send(&SessionResponse::Terminated { forced: false, code: 0 })
+                .await;
+        });
+
+        let code = tokio::time::timeout(TERMINATE_ACK_TIMEOUT, client.run())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(code, exit_code::NORMAL);
+    }
+
+    #[tokio::test]
+    async fn a_keepalive_ping_is_answered_with_a_pong() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = SessionClient::new(1, Channel::new(a));
+        let mut daemon_side = Channel::new(b);
+
+        let code = client.handle_response(SessionResponse::Ping).await;
+        assert_eq!(code, None);
+
+        let reply = daemon_side
+            .recv::<SessionRequest>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(reply, SessionRequest::Pong));
+    }
+
+    #[tokio::test]
+    async fn unexpected_disconnect_reports_daemon_lost() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = SessionClient::new(1, Channel::new(a));
+        drop(b);
+
+        let code = client.run().await.unwrap();
+        assert_eq!(code, exit_code::DAEMON_LOST);
+    }
+
+    #[tokio::test]
+    async fn reconnect_reattaches_after_the_listener_is_recreated() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("reconnect.sock");
+
+        // The "daemon" the client starts out attached to: accept one
+        // connection, then vanish without a word, as if the process had
+        // just been killed out from under the client.
+        let listener = UnixSeqpacketListener::bind(&socket).unwrap();
+        let client_conn = UnixSeqpacket::connect(&socket).await.unwrap();
+        let daemon_conn = listener.accept().await.unwrap();
+        drop(daemon_conn);
+        drop(listener);
+        // The stale socket file has to go before a second listener can bind
+        // the same path, same as `Server::new` does on startup.
+        std::fs::remove_file(&socket).unwrap();
+
+        let mut session = SessionClient::new(1, Channel::new(client_conn)).with_reconnect(
+            Some(socket.clone()),
+            None,
+            Duration::from_secs(2),
+        );
+        let run_task = tokio::spawn(async move {
+            let result = session.run().await;
+            (result, session)
+        });
+
+        // Stand up the "new" daemon only after giving the client a moment to
+        // notice the first one is gone and start retrying.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let listener = UnixSeqpacketListener::bind(&socket).unwrap();
+        let daemon_conn = listener.accept().await.unwrap();
+        let mut daemon_side = Channel::new(daemon_conn);
+
+        let request = daemon_side.recv::<Request>().await.unwrap().unwrap();
+        assert!(matches!(
+            request,
+            Request::AttachSession { id: 1, takeover: false, passphrase: None }
+        ));
+        daemon_side
+            .send(&Response::Attached { id: 1, alias: None })
+            .await
+            .unwrap();
+        daemon_side
+            .send(&SessionResponse::AttachAck {
+                sid: 1,
+                alias: None,
+                cwd: None,
+                files: Vec::new(),
+                size: None,
+                seq: 1,
+            })
+            .await
+            .unwrap();
+        daemon_side
+            .send(&SessionResponse::Terminated { forced: false, code: 0 })
+            .await
+            .unwrap();
+
+        let (code, _session) = tokio::time::timeout(Duration::from_secs(2), run_task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(code.unwrap(), exit_code::NORMAL);
+    }
+
+    #[tokio::test]
+    async fn a_garbage_message_is_skipped_rather_than_ending_the_session() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = SessionClient::new(1, Channel::new(a));
+
+        // A datagram that isn't valid bincode for any `SessionResponse`, sent
+        // on the raw socket since `Channel::send` only ever encodes valid
+        // messages.
+        b.send(&[0xff; 16]).await.unwrap();
+        let mut daemon_side = Channel::new(b);
+        daemon_side
+            .send(&SessionResponse::Terminated { forced: false, code: 0 })
+            .await
+            .unwrap();
+
+        let code = client.run().await.unwrap();
+        assert_eq!(code, exit_code::NORMAL);
+    }
+
+    #[tokio::test]
+    async fn a_nonzero_graceful_exit_code_is_reported_as_is() {
+        let mut client = dummy_session_client();
+        assert_eq!(
+            client
+                .handle_response(SessionResponse::Terminated { forced: false, code: 17 })
+                .await,
+            Some(17)
+        );
+    }
+
+    #[test]
+    fn coalesce_resize_sends_the_first_observed_size() {
+        assert_eq!(coalesce_resize(None, (40, 120)), Some((40, 120)));
+    }
+
+    #[test]
+    fn coalesce_resize_drops_a_size_unchanged_since_the_last_send() {
+        assert_eq!(coalesce_resize(Some((40, 120)), (40, 120)), None);
+    }
+
+    #[test]
+    fn coalesce_resize_forwards_a_genuinely_different_size() {
+        assert_eq!(coalesce_resize(Some((40, 120)), (50, 120)), Some((50, 120)));
+    }
+
+    #[test]
+    fn detach_key_from_str_parses_the_tmux_style_default() {
+        assert_eq!("C-b d".parse(), Ok(DetachKey::DEFAULT));
+    }
+
+    #[test]
+    fn detach_key_from_str_rejects_a_malformed_spec() {
+        assert!("C-b".parse::<DetachKey>().is_err());
+        assert!("b d".parse::<DetachKey>().is_err());
+        assert!("C-bb d".parse::<DetachKey>().is_err());
+        assert!("C-b dd".parse::<DetachKey>().is_err());
+        assert!("C-1 d".parse::<DetachKey>().is_err());
+    }
+
+    #[test]
+    fn scan_for_detach_key_matches_the_sequence_within_a_single_chunk() {
+        let mut awaiting = false;
+        let chunk = [b'x', DetachKey::DEFAULT.prefix, b'd', b'y'];
+        assert!(scan_for_detach_key(&chunk, DetachKey::DEFAULT, &mut awaiting));
+    }
+
+    #[test]
+    fn scan_for_detach_key_matches_a_sequence_split_across_two_reads() {
+        let mut awaiting = false;
+        assert!(!scan_for_detach_key(&[DetachKey::DEFAULT.prefix], DetachKey::DEFAULT, &mut awaiting));
+        assert!(awaiting);
+        assert!(scan_for_detach_key(b"d", DetachKey::DEFAULT, &mut awaiting));
+    }
+
+    #[test]
+    fn scan_for_detach_key_resets_after_a_prefix_not_followed_by_the_key() {
+        let mut awaiting = false;
+        assert!(!scan_for_detach_key(
+            &[DetachKey::DEFAULT.prefix, b'x'],
+            DetachKey::DEFAULT,
+            &mut awaiting
+        ));
+        assert!(!awaiting);
+    }
+
+    #[test]
+    fn session_env_keeps_only_allowlisted_vars_in_allowlist_order() {
+        let vars = vec![
+            ("SECRET_TOKEN".to_string(), "shh".to_string()),
+            ("TERM".to_string(), "xterm".to_string()),
+            ("PATH".to_string(), "/usr/bin".to_string()),
+        ];
+        assert_eq!(
+            filter_session_env(vars),
+            vec![
+                ("PATH".to_string(), "/usr/bin".to_string()),
+                ("TERM".to_string(), "xterm".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn classify_error_recognizes_known_messages() {
+        assert_eq!(
+            Client::classify_error("no such session".into()),
+            ClientError::SessionNotFound
+        );
+        assert_eq!(
+            Client::classify_error("session is occupied".into()),
+            ClientError::Occupied
+        );
+        assert_eq!(
+            Client::classify_error("something else went wrong".into()),
+            ClientError::Server("something else went wrong".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn with_max_message_size_raises_the_underlying_channels_cap() {
+        let (a, _a_client) = UnixSeqpacket::pair().unwrap();
+        let client = Client { channel: Some(Channel::new(a)), timeout: None }
+            .with_max_message_size(64 * 1024 * 1024);
+        assert_eq!(
+            client.channel.unwrap().max_message_size(),
+            64 * 1024 * 1024
+        );
+    }
+
+    #[tokio::test]
+    async fn kill_session_maps_a_missing_session_to_session_not_found() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = Client { channel: Some(Channel::new(a)), timeout: None };
+        let mut daemon_side = Channel::new(b);
+
+        let (result, _) = tokio::join!(client.kill_session(1, false), async {
+            let request = daemon_side.recv::<Request>().await.unwrap().unwrap();
+            assert!(matches!(request, Request::KillSession { id: 1, force: false }));
+            daemon_side
+                .send(&Response::Err("no such session".into()))
+                .await
+                .unwrap();
+        });
+
+        assert!(matches!(
+            result,
+            Err(Error::Session(ClientError::SessionNotFound))
+        ));
+    }
+
+    #[tokio::test]
+    async fn kill_session_wait_blocks_until_the_session_actually_ends() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = Client { channel: Some(Channel::new(a)), timeout: None };
+        let mut daemon_side = Channel::new(b);
+
+        let (result, _) = tokio::join!(
+            client.kill_session_wait(1, false, Duration::from_secs(5)),
+            async {
+                let request = daemon_side.recv::<Request>().await.unwrap().unwrap();
+                assert!(matches!(request, Request::KillSession { id: 1, force: false }));
+                daemon_side.send(&Response::Ok).await.unwrap();
+
+                let request = daemon_side.recv::<Request>().await.unwrap().unwrap();
+                assert!(matches!(request, Request::WaitSession(1)));
+                daemon_side
+                    .send(&Response::SessionEnded { code: 0, forced: false })
+                    .await
+                    .unwrap();
+            }
+        );
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn kill_session_wait_treats_a_session_already_reaped_as_gone() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = Client { channel: Some(Channel::new(a)), timeout: None };
+        let mut daemon_side = Channel::new(b);
+
+        let (result, _) = tokio::join!(
+            client.kill_session_wait(1, false, Duration::from_secs(5)),
+            async {
+                daemon_side.recv::<Request>().await.unwrap().unwrap();
+                daemon_side.send(&Response::Ok).await.unwrap();
+
+                daemon_side.recv::<Request>().await.unwrap().unwrap();
+                daemon_side
+                    .send(&Response::Err("no such session".into()))
+                    .await
+                    .unwrap();
+            }
+        );
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_to_session_delivers_the_payload_and_reports_success() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = Client { channel: Some(Channel::new(a)), timeout: None };
+        let mut daemon_side = Channel::new(b);
+
+        let (result, _) = tokio::join!(
+            client.send_to_session("work".to_string(), ":write-all".to_string()),
+            async {
+                let request = daemon_side.recv::<Request>().await.unwrap().unwrap();
+                assert!(matches!(
+                    request,
+                    Request::SendToSession { ref sid_or_alias, ref payload }
+                        if sid_or_alias == "work" && payload == ":write-all"
+                ));
+                daemon_side.send(&Response::Ok).await.unwrap();
+            }
+        );
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_to_session_maps_a_detached_session_to_a_server_error() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = Client { channel: Some(Channel::new(a)), timeout: None };
+        let mut daemon_side = Channel::new(b);
+
+        let (result, _) = tokio::join!(
+            client.send_to_session("work".to_string(), ":write-all".to_string()),
+            async {
+                let _ = daemon_side.recv::<Request>().await.unwrap().unwrap();
+                daemon_side
+                    .send(&Response::Err("session is detached".into()))
+                    .await
+                    .unwrap();
+            }
+        );
+
+        assert!(matches!(
+            result,
+            Err(Error::Session(ClientError::Server(ref m))) if m == "session is detached"
+        ));
+    }
+
+    #[tokio::test]
+    async fn connect_with_retry_succeeds_once_the_socket_appears() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("late.sock");
+
+        let bind_path = socket.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let listener = tokio_seqpacket::UnixSeqpacketListener::bind(&bind_path).unwrap();
+            let _ = listener.accept().await;
+        });
+
+        let client = Client::connect_with_retry(Some(socket), Duration::from_secs(2)).await;
+        assert!(client.is_ok());
+    }
+
+    #[tokio::test]
+    async fn connect_with_retry_times_out_if_the_socket_never_appears() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("never.sock");
+
+        let result = Client::connect_with_retry(Some(socket), Duration::from_millis(200)).await;
+        assert!(matches!(result, Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn terminal_title_escape_wraps_the_alias_in_an_osc_2_sequence() {
+        assert_eq!(terminal_title_escape("scratch"), "\x1b]2;scratch\x07");
+    }
+
+    #[test]
+    fn describe_attach_includes_only_the_metadata_that_is_present() {
+        assert_eq!(describe_attach(1, None, None, &[]), "session 1");
+        assert_eq!(
+            describe_attach(1, Some("scratch"), None, &[]),
+            "session 1 (scratch)"
+        );
+        assert_eq!(
+            describe_attach(
+                1,
+                Some("scratch"),
+                Some("/home/alice"),
+                &["a.rs".to_string(), "b.rs".to_string()]
+            ),
+            "session 1 (scratch): cwd /home/alice, 2 file(s) open"
+        );
+    }
+
+    #[tokio::test]
+    async fn post_attach_consumes_the_ack_and_prints_the_terminal_title() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = SessionClient::new(1, Channel::new(a));
+        let mut daemon_side = Channel::new(b);
+
+        daemon_side
+            .send(&SessionResponse::AttachAck {
+                sid: 1,
+                alias: Some("scratch".into()),
+                cwd: Some("/home/alice".into()),
+                files: vec!["a.rs".into()],
+                size: None,
+                seq: 1,
+            })
+            .await
+            .unwrap();
+
+        client.post_attach().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn post_attach_times_out_if_no_ack_ever_arrives() {
+        let (a, _b) = UnixSeqpacket::pair().unwrap();
+        let mut client = SessionClient::new(1, Channel::new(a));
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(2),
+            client.post_attach_with_timeout(Duration::from_millis(50)),
+        )
+        .await
+        .expect("post_attach should give up on its own rather than hang forever");
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn post_attach_rejects_an_unexpected_first_message() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = SessionClient::new(1, Channel::new(a));
+        let mut daemon_side = Channel::new(b);
+
+        daemon_side
+            .send(&SessionResponse::Notice("hi".into()))
+            .await
+            .unwrap();
+
+        assert!(client.post_attach().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn close_makes_the_daemon_see_a_clean_disconnect() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let client = Client { channel: Some(Channel::new(a)), timeout: None };
+        let mut daemon_side = Channel::new(b);
+
+        client.close().unwrap();
+
+        // A shut-down channel reads as the peer having closed the
+        // connection, exactly like an ordinary drop, but without the
+        // daemon having to wait to notice this `Client` was actually gone.
+        assert!(daemon_side.recv::<Request>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn dropping_a_client_also_shuts_the_channel_down() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let client = Client { channel: Some(Channel::new(a)), timeout: None };
+        let mut daemon_side = Channel::new(b);
+
+        drop(client);
+
+        assert!(daemon_side.recv::<Request>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_channel_handed_off_to_a_session_client_survives_the_original_drop() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let client = Client { channel: Some(Channel::new(a)), timeout: None };
+        let mut daemon_side = Channel::new(b);
+
+        // `new_session`'s `SessionClient` now owns the channel; dropping the
+        // now-empty `Client` it came from must not shut down the connection
+        // out from under it.
+        let (session_result, _) = tokio::join!(client.new_session(), async {
+            let request = daemon_side.recv::<Request>().await.unwrap().unwrap();
+            assert!(matches!(request, Request::NewSession { .. }));
+            daemon_side.send(&Response::SessionCreated { id: 1 }).await.unwrap();
+        });
+        let session = session_result.unwrap();
+        drop(session);
+
+        assert!(daemon_side.recv::<Request>().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn pipeline_sends_a_batch_and_collects_responses_in_order() {
+        let (a, b) = UnixSeqpacket::pair().unwrap();
+        let mut client = Client { channel: Some(Channel::new(a)), timeout: None };
+        let mut daemon_side = Channel::new(b);
+
+        let requests = vec![
+            Request::TagSession { id: 1, add: vec!["work".into()], remove: Vec::new() },
+            Request::ListSessions { sort: SortBy::Id, all: false, tag: None },
+        ];
+
+        let (result, _) = tokio::join!(client.pipeline(requests), async {
+            let first = daemon_side.recv::<Request>().await.unwrap().unwrap();
+            assert!(matches!(first, Request::TagSession { id: 1, .. }));
+            daemon_side.send(&Response::Ok).await.unwrap();
+
+            let second = daemon_side.recv::<Request>().await.unwrap().unwrap();
+            assert!(matches!(second, Request::ListSessions { .. }));
+            daemon_side.send(&Response::Sessions(Vec::new())).await.unwrap();
+        });
+
+        let responses = result.unwrap();
+        assert!(matches!(responses[0], Response::Ok));
+        assert!(matches!(responses[1], Response::Sessions(ref s) if s.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn pipeline_rejects_a_batch_containing_an_attaching_request() {
+        let (a, _b) = UnixSeqpacket::pair().unwrap();
+        let mut client = Client { channel: Some(Channel::new(a)), timeout: None };
+
+        let requests = vec![
+            Request::ListSessions { sort: SortBy::Id, all: false, tag: None },
+            Request::AttachLast { takeover: false },
+        ];
+
+        assert!(client.pipeline(requests).await.is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! On-disk daemon configuration.
+//!
+//! [`Config`] holds only knobs that are safe to change on a running daemon:
+//! the set [`crate::server::Server`] re-reads on `SIGHUP`. [`FileConfig`] is
+//! read once at startup and also carries startup-only settings (the socket
+//! path, ...); a reload doesn't revisit those.
+
+use crate::error::Result;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Hot-reloadable daemon settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Caps the number of concurrently running sessions. `None` (the
+    /// default) means unlimited.
+    pub max_sessions: Option<usize>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+}
+
+/// The full on-disk shape of `daemon.toml`, read once at `hxd` startup (see
+/// `hxd --config`/`hxd --check`). A field here and not in [`Config`] can
+/// only take effect by restarting the daemon; [`Config`] is the narrower
+/// subset that's also safe to pick up again on `SIGHUP`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FileConfig {
+    /// Same as `hxd --socket`; the flag always overrides this.
+    pub socket: Option<String>,
+    /// Same as [`Config::max_sessions`].
+    pub max_sessions: Option<usize>,
+    /// Same as `ServerConfig::max_attached_peers`.
+    pub max_attached_peers: Option<usize>,
+    /// Same as `ServerConfig::mirror_queue_capacity`.
+    pub mirror_queue_capacity: Option<usize>,
+    /// One of `"warn"`, `"info"`, `"debug"`, `"trace"`; maps onto the same
+    /// scale as repeating `-v`. A CLI `-v` always overrides this.
+    pub log_level: Option<String>,
+    /// Same as `ServerConfig::on_create`.
+    pub on_create: Option<String>,
+}
+
+/// Keys [`FileConfig`] understands, for the unknown-key warning in
+/// [`FileConfig::load`].
+const KNOWN_KEYS: &[&str] = &[
+    "socket",
+    "max_sessions",
+    "max_attached_peers",
+    "mirror_queue_capacity",
+    "log_level",
+    "on_create",
+];
+
+impl FileConfig {
+    /// Unknown keys are warned about rather than rejected outright, so a
+    /// typo or a field from a newer `hxd` doesn't stop an older one from
+    /// starting at all.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let value: toml::Value = toml::from_str(&text)?;
+        if let Some(table) = value.as_table() {
+            let unknown: Vec<&str> = table
+                .keys()
+                .map(String::as_str)
+                .filter(|key| !KNOWN_KEYS.contains(key))
+                .collect();
+            if !unknown.is_empty() {
+                log::warn!(
+                    "{}: ignoring unknown config key(s): {}",
+                    path.display(),
+                    unknown.join(", ")
+                );
+            }
+        }
+        Ok(value.try_into()?)
+    }
+
+    /// Maps [`Self::log_level`] onto the same 0..=3 scale as repeated `-v`
+    /// flags (see `logging::level_filter`). `None`, or anything not
+    /// recognized, leaves the verbosity unset rather than guessing.
+    pub fn verbosity(&self) -> Option<u64> {
+        match self.log_level.as_deref() {
+            Some("warn") => Some(0),
+            Some("info") => Some(1),
+            Some("debug") => Some(2),
+            Some("trace") => Some(3),
+            Some(other) => {
+                log::warn!("ignoring unrecognized log_level {other:?} in daemon.toml");
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_config_defaults_to_every_field_unset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.toml");
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(FileConfig::load(&path).unwrap(), FileConfig::default());
+    }
+
+    #[test]
+    fn file_config_parses_every_known_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.toml");
+        std::fs::write(
+            &path,
+            "socket = \"/tmp/hxd.sock\"\n\
+             max_sessions = 4\n\
+             max_attached_peers = 2\n\
+             mirror_queue_capacity = 16\n\
+             log_level = \"debug\"\n\
+             on_create = \"echo {sid} >> /tmp/sessions\"\n",
+        )
+        .unwrap();
+
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.socket.as_deref(), Some("/tmp/hxd.sock"));
+        assert_eq!(config.max_sessions, Some(4));
+        assert_eq!(config.max_attached_peers, Some(2));
+        assert_eq!(config.mirror_queue_capacity, Some(16));
+        assert_eq!(config.verbosity(), Some(2));
+        assert_eq!(config.on_create.as_deref(), Some("echo {sid} >> /tmp/sessions"));
+    }
+
+    #[test]
+    fn file_config_tolerates_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.toml");
+        std::fs::write(&path, "max_sessions = 4\ndetached_ttl = 60\n").unwrap();
+
+        let config = FileConfig::load(&path).unwrap();
+        assert_eq!(config.max_sessions, Some(4));
+    }
+
+    #[test]
+    fn file_config_verbosity_is_unset_for_an_unrecognized_log_level() {
+        let config = FileConfig {
+            log_level: Some("verbose".to_string()),
+            ..FileConfig::default()
+        };
+        assert_eq!(config.verbosity(), None);
+    }
+}
@@ -0,0 +1,120 @@
+//! A human-friendly duration parser shared by `hxc` flags that accept a
+//! duration (e.g. a session idle-timeout override), so each one doesn't
+//! reinvent `30m`/`2h` parsing on its own.
+
+use std::time::Duration;
+
+/// Parse a duration like `30s`, `5m`, `2h`, `1d`, a bare number of seconds
+/// (`90`), or a concatenation of several units (`1h30m`). Each unit may
+/// appear at most once, in any order. Rejects an empty input, a segment
+/// with no digits, an unrecognized suffix, and a repeated unit, all with an
+/// error naming the offending input.
+pub fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+    if value.is_empty() {
+        anyhow::bail!("invalid duration: expected e.g. 30s, 5m, 2h, 1d, or a plain number of seconds");
+    }
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    // Index: 0=s, 1=m, 2=h, 3=d. Tracks which units this input has already
+    // used, so `10m5m` is rejected as ambiguous rather than silently summed.
+    let mut seen = [false; 4];
+    let mut total_seconds: u64 = 0;
+    let mut digits = String::new();
+    for ch in value.chars() {
+        if ch.is_ascii_digit() {
+            digits.push(ch);
+            continue;
+        }
+        if digits.is_empty() {
+            anyhow::bail!("invalid duration: {value:?} (expected e.g. 30s, 5m, 2h, or 1d)");
+        }
+        let count: u64 = digits
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid duration: {value:?}"))?;
+        digits.clear();
+
+        let (scale, index) = match ch {
+            's' => (1, 0),
+            'm' => (60, 1),
+            'h' => (60 * 60, 2),
+            'd' => (60 * 60 * 24, 3),
+            other => anyhow::bail!(
+                "invalid duration: {value:?} (unrecognized unit {other:?}; expected s, m, h, or d)"
+            ),
+        };
+        if seen[index] {
+            anyhow::bail!("invalid duration: {value:?} (unit {ch:?} repeated)");
+        }
+        seen[index] = true;
+
+        let segment_seconds = count
+            .checked_mul(scale)
+            .ok_or_else(|| anyhow::anyhow!("invalid duration: {value:?} (too large)"))?;
+        total_seconds = total_seconds
+            .checked_add(segment_seconds)
+            .ok_or_else(|| anyhow::anyhow!("invalid duration: {value:?} (too large)"))?;
+    }
+    if !digits.is_empty() {
+        anyhow::bail!("invalid duration: {value:?} (trailing digits with no unit)");
+    }
+
+    Ok(Duration::from_secs(total_seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_accepts_a_plain_number_as_seconds() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn parse_duration_accepts_each_suffix() {
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(5 * 60));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(2 * 60 * 60));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(24 * 60 * 60));
+    }
+
+    #[test]
+    fn parse_duration_accepts_combined_values() {
+        assert_eq!(
+            parse_duration("1h30m").unwrap(),
+            Duration::from_secs(60 * 60 + 30 * 60)
+        );
+        assert_eq!(
+            parse_duration("1d2h3m4s").unwrap(),
+            Duration::from_secs(24 * 60 * 60 + 2 * 60 * 60 + 3 * 60 + 4)
+        );
+    }
+
+    #[test]
+    fn parse_duration_rejects_an_empty_input() {
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn parse_duration_rejects_an_unknown_suffix() {
+        assert!(parse_duration("30x").is_err());
+    }
+
+    #[test]
+    fn parse_duration_rejects_a_suffix_with_no_digits() {
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("1hm").is_err());
+    }
+
+    #[test]
+    fn parse_duration_rejects_trailing_digits_with_no_unit() {
+        assert!(parse_duration("1h30").is_err());
+    }
+
+    #[test]
+    fn parse_duration_rejects_a_repeated_unit() {
+        assert!(parse_duration("10m5m").is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("IO Error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("failed to encode/decode message: {0}")]
+    Codec(#[from] bincode::Error),
+    #[error("failed to parse config: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error("channel closed")]
+    Closed,
+    #[error("message too large: got {got} bytes, max {max} bytes")]
+    MessageTooLarge { got: usize, max: usize },
+    #[error("short write: sent {sent} of {expected} bytes in one datagram")]
+    ShortWrite { sent: usize, expected: usize },
+    #[error("timed out waiting for a response")]
+    Timeout,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+    #[error(transparent)]
+    Session(#[from] ClientError),
+}
+
+/// A typed error from a session-related [`crate::client::Client`] call, so a
+/// script can tell "the session doesn't exist" apart from an opaque server
+/// error instead of matching on a message string.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    #[error("no such session")]
+    SessionNotFound,
+    #[error("session is occupied")]
+    Occupied,
+    #[error("no detached sessions")]
+    NoDetachedSessions,
+    #[error("incorrect or missing passphrase")]
+    WrongPassphrase,
+    #[error("{0}")]
+    Server(String),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
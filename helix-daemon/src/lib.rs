@@ -0,0 +1,14 @@
+pub mod auth;
+pub mod channel;
+pub mod client;
+pub mod config;
+pub mod duration;
+pub mod error;
+pub mod logging;
+pub mod proto;
+pub mod retry;
+pub mod server;
+pub mod session;
+pub mod socket;
+
+pub use error::{Error, Result};
@@ -0,0 +1,371 @@
+//! Shared `log`/`fern` setup for `hxc` and `hxd`'s `-v`/`-vv`/`-vvv` flags.
+//!
+//! A bare `-v` writes a compact, undated line straight to stderr (colored by
+//! level if stderr is a terminal), for quick debug output while reproducing
+//! a problem interactively. `-v FILE` instead writes the fuller, timestamped
+//! format to `FILE`, for a log meant to be tailed or kept around.
+//!
+//! `server::Server` and `session::Session` also open `tracing` spans around
+//! a connection's/session's lifecycle, so a single one can be grepped out of
+//! the log. Rather than replace this module's `fern::Dispatch` with a
+//! `tracing::Subscriber` (which would need its own filtering/coloring/file
+//! setup redone from scratch), `tracing` is pulled in with only its `log`
+//! feature enabled: that makes every `tracing` event and span emit through
+//! the `log` facade instead, so it lands in the sinks set up below for free.
+//! This is the opposite direction from the `tracing-log` crate, which
+//! forwards plain `log` calls into an already-installed `tracing`
+//! `Subscriber` — there isn't one here.
+
+use crate::error::Result;
+use fern::colors::{Color, ColoredLevelConfig};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// While set, a foreground terminal UI is live (see
+/// [`suppress_verbose_stderr`]) and the stderr sink drops everything below
+/// `Warn`, so `-v`'s extra output doesn't interleave with it. Has no effect
+/// on the file sink: a log file is never displayed live, so there's nothing
+/// for verbose lines to interleave with.
+static SUPPRESS_BELOW_WARN: AtomicBool = AtomicBool::new(false);
+
+/// Suppress (`true`) or restore (`false`) verbose stderr output below
+/// `Warn`, e.g. around [`crate::client::SessionClient::run`]'s raw-mode
+/// terminal UI.
+pub fn suppress_verbose_stderr(suppress: bool) {
+    SUPPRESS_BELOW_WARN.store(suppress, Ordering::Relaxed);
+}
+
+/// The `log::LevelFilter` implied by a `-v` count (0 = `Warn`, 1 = `Info`,
+/// 2 = `Debug`, 3+ = `Trace`), shared by [`setup`] and [`set_level`] so the
+/// two scales can never drift apart.
+fn level_filter(verbosity: u64) -> log::LevelFilter {
+    match verbosity {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _3_or_more => log::LevelFilter::Trace,
+    }
+}
+
+/// Raise or lower the active log level to the one implied by `verbosity`,
+/// without touching where log lines go (see [`setup`]). `log::set_max_level`
+/// is itself a plain atomic store the `log` crate already exposes for
+/// exactly this, so there's no reload handle to thread through
+/// `Request::SetLogLevel` beyond this call.
+pub fn set_level(verbosity: u8) {
+    log::set_max_level(level_filter(verbosity as u64));
+}
+
+/// The verbosity `-v`/`-vv`/`-vvv` each add, shared by `hxc` and `hxd`'s flag
+/// parsing so the two scales can't drift apart again. `0` for anything else;
+/// callers only reach this after already matching one of the three flags.
+pub fn verbosity_for_flag(flag: &str) -> u64 {
+    match flag {
+        "-v" => 1,
+        "-vv" => 2,
+        "-vvv" => 3,
+        _ => 0,
+    }
+}
+
+/// `true` if `token` looks like a flag rather than a value the flag before
+/// it should consume — e.g. so `-v --socket foo` doesn't swallow `--socket`
+/// as `-v`'s log filename. Shared by `hxc` and `hxd`'s argument parsing
+/// generally, not just `-v`, since both need the same rule for any
+/// optional trailing value.
+pub fn looks_like_a_flag(token: &str) -> bool {
+    token.starts_with('-') && token != "-"
+}
+
+/// Past this size, the file sink [`setup`] installs rotates rather than
+/// growing without bound — see [`RotatingFile`].
+pub const DEFAULT_MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// How many rotated generations (`.1`, `.2`, `.3`) [`RotatingFile`] keeps
+/// alongside the live file before the oldest is discarded.
+const ROTATED_GENERATIONS: u32 = 3;
+
+/// Configure `log` to print at the level implied by `verbosity` (0 = `Warn`,
+/// 1 = `Info`, 2 = `Debug`, 3+ = `Trace`), to `file` if given or stderr
+/// otherwise. A file sink rotates once it passes `max_bytes` (see
+/// [`RotatingFile`]); callers without an opinion should pass
+/// [`DEFAULT_MAX_LOG_BYTES`].
+///
+/// `also_stdout` additionally chains a second, shorter-format sink to
+/// stdout at the same level, for `hxd --foreground` (see
+/// [`stdout_dispatch`]): a file sink alone means nothing is visible in the
+/// terminal a foreground `hxd` was started from.
+pub fn setup(verbosity: u64, file: Option<&Path>, max_bytes: u64, also_stdout: bool) -> Result<()> {
+    let level = level_filter(verbosity);
+
+    let mut dispatch = fern::Dispatch::new().level(level);
+    dispatch = match file {
+        Some(path) => dispatch.chain(file_dispatch(path, max_bytes)?),
+        None => dispatch.chain(stderr_dispatch()),
+    };
+    if also_stdout {
+        dispatch = dispatch.chain(stdout_dispatch());
+    }
+
+    dispatch.apply().map_err(|err| anyhow::anyhow!(err))?;
+    Ok(())
+}
+
+/// The file sink: full timestamp, target, and level, for a log meant to be
+/// tailed or kept around (see [`RotatingFile`]).
+fn file_dispatch(path: &Path, max_bytes: u64) -> Result<fern::Dispatch> {
+    if let Some(parent) = path.parent() {
+        crate::retry::retry_eintr(|| std::fs::create_dir_all(parent))?;
+    }
+    Ok(fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{} {} [{}] {}",
+                chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
+                record.target(),
+                record.level(),
+                message
+            ))
+        })
+        .chain(Box::new(RotatingFile::open(path, max_bytes)?) as Box<dyn Write + Send>))
+}
+
+/// The bare `-v` sink: a compact, undated line straight to stderr (colored
+/// by level if stderr is a terminal), suppressed below `Warn` while a
+/// foreground terminal UI is live (see [`suppress_verbose_stderr`]).
+fn stderr_dispatch() -> fern::Dispatch {
+    let colors = ColoredLevelConfig::new()
+        .warn(Color::Yellow)
+        .error(Color::Red)
+        .info(Color::Green)
+        .debug(Color::Blue)
+        .trace(Color::Magenta);
+    let colored = stderr_is_tty();
+    fern::Dispatch::new()
+        .filter(|metadata| {
+            metadata.level() <= log::Level::Warn || !SUPPRESS_BELOW_WARN.load(Ordering::Relaxed)
+        })
+        .format(move |out, message, record| {
+            if colored {
+                out.finish(format_args!("{} {message}", colors.color(record.level())))
+            } else {
+                out.finish(format_args!("{} {message}", record.level()))
+            }
+        })
+        .chain(std::io::stderr())
+}
+
+/// `hxd --foreground`'s extra stdout sink (see [`setup`]'s `also_stdout`):
+/// a shorter line than the file sink's (time, level, message; no target),
+/// for a quick glance in the terminal rather than a log meant to be kept.
+fn stdout_dispatch() -> fern::Dispatch {
+    fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!("{}", format_stdout_line(record.level(), message)))
+        })
+        .chain(std::io::stdout())
+}
+
+/// The line [`stdout_dispatch`] prints for one record: just enough to
+/// follow along in a terminal, unlike the file sink's fuller, tailable
+/// format (see [`file_dispatch`]).
+fn format_stdout_line(level: log::Level, message: &std::fmt::Arguments) -> String {
+    format!("{} {level} {message}", chrono::Local::now().format("%H:%M:%S"))
+}
+
+#[cfg(not(windows))]
+fn stderr_is_tty() -> bool {
+    use std::os::unix::io::AsRawFd;
+    // Safety: `isatty` only reads the fd's properties; it never touches the
+    // stream's buffer or lifetime.
+    unsafe { libc::isatty(std::io::stderr().as_raw_fd()) != 0 }
+}
+
+#[cfg(windows)]
+fn stderr_is_tty() -> bool {
+    false
+}
+
+/// A `fern` file sink that rotates once it passes a size limit, since
+/// `fern::log_file` just appends forever and a daemon left running at
+/// `Trace` for weeks turns that into gigabytes.
+///
+/// Past `max_bytes`, [`RotatingFile::write`] renames the live file to `.1`,
+/// shifting any existing `.1`/`.2` up one slot and discarding whatever was in
+/// `.3`, then reopens a fresh file at the original path. All of this is
+/// behind a [`Mutex`] so concurrent log calls from multiple threads can't
+/// race a rotation, even though `fern` would normally serve that role for a
+/// plain file sink.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    inner: Mutex<RotatingFileInner>,
+}
+
+struct RotatingFileInner {
+    file: std::fs::File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_bytes: u64) -> Result<Self> {
+        let file = crate::retry::retry_eintr(|| {
+            std::fs::OpenOptions::new().create(true).append(true).open(path)
+        })?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_bytes,
+            inner: Mutex::new(RotatingFileInner { file, size }),
+        })
+    }
+
+    /// The `.1`/`.2`/`.3` path for `generation` generations back from the
+    /// live file.
+    fn rotated_path(&self, generation: u32) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{generation}"));
+        PathBuf::from(name)
+    }
+
+    /// Shift `.1`→`.2`→`.3` (dropping whatever was in the last slot), rename
+    /// the live file to `.1`, then reopen a fresh one in its place.
+    fn rotate(&self, inner: &mut RotatingFileInner) -> std::io::Result<()> {
+        for generation in (1..ROTATED_GENERATIONS).rev() {
+            let from = self.rotated_path(generation);
+            let to = self.rotated_path(generation + 1);
+            if from.exists() {
+                std::fs::rename(from, to)?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated_path(1))?;
+        inner.file = crate::retry::retry_eintr(|| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+        })?;
+        inner.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.size > 0 && inner.size.saturating_add(buf.len() as u64) > self.max_bytes {
+            self.rotate(&mut inner)?;
+        }
+        let written = inner.file.write(buf)?;
+        inner.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.lock().unwrap().file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Smoke test for `hxd --foreground`'s extra stdout sink: the exact
+    /// formatting [`stdout_dispatch`] writes to stdout includes the
+    /// record's level and message. `setup`/`stdout_dispatch` themselves
+    /// aren't exercised directly here since `fern`'s logger can only be
+    /// installed once per process, which every other test in this binary
+    /// already shares.
+    #[test]
+    fn format_stdout_line_includes_the_level_and_message() {
+        let line = format_stdout_line(log::Level::Info, &format_args!("listening on /tmp/x.sock"));
+        assert!(line.ends_with("INFO listening on /tmp/x.sock"));
+    }
+
+    #[test]
+    fn verbosity_for_flag_matches_the_repeated_v_count() {
+        assert_eq!(verbosity_for_flag("-v"), 1);
+        assert_eq!(verbosity_for_flag("-vv"), 2);
+        assert_eq!(verbosity_for_flag("-vvv"), 3);
+        assert_eq!(verbosity_for_flag("--socket"), 0);
+    }
+
+    #[test]
+    fn looks_like_a_flag_treats_a_bare_dash_as_a_value() {
+        assert!(looks_like_a_flag("--socket"));
+        assert!(looks_like_a_flag("-v"));
+        assert!(!looks_like_a_flag("-"));
+        assert!(!looks_like_a_flag("/tmp/hxd.log"));
+    }
+
+    #[test]
+    fn suppress_verbose_stderr_toggles_the_shared_flag() {
+        suppress_verbose_stderr(true);
+        assert!(SUPPRESS_BELOW_WARN.load(Ordering::Relaxed));
+        suppress_verbose_stderr(false);
+        assert!(!SUPPRESS_BELOW_WARN.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn set_level_raises_the_active_filter_so_previously_dropped_messages_pass() {
+        set_level(0);
+        assert!(!log::log_enabled!(log::Level::Debug));
+        set_level(2);
+        assert!(log::log_enabled!(log::Level::Debug));
+        set_level(0);
+    }
+
+    #[test]
+    fn rotating_file_stays_under_the_limit_while_the_live_file_is_small() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.log");
+        let mut file = RotatingFile::open(&path, 1024).unwrap();
+        file.write_all(b"hello\n").unwrap();
+        let mut rotated = path.as_os_str().to_os_string();
+        rotated.push(".1");
+        assert!(!std::path::PathBuf::from(rotated).exists());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn rotating_file_rotates_once_a_write_would_cross_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.log");
+        let mut file = RotatingFile::open(&path, 10).unwrap();
+        file.write_all(b"0123456789").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "0123456789");
+
+        file.write_all(b"more").unwrap();
+        let rotated = {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(".1");
+            std::path::PathBuf::from(name)
+        };
+        assert_eq!(std::fs::read_to_string(&rotated).unwrap(), "0123456789");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "more");
+    }
+
+    #[test]
+    fn rotating_file_keeps_only_the_configured_number_of_generations() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.log");
+        let mut file = RotatingFile::open(&path, 1).unwrap();
+
+        for chunk in ["a", "b", "c", "d"] {
+            file.write_all(chunk.as_bytes()).unwrap();
+        }
+
+        let at = |generation: u32| {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(format!(".{generation}"));
+            std::path::PathBuf::from(name)
+        };
+        assert_eq!(std::fs::read_to_string(at(1)).unwrap(), "c");
+        assert_eq!(std::fs::read_to_string(at(2)).unwrap(), "b");
+        assert_eq!(std::fs::read_to_string(at(3)).unwrap(), "a");
+        assert!(!at(4).exists());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "d");
+    }
+}
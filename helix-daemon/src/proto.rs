@@ -0,0 +1,696 @@
+//! The wire protocol spoken between `hxc` (and other clients) and `hxd`.
+//!
+//! A short-lived *control* connection sends a single [`Request`] and reads back
+//! a single [`Response`]. Once a client attaches to a session the same
+//! connection is handed over to the session task, which speaks the
+//! [`SessionRequest`]/[`SessionResponse`] pair instead.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+/// The wire protocol's own version, independent of `CARGO_PKG_VERSION` (which
+/// bumps on every release, including ones that don't touch the wire format
+/// at all, and also names the default socket — see [`addr`]). Reported
+/// alongside the crate version in `Response::Version`, so a client can tell
+/// an incompatible wire format apart from a merely different daemon build.
+///
+/// Bump this whenever `Request`, `Response`, `SessionRequest`, or
+/// `SessionResponse` change in a way `bincode` can't decode across: adding a
+/// new variant at the end of an enum, or a new field with a `#[serde(default)]`,
+/// is compatible and doesn't need a bump; reordering variants, changing or
+/// reordering a variant's fields, or removing one, does.
+pub const PROTO_VERSION: u32 = 1;
+
+pub type SessionId = u64;
+
+/// A `file[:row[:col]]` positional argument collected by a client (e.g.
+/// `hxc foo.rs:12:3`), naming a file the new session's hosted editor should
+/// open and, optionally, a cursor position within it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileSpec {
+    pub path: String,
+    pub row: Option<u32>,
+    pub col: Option<u32>,
+}
+
+/// A request sent from a client to the daemon on a fresh connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Spawn a new session and attach the connection to it. `env` is the
+    /// client's environment, filtered down to a fixed allowlist of
+    /// editor-relevant variables (see `Client::new_session`'s caller), for
+    /// the eventual editor process hosted in the session to inherit. `cwd`
+    /// is the client's working directory at creation, so the session has a
+    /// correct one for display and relative file resolution right away
+    /// instead of waiting for a `SessionRequest::SetCwd` from whatever
+    /// editor ends up hosted in it. `files` are the client's `file[:row[:col]]`
+    /// positional arguments, for the eventual editor process to open;
+    /// their paths also seed `SessionSummary::files` right away.
+    NewSession {
+        env: Vec<(String, String)>,
+        cwd: String,
+        files: Vec<FileSpec>,
+    },
+    /// List all known sessions, ordered by `sort`. When `all` is set, the
+    /// response also includes sessions from before the daemon's last
+    /// restart (see [`SessionSummary::stale`]). `tag`, if set, restricts the
+    /// result to sessions carrying that tag (see [`Request::TagSession`]).
+    ListSessions {
+        sort: SortBy,
+        all: bool,
+        tag: Option<String>,
+    },
+    /// Attach the connection to an existing session. If the session is
+    /// already attached, `takeover` forcibly detaches the current client
+    /// first (subject to [`crate::server::ServerConfig::allow_takeover`]).
+    /// `passphrase` is required, and compared against the session's stored
+    /// hash, if it was locked via [`Request::LockSession`].
+    AttachSession {
+        id: SessionId,
+        takeover: bool,
+        passphrase: Option<String>,
+    },
+    /// Attach to the detached session with the most recent
+    /// [`SessionSummary::last_detached`] (falling back to creation time for
+    /// one that was never attached to begin with), like `tmux attach` with
+    /// no target. Rejected with `Response::Err("no detached sessions")` if
+    /// every session is currently attached (or there are none at all). Locked
+    /// sessions are skipped rather than considered, since there would be no
+    /// way to supply a passphrase for one picked implicitly.
+    AttachLast { takeover: bool },
+    /// Lock a detached session behind a passphrase, so it can't be attached
+    /// (even by the same uid) without it — see [`crate::auth::hash_passphrase`]
+    /// for how `passphrase_hash` should be produced; the daemon never sees
+    /// the plaintext. Pass `passphrase_hash: None` to unlock a session
+    /// again. Rejected with `Response::Err` if the session doesn't exist or
+    /// is currently attached (it must be detached first).
+    LockSession {
+        id: SessionId,
+        passphrase_hash: Option<String>,
+    },
+    /// Attach to the detached session aliased `alias`, or atomically create
+    /// one pre-aliased to it if none exists yet — so a script's "attach to
+    /// `work`, creating it if needed" doesn't race a separate list-then-decide
+    /// against itself. `env`/`cwd`/`files` are only used if a session ends up
+    /// being created (see [`Request::NewSession`]). Rejected with
+    /// `Response::Err("session is occupied")` if a session with that alias
+    /// exists but is already attached, or `Response::Err("session is
+    /// locked")` if it exists but is locked (see [`Request::LockSession`]).
+    AttachOrCreate {
+        alias: String,
+        env: Vec<(String, String)>,
+        cwd: String,
+        files: Vec<FileSpec>,
+    },
+    /// Add or remove tags on a session, for grouping beyond a single alias
+    /// (e.g. `"project:foo"`, `"wip"`). `remove` is applied before `add`, so
+    /// a tag present in both ends up added. Rejected with `Response::Err` if
+    /// the session doesn't exist.
+    TagSession {
+        id: SessionId,
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    /// Terminate a session. `force` skips any graceful shutdown handshake.
+    KillSession { id: SessionId, force: bool },
+    /// Terminate several sessions in one round trip. `force` applies to
+    /// every id in `ids`. A missing id doesn't abort the rest: each gets its
+    /// own [`KillResult`] in `Response::KillResults`, in the same order as
+    /// `ids`.
+    KillSessions { ids: Vec<SessionId>, force: bool },
+    /// Ask the daemon to shut down, terminating all sessions first.
+    StopServer,
+    /// Atomically swap the ids of two sessions, so a frequently used session
+    /// can be renumbered to a low id (listings sort by [`SessionId`]).
+    /// Rejected with `Response::Err` if either id is missing. Swapping a
+    /// session with itself is a no-op that still succeeds.
+    SwapSessions(SessionId, SessionId),
+    /// Ask the daemon to report its version, so a client can tell a stale
+    /// binary apart from a genuinely unreachable daemon instead of just
+    /// seeing "connection refused" (the socket path already embeds the
+    /// version, but that only prevents a mismatched pair from finding each
+    /// other, it doesn't explain why).
+    Version,
+    /// Subscribe to a live stream of session-list changes instead of the
+    /// usual single request/response round trip: after one acknowledging
+    /// `Response::Ok`, the daemon keeps pushing `Response::SessionListDelta`
+    /// messages down this same connection (leveraging
+    /// [`crate::server::Server::subscribe`]) until the client disconnects.
+    /// See `hxc --list --follow`.
+    WatchSessions,
+    /// Block until the given session terminates, then reply with
+    /// `Response::SessionEnded`. Rejected up front with
+    /// `Response::Err("no such session")` if it doesn't currently exist;
+    /// otherwise the connection is handed off the same way
+    /// `Request::WatchSessions` is, so it doesn't tie up the accept loop.
+    /// Several waiters may wait on the same session; all are answered. If
+    /// the waiter disconnects first, its registration is simply dropped.
+    /// See `hxc --wait`.
+    WaitSession(SessionId),
+    /// Forward `payload` to the session named or numbered `sid_or_alias` as a
+    /// `SessionResponse::Command`, without attaching. Rejected with
+    /// `Response::Err("no such session")` if it doesn't resolve to a live
+    /// session, or `Response::Err("session is detached")` if it resolves to
+    /// one with no attached client to deliver it to. Otherwise replies
+    /// `Response::Ok` once the session has been handed the command — that
+    /// only means delivery was attempted, not that whatever eventually
+    /// consumes it (an editor core, today nothing) acted on it. See `hxc
+    /// --send`.
+    SendToSession { sid_or_alias: String, payload: String },
+    /// Ask the daemon for its running counters (see
+    /// [`crate::server::Server::metrics`]), e.g. `hxc --metrics` or, in
+    /// Prometheus textfile collector format, `hxc --metrics --prometheus`/
+    /// `hxc --stats`. Always answered with `Response::Metrics`, never
+    /// rejected.
+    Metrics,
+    /// Change the daemon's active log level to the one implied by
+    /// `verbosity` (same 0 = `Warn` .. 3+ = `Trace` scale as `-v`/`-vv`/`-vvv`;
+    /// see `crate::logging::set_level`), without restarting it. Always
+    /// answered with `Response::Ok`, never rejected. See `hxc --log-level`.
+    SetLogLevel(u8),
+    /// Override the idle-reap policy for a single session: it's terminated
+    /// after sitting detached for `timeout`, independent of any other
+    /// session's override. `None` means never reap it. Only has any effect
+    /// once the session goes detached (an attached session is never idle);
+    /// see `crate::server::Server`'s idle sweep. Rejected with
+    /// `Response::Err("no such session")` if the session doesn't exist,
+    /// otherwise always answered with `Response::Ok`. See `hxc --timeout`.
+    SetSessionTimeout {
+        id: SessionId,
+        timeout: Option<Duration>,
+    },
+}
+
+/// The field [`Request::ListSessions`] should order its results by. Ties
+/// always fall back to [`SortBy::Id`] so the order is stable.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum SortBy {
+    #[default]
+    Id,
+    CreatedAt,
+    Alias,
+}
+
+/// Upper bounds (in microseconds) of each finite bucket in
+/// [`SessionStats::latency_buckets`], in ascending order. A request whose
+/// latency exceeds every bound here still lands in the final bucket, so the
+/// array has one fewer entry than `latency_buckets` is long.
+pub const SESSION_LATENCY_BUCKET_BOUNDS_US: [u64; 6] = [100, 500, 1_000, 5_000, 20_000, 100_000];
+
+/// Per-session request latency and message throughput counters, reported by
+/// [`SessionSummary::stats`] and the daemon's SIGUSR1 state dump. Entirely
+/// `Copy`, fixed-size fields only, so a running session can update and
+/// report these on every request without allocating.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionStats {
+    /// How many `SessionRequest`s this session has received from attached
+    /// clients over its lifetime.
+    pub messages_in: u64,
+    /// How many `SessionResponse`s this session has sent to attached clients
+    /// over its lifetime, including ones mirrored to more than one peer.
+    pub messages_out: u64,
+    /// How many requests have had a latency recorded via
+    /// [`Self::record_latency`]. The divisor for a mean of
+    /// `latency_sum_us`.
+    pub latency_count: u64,
+    /// Sum of every recorded request's handling latency, in microseconds.
+    pub latency_sum_us: u64,
+    /// The single slowest request handled so far, in microseconds.
+    pub latency_max_us: u64,
+    /// A histogram of request latencies: `latency_buckets[i]` counts
+    /// requests handled in at most [`SESSION_LATENCY_BUCKET_BOUNDS_US`]`[i]`
+    /// microseconds, and the last entry counts everything slower than the
+    /// final bound.
+    pub latency_buckets: [u64; SESSION_LATENCY_BUCKET_BOUNDS_US.len() + 1],
+}
+
+impl SessionStats {
+    /// Fold one more request's handling time into the histogram and running
+    /// sum/max. `elapsed` is expected to come from an `Instant::elapsed()`
+    /// taken around the request's handler.
+    pub fn record_latency(&mut self, elapsed: std::time::Duration) {
+        let us = elapsed.as_micros().try_into().unwrap_or(u64::MAX);
+        self.latency_count += 1;
+        self.latency_sum_us += us;
+        self.latency_max_us = self.latency_max_us.max(us);
+        let bucket = SESSION_LATENCY_BUCKET_BOUNDS_US
+            .iter()
+            .position(|&bound| us <= bound)
+            .unwrap_or(SESSION_LATENCY_BUCKET_BOUNDS_US.len());
+        self.latency_buckets[bucket] += 1;
+    }
+}
+
+/// A summary of a session, as returned by [`Request::ListSessions`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub id: SessionId,
+    pub alias: Option<String>,
+    pub created_at: SystemTime,
+    pub attached: bool,
+    /// The session's current working directory: the creating client's own
+    /// cwd (see [`Request::NewSession`]) until the session reports a new one
+    /// via [`SessionRequest::SetCwd`], or `None` if even that couldn't be
+    /// read.
+    pub cwd: Option<String>,
+    /// Basenames of the first few files the session has reported open (see
+    /// [`SessionRequest::FilesChanged`]), truncated for display in a listing.
+    /// The daemon's SIGUSR1 state dump logs the untruncated list instead.
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// When the session last went from attached to fully detached (no peers
+    /// left), for showing how long it's been sitting idle. `None` while
+    /// attached, or if it has never been detached yet.
+    #[serde(default)]
+    pub last_detached: Option<SystemTime>,
+    /// Set on entries that describe a session from before the daemon's last
+    /// restart rather than one that is actually running. Only ever present
+    /// when the request set `all: true`.
+    #[serde(default)]
+    pub stale: bool,
+    /// The path of this session's output capture file, if capture is active
+    /// (see [`SessionRequest::SetCapture`]). `None` if capture was never
+    /// enabled, or the daemon isn't configured with a capture directory.
+    #[serde(default)]
+    pub capturing: Option<String>,
+    /// The environment the session was created with (see
+    /// `Request::NewSession`), for diagnosing what an eventual hosted editor
+    /// process would inherit.
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// Whether the session is locked behind a passphrase (see
+    /// [`Request::LockSession`]). The hash itself is never exposed here.
+    #[serde(default)]
+    pub locked: bool,
+    /// Arbitrary tags attached via [`Request::TagSession`], for grouping
+    /// beyond a single alias.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The session's last known terminal size as `(rows, cols)`, reported by
+    /// an attached client via `SessionRequest::Resize`. `None` if no client
+    /// has ever reported one.
+    #[serde(default)]
+    pub size: Option<(u16, u16)>,
+    /// Request latency and message throughput counters for this session; see
+    /// [`SessionStats`].
+    #[serde(default)]
+    pub stats: SessionStats,
+    /// Where this session's own dedicated log file lives, if the daemon is
+    /// configured with `ServerConfig::per_session_logs`. `None` if the
+    /// feature is off, or the file failed to open.
+    #[serde(default)]
+    pub log_path: Option<String>,
+}
+
+/// A response to a [`Request`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    SessionCreated { id: SessionId },
+    Sessions(Vec<SessionSummary>),
+    /// Accepted an `AttachSession`/`AttachLast`. Carries the id and alias of
+    /// the session actually attached to, since `AttachLast`'s caller doesn't
+    /// know either ahead of time.
+    Attached {
+        id: SessionId,
+        alias: Option<String>,
+    },
+    /// Accepted an `AttachOrCreate`. `created` tells the caller whether it
+    /// got a fresh session or was attached to one that already existed.
+    AttachedOrCreated { id: SessionId, created: bool },
+    Ok,
+    Err(String),
+    /// The daemon's `CARGO_PKG_VERSION` and [`PROTO_VERSION`], in reply to
+    /// `Request::Version`.
+    Version {
+        crate_version: String,
+        proto_version: u32,
+    },
+    /// One change in the live session list, in reply to
+    /// `Request::WatchSessions`. Unlike every other `Response`, many of these
+    /// can arrive on the same connection over time.
+    SessionListDelta(SessionListDelta),
+    /// One [`KillResult`] per id in a `Request::KillSessions`, in the same
+    /// order as the request.
+    KillResults(Vec<KillResult>),
+    /// The session a `Request::WaitSession` was waiting on has terminated.
+    /// `code`/`forced` mirror `SessionResponse::Terminated`'s fields: `1`
+    /// and `true` for a forced termination, `0` and `false` otherwise.
+    SessionEnded { code: i32, forced: bool },
+    /// The daemon's current counters, in reply to `Request::Metrics`. Named
+    /// rather than a fixed struct so a counter can be added or removed
+    /// without a wire-format bump; see `Server::metrics` for the current
+    /// set of keys.
+    Metrics(BTreeMap<String, u64>),
+    /// Every session has actually finished terminating, in reply to
+    /// `Request::StopServer`. `clean` and `forced` count sessions whose
+    /// `SessionEventKind::Terminated` reported `forced: false`/`true`
+    /// respectively; `failed` lists the ids that were still running once
+    /// `ServerConfig::shutdown_deadline` elapsed and had to have their task
+    /// aborted outright.
+    Stopped {
+        clean: u64,
+        forced: u64,
+        failed: Vec<SessionId>,
+    },
+}
+
+/// The outcome of killing one session in a `Request::KillSessions` batch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KillResult {
+    pub id: SessionId,
+    /// `None` on success; `Some(message)` mirrors what a single
+    /// `Request::KillSession` would have replied with, e.g. "no such
+    /// session".
+    pub error: Option<String>,
+}
+
+/// A single change in the live session list, as streamed by
+/// [`Request::WatchSessions`]. A serializable counterpart to
+/// [`crate::session::SessionEventKind`], covering only the subset of session
+/// events a listing actually cares about.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SessionListDelta {
+    /// A new session was created.
+    Created { id: SessionId },
+    /// A session's last attached client went away.
+    Detached { id: SessionId },
+    /// A session terminated.
+    Terminated { id: SessionId },
+    /// A session was renamed.
+    Aliased { id: SessionId, alias: String },
+}
+
+/// A request sent from an attached [`crate::client::SessionClient`] down to the
+/// [`crate::session::Session`] it is attached to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SessionRequest {
+    /// The client wishes to detach, leaving the session running.
+    Detach,
+    /// The client wishes to terminate the session outright. `code` is
+    /// reported back as `SessionResponse::Terminated`'s exit status, for a
+    /// client (eventually an editor core) that already knows the status it
+    /// wants to exit with; 0 for an ordinary graceful quit.
+    Terminate { code: i32 },
+    /// Report the session's current working directory, e.g. after the
+    /// editor inside it runs `:cd`. Rejected with `SessionResponse::Err` if
+    /// `path` is empty or not absolute.
+    SetCwd(String),
+    /// Report the session's current list of open files, e.g. after the
+    /// editor inside it opens or closes a buffer. An empty list is valid
+    /// (no buffers open) and simply clears the previous one.
+    FilesChanged(Vec<String>),
+    /// Acknowledge a `SessionResponse::ConfirmTerminate`, letting a graceful
+    /// termination proceed. `save` reports whether the client wants its
+    /// unsaved changes written first; wiring that up to an actual editor
+    /// save is left to a later change, this just carries the client's
+    /// answer.
+    TerminateAck { save: bool },
+    /// Rename the session, so it shows up under this name in `hxc --list`.
+    /// Rejected with `SessionResponse::Err` if `alias` is empty.
+    SetAlias(String),
+    /// Enable or disable teeing the session's output to a per-session log
+    /// file under `crate::server::ServerConfig::capture_dir`. Rejected with
+    /// `SessionResponse::Err` if no capture directory is configured, or if
+    /// the file can't be opened.
+    SetCapture(bool),
+    /// Report the client terminal's current size, e.g. after a `SIGWINCH`.
+    /// The client is expected to coalesce bursts of these itself rather than
+    /// sending one per resize event; the daemon just records whatever it's
+    /// sent as the session's current size.
+    Resize { rows: u16, cols: u16 },
+    /// The client is about to receive `SIGTSTP` and stop reading from its
+    /// socket; the session should stop forwarding output until `Resumed`
+    /// arrives instead of letting it pile up against a client that isn't
+    /// listening.
+    Suspended,
+    /// The client has resumed after `SIGCONT`, reporting `rows`/`cols` as
+    /// its (possibly changed) terminal size.
+    Resumed { rows: u16, cols: u16 },
+    /// Reply to a `SessionResponse::Ping`, proving the client is still
+    /// there. See `crate::server::ServerConfig::keepalive_interval`.
+    Pong,
+}
+
+/// A message sent from a [`crate::session::Session`] to its attached client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SessionResponse {
+    /// Output produced by the session, to be written to the client's terminal.
+    Output(Vec<u8>),
+    /// The session has terminated. `forced` is true when it was killed rather
+    /// than shut down gracefully. `code` is the exit status of the process
+    /// hosted in the session (0 for a clean exit), once a real editor process
+    /// is wired up; defaults to 0 so older daemons that never set it still
+    /// decode cleanly.
+    Terminated {
+        forced: bool,
+        #[serde(default)]
+        code: i32,
+    },
+    /// An informational notice from the daemon (e.g. "daemon shutting down"),
+    /// meant to be printed to the client's stderr rather than mixed into the
+    /// session's own output stream.
+    Notice(String),
+    /// The server detached this client on its own initiative (e.g. a
+    /// takeover by another client). Distinct from `Terminated`: the session
+    /// itself is still alive.
+    Detached,
+    /// A request from this client was rejected, e.g. an invalid
+    /// `SessionRequest::SetCwd`.
+    Err(String),
+    /// The session is about to terminate gracefully and is giving the
+    /// attached client a chance to save first. Reply with
+    /// `SessionRequest::TerminateAck`; if none arrives before the daemon's
+    /// timeout, termination proceeds anyway.
+    ConfirmTerminate,
+    /// Sent as the first message to a client right after
+    /// `DetachableChannel::attach` hands it a slot, describing the session's
+    /// current state so it doesn't have to wait for the next incidental
+    /// update to know what it's looking at. `seq` counts how many attach
+    /// handshakes this session has sent so far (starting at 1), in case a
+    /// client ever needs to notice it missed one.
+    AttachAck {
+        sid: SessionId,
+        alias: Option<String>,
+        cwd: Option<String>,
+        files: Vec<String>,
+        /// The session's last known terminal size, as reported via
+        /// `SessionRequest::Resize` by whichever client attached before this
+        /// one. `None` if no client has ever reported a size.
+        #[serde(default)]
+        size: Option<(u16, u16)>,
+        seq: u64,
+    },
+    /// A keepalive probe (see `crate::server::ServerConfig::keepalive_interval`).
+    /// Reply with `SessionRequest::Pong`; missing the deadline gets the
+    /// client treated as gone and detached.
+    Ping,
+    /// A command delivered via `Request::SendToSession`, forwarded verbatim
+    /// for the attached client (today: `hxc`, eventually an editor core) to
+    /// act on.
+    Command(String),
+}
+
+/// Internal, non-wire event routed from the [`crate::server::Server`] to a
+/// running [`crate::session::Session`] task.
+pub enum ServerEvent {
+    /// Hand over a freshly-accepted connection as the session's new attached
+    /// channel. `takeover` is set when this displaces an already-attached
+    /// client, which should be told so via `SessionResponse::Detached`.
+    Attach {
+        channel: crate::channel::Channel,
+        takeover: bool,
+        /// The session's alias/cwd/files/size as the server currently knows
+        /// them, snapshotted at the moment of attach, for the `Session` to
+        /// hand back to the newly attached client as a
+        /// `SessionResponse::AttachAck`.
+        alias: Option<String>,
+        cwd: Option<String>,
+        files: Vec<String>,
+        size: Option<(u16, u16)>,
+    },
+    /// Detach whatever client is currently attached.
+    Detach,
+    /// Terminate the session. `force` skips any graceful shutdown handshake.
+    Terminate(bool),
+    /// Forward an informational notice to the attached client, if any.
+    Notify(String),
+    /// The session's id changed (see [`Request::SwapSessions`]); the session
+    /// must update its own idea of `self.id` so future outgoing messages
+    /// (e.g. `SessionResponse::AttachAck`) report the new one.
+    Reassign(SessionId),
+    /// Output produced by whatever process ends up hosted in the session,
+    /// once one is wired up. Forwarded to the attached client as
+    /// `SessionResponse::Output` and, if capture is active (see
+    /// [`SessionRequest::SetCapture`]), teed to the session's capture file.
+    Output(Vec<u8>),
+    /// Deliver a command from `Request::SendToSession` to the attached
+    /// client, if any, as a `SessionResponse::Command`.
+    Deliver(String),
+}
+
+/// The path of the control socket the daemon listens on and clients connect
+/// to by default. Embeds the crate version so an incompatible client/daemon
+/// pair simply fail to find each other's socket rather than talk past one
+/// another.
+pub fn addr() -> std::path::PathBuf {
+    helix_loader::cache_dir().join(format!("hxd-{}.sock", env!("CARGO_PKG_VERSION")))
+}
+
+/// Resolve the filesystem socket path a client or daemon should use, in
+/// precedence order: `explicit` (e.g. a `--socket` flag), then
+/// `$HELIX_DAEMON_SOCKET`, then [`addr()`]. Only meaningful for filesystem
+/// sockets; abstract-namespace (`@name`) sockets are resolved separately by
+/// their own callers and never go through here.
+pub fn resolve_socket_path(explicit: Option<&std::path::Path>) -> std::path::PathBuf {
+    if let Some(path) = explicit {
+        return path.to_path_buf();
+    }
+    if let Ok(path) = std::env::var("HELIX_DAEMON_SOCKET") {
+        if !path.is_empty() {
+            return std::path::PathBuf::from(path);
+        }
+    }
+    addr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn resolve_socket_path_prefers_the_explicit_value() {
+        std::env::remove_var("HELIX_DAEMON_SOCKET");
+        assert_eq!(
+            resolve_socket_path(Some(Path::new("/tmp/explicit.sock"))),
+            Path::new("/tmp/explicit.sock")
+        );
+    }
+
+    #[test]
+    fn resolve_socket_path_falls_back_to_the_env_var_then_the_default() {
+        std::env::set_var("HELIX_DAEMON_SOCKET", "/tmp/env.sock");
+        assert_eq!(resolve_socket_path(None), Path::new("/tmp/env.sock"));
+
+        std::env::remove_var("HELIX_DAEMON_SOCKET");
+        assert_eq!(resolve_socket_path(None), addr());
+    }
+
+    #[test]
+    fn resize_round_trips_through_bincode() {
+        let request = SessionRequest::Resize { rows: 40, cols: 120 };
+        let encoded = bincode::serialize(&request).unwrap();
+        let decoded: SessionRequest = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(
+            decoded,
+            SessionRequest::Resize { rows: 40, cols: 120 }
+        ));
+    }
+
+    #[test]
+    fn suspended_and_resumed_round_trip_through_bincode() {
+        let encoded = bincode::serialize(&SessionRequest::Suspended).unwrap();
+        let decoded: SessionRequest = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(decoded, SessionRequest::Suspended));
+
+        let request = SessionRequest::Resumed { rows: 40, cols: 120 };
+        let encoded = bincode::serialize(&request).unwrap();
+        let decoded: SessionRequest = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(
+            decoded,
+            SessionRequest::Resumed { rows: 40, cols: 120 }
+        ));
+    }
+
+    #[test]
+    fn kill_sessions_and_its_response_round_trip_through_bincode() {
+        let request = Request::KillSessions { ids: vec![1, 2], force: true };
+        let encoded = bincode::serialize(&request).unwrap();
+        let decoded: Request = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(
+            decoded,
+            Request::KillSessions { ids, force: true } if ids == vec![1, 2]
+        ));
+
+        let response = Response::KillResults(vec![
+            KillResult { id: 1, error: None },
+            KillResult { id: 2, error: Some("no such session".into()) },
+        ]);
+        let encoded = bincode::serialize(&response).unwrap();
+        let decoded: Response = bincode::deserialize(&encoded).unwrap();
+        match decoded {
+            Response::KillResults(results) => {
+                assert_eq!(results.len(), 2);
+                assert_eq!(results[0].id, 1);
+                assert!(results[0].error.is_none());
+                assert_eq!(results[1].id, 2);
+                assert_eq!(results[1].error.as_deref(), Some("no such session"));
+            }
+            other => panic!("expected KillResults, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stop_server_and_its_response_round_trip_through_bincode() {
+        let encoded = bincode::serialize(&Request::StopServer).unwrap();
+        let decoded: Request = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(decoded, Request::StopServer));
+
+        let response = Response::Stopped { clean: 2, forced: 1, failed: vec![3] };
+        let encoded = bincode::serialize(&response).unwrap();
+        let decoded: Response = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(
+            decoded,
+            Response::Stopped { clean: 2, forced: 1, ref failed } if failed == &vec![3]
+        ));
+    }
+
+    #[test]
+    fn version_and_its_response_round_trip_through_bincode() {
+        let encoded = bincode::serialize(&Request::Version).unwrap();
+        let decoded: Request = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(decoded, Request::Version));
+
+        let response = Response::Version {
+            crate_version: "0.1.0".into(),
+            proto_version: PROTO_VERSION,
+        };
+        let encoded = bincode::serialize(&response).unwrap();
+        let decoded: Response = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(
+            decoded,
+            Response::Version { ref crate_version, proto_version } if crate_version == "0.1.0" && proto_version == PROTO_VERSION
+        ));
+    }
+
+    #[test]
+    fn set_session_timeout_round_trips_through_bincode() {
+        let request = Request::SetSessionTimeout { id: 3, timeout: Some(Duration::from_secs(1800)) };
+        let encoded = bincode::serialize(&request).unwrap();
+        let decoded: Request = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(
+            decoded,
+            Request::SetSessionTimeout { id: 3, timeout: Some(d) } if d == Duration::from_secs(1800)
+        ));
+
+        let request = Request::SetSessionTimeout { id: 3, timeout: None };
+        let encoded = bincode::serialize(&request).unwrap();
+        let decoded: Request = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(
+            decoded,
+            Request::SetSessionTimeout { id: 3, timeout: None }
+        ));
+    }
+
+    #[test]
+    fn ping_and_pong_round_trip_through_bincode() {
+        let encoded = bincode::serialize(&SessionResponse::Ping).unwrap();
+        let decoded: SessionResponse = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(decoded, SessionResponse::Ping));
+
+        let encoded = bincode::serialize(&SessionRequest::Pong).unwrap();
+        let decoded: SessionRequest = bincode::deserialize(&encoded).unwrap();
+        assert!(matches!(decoded, SessionRequest::Pong));
+    }
+}
@@ -0,0 +1,51 @@
+//! Retry a blocking syscall-backed operation that failed with `EINTR`.
+//!
+//! Blocking filesystem calls made from the daemon's startup/shutdown paths
+//! (not the async runtime) can be interrupted by a signal before they
+//! complete, surfacing as `io::ErrorKind::Interrupted` even though the
+//! operation itself would otherwise have succeeded. Retrying is the
+//! standard, safe response — Rust's std doesn't do this automatically for
+//! `fs` operations the way it does for some lower-level read/write calls.
+
+/// Retry `f` as long as it fails with `io::ErrorKind::Interrupted`, returning
+/// its first non-`EINTR` result (success or a genuine error).
+pub fn retry_eintr<T>(mut f: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    loop {
+        match f() {
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn retries_once_on_eintr_then_returns_the_success() {
+        let attempts = Cell::new(0);
+        let result = retry_eintr(|| {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() == 1 {
+                Err(std::io::Error::from(std::io::ErrorKind::Interrupted))
+            } else {
+                Ok(42)
+            }
+        });
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn passes_through_a_non_eintr_error_without_retrying() {
+        let attempts = Cell::new(0);
+        let result = retry_eintr(|| {
+            attempts.set(attempts.get() + 1);
+            Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -0,0 +1,3429 @@
+//! The daemon side of the protocol: accepts connections, spawns sessions, and
+//! routes events between them.
+
+use crate::channel::{Channel, MirrorPolicy, OverflowPolicy};
+use crate::config::Config;
+use crate::error::Result;
+use crate::proto::{
+    self, FileSpec, KillResult, Request, Response, ServerEvent, SessionId, SessionStats,
+    SessionSummary, SortBy, PROTO_VERSION,
+};
+use crate::session::{Session, SessionEvent, SessionEventKind};
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio_seqpacket::UnixSeqpacketListener;
+use tracing::Instrument;
+
+#[cfg(not(windows))]
+use {signal_hook::consts::signal, signal_hook_tokio::Signals};
+#[cfg(windows)]
+type Signals = futures_util::stream::Empty<i32>;
+
+/// Tunable knobs for a running [`Server`].
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// How long attached clients get to see a shutdown [`crate::proto::SessionResponse::Notice`]
+    /// before their session is forcibly terminated.
+    pub shutdown_grace_period: Duration,
+    /// Whether an `AttachSession { takeover: true }` may forcibly displace an
+    /// already-attached client. Off by default: an occupied session simply
+    /// rejects new attaches.
+    pub allow_takeover: bool,
+    /// How long a freshly-accepted connection has to send its initial
+    /// [`crate::proto::Request`], and then how long the daemon has to finish
+    /// answering it, before the connection is dropped. Guards the accept
+    /// loop against connections that open and then stall, whether by
+    /// accident or design.
+    pub handshake_timeout: Duration,
+    /// Caps the number of concurrently running sessions. `None` means
+    /// unlimited. Hot-reloadable via [`crate::config::Config`] and `SIGHUP`.
+    pub max_sessions: Option<usize>,
+    /// Caps how many clients may be attached (mirrored) to a single session
+    /// at once. `1` (the default) preserves the historical exclusive-attach
+    /// behavior; raising it lets multiple terminals see the same session.
+    pub max_attached_peers: usize,
+    /// Capacity of each mirrored peer's outgoing message queue, so one slow
+    /// client can't block delivery of output to the others.
+    pub mirror_queue_capacity: usize,
+    /// What happens to a mirrored peer whose outgoing queue is already full
+    /// when another message needs to be sent to it.
+    pub mirror_overflow: OverflowPolicy,
+    /// How long a non-forced termination waits for the attached client's
+    /// `SessionRequest::TerminateAck` before giving up and terminating
+    /// anyway.
+    pub terminate_confirm_timeout: Duration,
+    /// Whether a session whose last attached peer disconnects unexpectedly
+    /// (rather than sending an explicit `Detach`) should be terminated
+    /// outright instead of simply left detached for a client to reattach
+    /// to later. Off by default, since losing the session along with the
+    /// connection defeats the point of a daemon in the first place.
+    pub exit_on_disconnect: bool,
+    /// Where a session's output capture file may be opened, if a client asks
+    /// to turn capture on via `SessionRequest::SetCapture`. `None` (the
+    /// default) disables capture outright, regardless of the request.
+    pub capture_dir: Option<PathBuf>,
+    /// How often an attached client is probed with a
+    /// `SessionResponse::Ping` to detect one that's gone silently (e.g. its
+    /// machine vanished) rather than through a clean disconnect. `None`
+    /// (the default) disables the keepalive entirely, matching the
+    /// historical behavior of only noticing a dead peer once a send fails.
+    pub keepalive_interval: Option<Duration>,
+    /// How long a `Ping` is given to draw a `SessionRequest::Pong` before
+    /// the client is treated as gone. Only meaningful when
+    /// `keepalive_interval` is set.
+    pub keepalive_timeout: Duration,
+    /// Caps how large a single incoming message may be (see
+    /// [`crate::channel::Channel::with_max_message_size`]), so a peer that
+    /// sends (or claims to send) an oversized payload is rejected instead of
+    /// growing a connection's receive buffer without bound.
+    pub max_message_size: usize,
+    /// How long [`Server::run`] waits, after signaling every session to
+    /// terminate, for them to actually finish before giving up on the
+    /// stragglers: their [`tokio::task::JoinHandle`]s are aborted and the
+    /// daemon exits anyway, rather than hanging forever on a single wedged
+    /// session.
+    pub shutdown_deadline: Duration,
+    /// A shell command template run (via `sh -c`, on its own task) each
+    /// time a session is created, e.g. to record the new session id
+    /// somewhere or set up a tmux-like environment for it. `{sid}` and
+    /// `{alias}` are substituted with the session's id and alias
+    /// (`{alias}` expands to an empty string for a session with none). A
+    /// non-zero exit or a failure to even spawn is logged and otherwise
+    /// ignored; it never aborts or delays session creation. `None` (the
+    /// default) runs nothing.
+    pub on_create: Option<String>,
+    /// Whether each session gets its own log file under
+    /// `cache_dir()/sessions/<sid>.log`, instead of interleaving its
+    /// diagnostic messages into the shared daemon log. Off by default, to
+    /// match the historical single-log behavior. See
+    /// [`crate::session::Session::spawn`]'s `session_log_dir`.
+    pub per_session_logs: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            shutdown_grace_period: Duration::from_millis(300),
+            allow_takeover: false,
+            handshake_timeout: Duration::from_secs(5),
+            max_sessions: None,
+            max_attached_peers: 1,
+            mirror_queue_capacity: 256,
+            mirror_overflow: OverflowPolicy::DropOldest,
+            terminate_confirm_timeout: Duration::from_secs(10),
+            exit_on_disconnect: false,
+            capture_dir: None,
+            keepalive_interval: None,
+            keepalive_timeout: Duration::from_secs(10),
+            max_message_size: crate::channel::DEFAULT_MAX_MESSAGE_SIZE,
+            shutdown_deadline: Duration::from_secs(5),
+            on_create: None,
+            per_session_logs: false,
+        }
+    }
+}
+
+/// Where [`ServerConfig::per_session_logs`] opens each session's dedicated
+/// log file, shared by [`Server::with_listener`]'s startup cleanup and
+/// [`Server::spawn_session`]'s call into [`Session::spawn`].
+fn session_log_dir() -> PathBuf {
+    helix_loader::cache_dir().join("sessions")
+}
+
+/// Delete per-session log files under [`session_log_dir`] that haven't been
+/// touched in over [`crate::session::STALE_SESSION_LOG_MAX_AGE`], since a
+/// session that logged there is long gone by the time a fresh daemon starts
+/// up. Best-effort: a directory that doesn't exist yet, or a file that can't
+/// be inspected or removed (e.g. a permissions issue), is silently skipped
+/// rather than failing daemon startup over stale housekeeping.
+fn clean_stale_session_logs(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let is_stale = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .map(|modified| modified.elapsed().unwrap_or_default() > crate::session::STALE_SESSION_LOG_MAX_AGE)
+            .unwrap_or(false);
+        if is_stale {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Make sure `dir`, the socket's parent directory, exists and is safe to
+/// bind into. A missing directory is created outright, mode `0700` (nobody
+/// but the current user can even see what's in it, matching the socket
+/// file itself). An existing one is left as-is but rejected with
+/// `PermissionDenied` if it belongs to a different user — bind would
+/// otherwise either fail with a confusing `EACCES`, or worse, succeed into
+/// a directory another user controls.
+#[cfg(not(windows))]
+fn ensure_socket_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    if !dir.exists() {
+        return std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir);
+    }
+
+    let owner = std::fs::metadata(dir)?.uid();
+    let current_user = unsafe { libc::geteuid() };
+    if owner != current_user {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "refusing to bind a socket under {}: owned by uid {owner}, not the current user (uid {current_user})",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn ensure_socket_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)
+}
+
+/// Expand `{sid}` and `{alias}` in an [`ServerConfig::on_create`] template
+/// for [`Server::run_on_create`]. `{alias}` becomes an empty string rather
+/// than being left unexpanded when the session has none.
+fn expand_on_create_template(template: &str, id: SessionId, alias: Option<&str>) -> String {
+    template
+        .replace("{sid}", &id.to_string())
+        .replace("{alias}", alias.unwrap_or(""))
+}
+
+impl ServerConfig {
+    /// The [`MirrorPolicy`] a new session should be spawned with, derived
+    /// from the mirroring-related fields above.
+    fn mirror_policy(&self) -> MirrorPolicy {
+        MirrorPolicy {
+            max_peers: self.max_attached_peers,
+            queue_capacity: self.mirror_queue_capacity,
+            overflow: self.mirror_overflow,
+        }
+    }
+}
+
+/// Capacity of the [`Server::subscribe`] broadcast channel. Subscribers that
+/// fall this far behind lose their oldest unread events rather than stall
+/// the daemon.
+const EVENT_BROADCAST_CAPACITY: usize = 64;
+
+struct SessionHandle {
+    alias: Option<String>,
+    created_at: SystemTime,
+    /// How many clients are currently attached (mirrored). Zero means fully
+    /// detached.
+    attached_peers: usize,
+    cwd: Option<String>,
+    /// Full list of files the session last reported open, as reported via
+    /// `SessionRequest::FilesChanged`. [`SessionSummary::files`] only ever
+    /// gets a truncated, basename-only view of this for display.
+    files: Vec<String>,
+    /// The environment captured from the creating client, see
+    /// `Request::NewSession`. Fixed for the session's lifetime.
+    env: Vec<(String, String)>,
+    /// When the session last went from attached to fully detached. Cleared
+    /// on attach, set on a `ClientDetached` event that leaves no peers.
+    last_detached: Option<SystemTime>,
+    /// The path of this session's output capture file, if capture is
+    /// currently active. See `SessionEventKind::CaptureChanged`.
+    capturing: Option<String>,
+    /// A salted passphrase hash (see `crate::auth::hash_passphrase`), if the
+    /// session is locked. `AttachSession` must supply a matching passphrase
+    /// while this is set. Never the plaintext.
+    lock: Option<String>,
+    /// Arbitrary tags set via `Request::TagSession`, for grouping beyond a
+    /// single alias. Deduplicated, but otherwise unordered.
+    tags: Vec<String>,
+    /// The session's last known terminal size, reported by an attached
+    /// client via `SessionRequest::Resize`. See `SessionEventKind::SizeChanged`.
+    size: Option<(u16, u16)>,
+    /// Per-session idle-reap override set via `Request::SetSessionTimeout`.
+    /// `None` (the default) means this session is never reaped, the same as
+    /// every session before that request existed. See
+    /// [`expired_idle_sessions`].
+    idle_timeout: Option<Duration>,
+    /// Request latency and message throughput counters, mirrored from the
+    /// session task via `SessionEventKind::StatsUpdated`.
+    stats: SessionStats,
+    /// Where this session's own dedicated log file lives, if the daemon was
+    /// started with `ServerConfig::per_session_logs`. Computed once at
+    /// spawn time from the same deterministic path `Session::spawn` opens
+    /// (see `session::session_log_path`), rather than waiting on an event,
+    /// since it never changes for the session's lifetime.
+    log_path: Option<String>,
+    events: mpsc::UnboundedSender<ServerEvent>,
+    task: JoinHandle<()>,
+}
+
+/// How many files [`SessionSummary::files`] shows before truncating; the
+/// full list is only available via the SIGUSR1 state dump.
+const SUMMARY_FILES_LIMIT: usize = 3;
+
+/// Basenames of the first [`SUMMARY_FILES_LIMIT`] entries in `files`, for
+/// display in a listing.
+fn summary_files(files: &[String]) -> Vec<String> {
+    files
+        .iter()
+        .take(SUMMARY_FILES_LIMIT)
+        .map(|path| {
+            std::path::Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.clone())
+        })
+        .collect()
+}
+
+/// The line-by-line body of [`Server::dump_state`]'s `SIGUSR1` log: ids,
+/// aliases, attached/detached state, creation and last-detach timestamps,
+/// the full (untruncated) file list, latency/throughput counters, and the
+/// per-session log path (if any), one session per line, sorted by id for a
+/// stable order. A pure function of the session table so it can be tested
+/// without standing up a real [`Server`] or capturing log output.
+fn session_dump_lines(sessions: &HashMap<SessionId, SessionHandle>) -> Vec<String> {
+    let mut ids: Vec<&SessionId> = sessions.keys().collect();
+    ids.sort();
+    let mut lines = vec![format!("state dump: {} session(s) running", sessions.len())];
+    for id in ids {
+        let handle = &sessions[id];
+        let state = if handle.attached_peers > 0 { "attached" } else { "detached" };
+        lines.push(format!(
+            "session {id}: alias={:?} state={state} created_at={:?} last_detached={:?} cwd={:?} files={:?} \
+             messages_in={} messages_out={} latency_count={} latency_sum_us={} latency_max_us={} log_path={:?}",
+            handle.alias,
+            handle.created_at,
+            handle.last_detached,
+            handle.cwd,
+            handle.files,
+            handle.stats.messages_in,
+            handle.stats.messages_out,
+            handle.stats.latency_count,
+            handle.stats.latency_sum_us,
+            handle.stats.latency_max_us,
+            handle.log_path,
+        ));
+    }
+    lines
+}
+
+/// How often [`Server::run`]'s idle sweep checks for sessions whose
+/// `Request::SetSessionTimeout` override has elapsed. Sessions with no
+/// override are never considered, so this only needs to be frequent enough
+/// that an opted-in session doesn't linger long past its timeout.
+const IDLE_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Which detached sessions' [`SessionHandle::idle_timeout`] override has
+/// elapsed as of `now`. An attached session is never idle regardless of its
+/// override, and a session with no override (the default) is never reaped.
+/// A pure function of the session table so the sweep's decision is testable
+/// without waiting out [`IDLE_SWEEP_INTERVAL`] for real. See
+/// [`Server::sweep_idle_sessions`].
+fn expired_idle_sessions(sessions: &HashMap<SessionId, SessionHandle>, now: SystemTime) -> Vec<SessionId> {
+    sessions
+        .iter()
+        .filter_map(|(id, handle)| {
+            if handle.attached_peers > 0 {
+                return None;
+            }
+            let timeout = handle.idle_timeout?;
+            let elapsed = now.duration_since(handle.last_detached?).ok()?;
+            (elapsed >= timeout).then_some(*id)
+        })
+        .collect()
+}
+
+/// How often [`Server::run`] checks [`Server::pending_kills`] for one that's
+/// waited longer than `ServerConfig::terminate_confirm_timeout` for its
+/// session to actually die. Deliberately much finer-grained than
+/// [`IDLE_SWEEP_INTERVAL`]: a `--kill` caller is waiting on this reply
+/// synchronously, so a coarse sweep would show up as added latency on every
+/// kill instead of just the ones that time out.
+const KILL_CONFIRM_SWEEP_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Order `sessions` by `sort`. A `HashMap` has no inherent order, so every
+/// sort falls back to id as a final tiebreaker to keep the listing stable
+/// across calls.
+fn sort_sessions(sessions: &mut [SessionSummary], sort: SortBy) {
+    match sort {
+        SortBy::Id => sessions.sort_by_key(|s| s.id),
+        SortBy::CreatedAt => {
+            sessions.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)))
+        }
+        SortBy::Alias => sessions.sort_by(|a, b| a.alias.cmp(&b.alias).then(a.id.cmp(&b.id))),
+    }
+}
+
+/// Map a [`SessionEvent`] to the [`proto::SessionListDelta`] it corresponds
+/// to, if any. Most `SessionEventKind` variants don't affect a session
+/// listing (e.g. `CaptureChanged`) and are simply not forwarded to watchers.
+fn session_list_delta(event: &SessionEvent) -> Option<proto::SessionListDelta> {
+    let id = event.sid;
+    match &event.kind {
+        SessionEventKind::Created => Some(proto::SessionListDelta::Created { id }),
+        SessionEventKind::ClientDetached { remaining: 0 } => {
+            Some(proto::SessionListDelta::Detached { id })
+        }
+        SessionEventKind::Terminated { .. } => Some(proto::SessionListDelta::Terminated { id }),
+        SessionEventKind::AliasChanged(alias) => Some(proto::SessionListDelta::Aliased {
+            id,
+            alias: alias.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// A short, stable name for a request variant, for the `connection` tracing
+/// span's `request` field. Deliberately doesn't include any of the
+/// variant's payload (env vars, file paths, passphrases, ...) — the span is
+/// meant to make a connection's log lines greppable, not to duplicate the
+/// request itself into the log.
+fn request_kind(request: &Request) -> &'static str {
+    match request {
+        Request::NewSession { .. } => "new_session",
+        Request::ListSessions { .. } => "list_sessions",
+        Request::AttachSession { .. } => "attach_session",
+        Request::AttachLast { .. } => "attach_last",
+        Request::LockSession { .. } => "lock_session",
+        Request::AttachOrCreate { .. } => "attach_or_create",
+        Request::TagSession { .. } => "tag_session",
+        Request::KillSession { .. } => "kill_session",
+        Request::KillSessions { .. } => "kill_sessions",
+        Request::StopServer => "stop_server",
+        Request::SwapSessions(..) => "swap_sessions",
+        Request::Version => "version",
+        Request::WatchSessions => "watch_sessions",
+        Request::WaitSession(..) => "wait_session",
+        Request::SendToSession { .. } => "send_to_session",
+        Request::Metrics => "metrics",
+        Request::SetLogLevel(..) => "set_log_level",
+        Request::SetSessionTimeout { .. } => "set_session_timeout",
+    }
+}
+
+/// Body of the task spawned for `Request::WatchSessions`: forward relevant
+/// events from `events` down `channel` as `Response::SessionListDelta` until
+/// either the client disconnects or this watcher falls too far behind the
+/// broadcast (see [`EVENT_BROADCAST_CAPACITY`]) and gets disconnected instead
+/// of silently skipping a gap.
+async fn watch_sessions(mut channel: Channel, mut events: broadcast::Receiver<SessionEvent>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => break,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let Some(delta) = session_list_delta(&event) else {
+            continue;
+        };
+        if channel
+            .send(&Response::SessionListDelta(delta))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Body of the task spawned for `Request::WaitSession`: wait for `id`'s
+/// `SessionEventKind::Terminated` on `events` and answer with
+/// `Response::SessionEnded`, or simply exit if `channel`'s peer disconnects
+/// first (its "registration" is nothing more than this task existing).
+/// Several of these can be waiting on the same `id` at once, each with its
+/// own subscription, so every one gets answered once it happens.
+async fn wait_session(mut channel: Channel, mut events: broadcast::Receiver<SessionEvent>, id: SessionId) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => break,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if event.sid != id {
+            continue;
+        }
+        if let SessionEventKind::Terminated { forced } = event.kind {
+            let code = if forced { 1 } else { 0 };
+            let _ = channel.send(&Response::SessionEnded { code, forced }).await;
+            break;
+        }
+    }
+}
+
+/// The path of the state file a [`Server`] persists its session table to,
+/// derived from its socket path.
+fn state_path_for(socket_path: &Path) -> PathBuf {
+    socket_path.with_extension("state.json")
+}
+
+/// Best-effort load of a previous run's session table. Missing or corrupt
+/// state is treated as "nothing to report", not an error: this is bookkeeping,
+/// not a source of truth.
+fn read_state(path: &Path) -> Vec<SessionSummary> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(_) => return Vec::new(),
+    };
+    match serde_json::from_slice::<Vec<SessionSummary>>(&bytes) {
+        Ok(mut sessions) => {
+            for session in &mut sessions {
+                session.stale = true;
+            }
+            sessions
+        }
+        Err(err) => {
+            warn!("failed to parse session state at {}: {err}", path.display());
+            Vec::new()
+        }
+    }
+}
+
+/// Write `sessions` to `path` atomically (tmp file + rename) so a crash
+/// mid-write can never leave a truncated state file behind.
+fn write_state(path: &Path, sessions: &[SessionSummary]) {
+    let tmp = path.with_extension("state.json.tmp");
+    let json = match serde_json::to_vec_pretty(sessions) {
+        Ok(json) => json,
+        Err(err) => {
+            warn!("failed to encode session state: {err}");
+            return;
+        }
+    };
+    if let Err(err) = std::fs::write(&tmp, json) {
+        warn!("failed to write session state: {err}");
+        return;
+    }
+    if let Err(err) = std::fs::rename(&tmp, path) {
+        warn!("failed to persist session state: {err}");
+    }
+}
+
+/// The daemon: owns the listening socket and the table of running sessions.
+pub struct Server {
+    listener: UnixSeqpacketListener,
+    socket_path: Option<PathBuf>,
+    state_path: PathBuf,
+    config: ServerConfig,
+    sessions: HashMap<SessionId, SessionHandle>,
+    /// Sessions from before the daemon's last restart, loaded from the state
+    /// file at startup. Cleared once served to a client via `all: true`.
+    stale: Vec<SessionSummary>,
+    next_id: SessionId,
+    events_tx: mpsc::UnboundedSender<SessionEvent>,
+    events_rx: mpsc::UnboundedReceiver<SessionEvent>,
+    /// High-level session lifecycle events, for embedders. Distinct from
+    /// `events_tx`/`events_rx`, which is the internal session-task-to-server
+    /// plumbing this is fed from.
+    broadcast_tx: broadcast::Sender<SessionEvent>,
+    /// Where to re-read hot-reloadable settings from on `SIGHUP`. Unset by
+    /// default: a daemon started without [`Self::watch_config`] just logs
+    /// and ignores `SIGHUP`.
+    config_path: Option<PathBuf>,
+    run: bool,
+    /// Total sessions ever spawned, including ones since terminated. Only
+    /// ever grows; see [`Self::metrics`] for the currently-live counts.
+    sessions_created: u64,
+    /// Connections dropped before a request could even be read: a recv
+    /// error, or one that never sent anything within
+    /// `ServerConfig::handshake_timeout`. See [`Self::metrics`].
+    connection_errors: u64,
+    /// Sessions that have fully terminated, split into clean vs. forced by
+    /// [`SessionEventKind::Terminated`]'s own `forced` flag. See
+    /// [`Self::metrics`].
+    sessions_terminated: u64,
+    sessions_force_terminated: u64,
+    /// Total attach/detach events over the daemon's lifetime, as opposed to
+    /// `sessions_attached`/`sessions_detached` in [`Self::metrics`], which
+    /// are live counts recomputed from the session table. Attaching or
+    /// detaching the same session repeatedly keeps incrementing these.
+    attaches_total: u64,
+    detaches_total: u64,
+    /// `listener.accept()` returning an error, e.g. the process running out
+    /// of file descriptors. Distinct from `connection_errors`, which only
+    /// counts connections that *were* accepted but never produced a usable
+    /// request. See [`Self::metrics`].
+    accept_errors: u64,
+    /// A monotonically increasing id handed out one per
+    /// [`Self::handle_connection`] call, for the `connection` tracing span.
+    /// The wire protocol has no notion of a peer pid to tag a connection
+    /// with, so this is the closest cheap, always-available substitute for
+    /// telling one connection's log lines apart from another's.
+    next_connection_id: u64,
+    /// When this daemon process started, for `uptime_seconds` in
+    /// [`Self::metrics`] and `hxd --status`.
+    started_at: SystemTime,
+    /// Channels awaiting a reply to `Request::KillSession`, keyed by the
+    /// session they asked to terminate. Held here rather than answered
+    /// immediately so the reply reflects the session actually dying (see
+    /// [`Self::handle_event`]'s `Terminated` arm) rather than just the
+    /// termination request having been enqueued; swept for ones that waited
+    /// too long by [`Self::sweep_pending_kills`].
+    pending_kills: HashMap<SessionId, Vec<(Channel, SystemTime)>>,
+    /// Channels awaiting a reply to `Request::StopServer`, answered with
+    /// `Response::Stopped` once [`Self::drain_remaining_sessions`] finishes
+    /// in [`Self::run`] rather than immediately, so the reply reflects every
+    /// session actually having terminated (or been given up on).
+    stop_replies: Vec<Channel>,
+}
+
+impl Server {
+    pub fn new(socket_path: Option<PathBuf>, config: ServerConfig) -> Result<Self> {
+        let path = proto::resolve_socket_path(socket_path.as_deref());
+        // Remove a stale socket left behind by a previous, uncleanly-terminated run.
+        let _ = crate::retry::retry_eintr(|| std::fs::remove_file(&path));
+        if let Some(parent) = path.parent() {
+            crate::retry::retry_eintr(|| ensure_socket_dir(parent))?;
+        }
+        let listener = UnixSeqpacketListener::bind(&path)?;
+        Self::with_listener(listener, Some(path), config)
+    }
+
+    /// Build a server around an already-bound listener, e.g. one handed over
+    /// by systemd socket activation (`LISTEN_FDS`). Unlike [`Self::new`], the
+    /// socket file is assumed to be owned by whoever bound it: it is never
+    /// removed, either up front or on [`Self::cleanup`].
+    pub fn from_listener(listener: UnixSeqpacketListener, config: ServerConfig) -> Result<Self> {
+        Self::with_listener(listener, None, config)
+    }
+
+    /// Bind in the Linux abstract namespace instead of the filesystem, under
+    /// `name`. There is no socket file to remove on shutdown, or for a stale
+    /// prior run to leave behind. Linux-only; fails clearly elsewhere.
+    pub fn bind_abstract(name: &str, config: ServerConfig) -> Result<Self> {
+        let listener = crate::socket::bind(name)?;
+        Self::with_listener(listener, None, config)
+    }
+
+    fn with_listener(
+        listener: UnixSeqpacketListener,
+        socket_path: Option<PathBuf>,
+        config: ServerConfig,
+    ) -> Result<Self> {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (broadcast_tx, _) = broadcast::channel(EVENT_BROADCAST_CAPACITY);
+
+        if config.per_session_logs {
+            clean_stale_session_logs(&session_log_dir());
+        }
+
+        let state_path = match &socket_path {
+            Some(path) => state_path_for(path),
+            None => helix_loader::cache_dir().join("hxd.state.json"),
+        };
+        let stale = read_state(&state_path);
+        for session in &stale {
+            info!(
+                "session {} ({}) was lost in the previous daemon run",
+                session.id,
+                session.alias.as_deref().unwrap_or("unnamed")
+            );
+        }
+
+        Ok(Self {
+            listener,
+            socket_path,
+            state_path,
+            config,
+            sessions: HashMap::new(),
+            stale,
+            next_id: 1,
+            events_tx,
+            events_rx,
+            broadcast_tx,
+            config_path: None,
+            run: true,
+            sessions_created: 0,
+            connection_errors: 0,
+            sessions_terminated: 0,
+            sessions_force_terminated: 0,
+            attaches_total: 0,
+            detaches_total: 0,
+            accept_errors: 0,
+            next_connection_id: 0,
+            started_at: SystemTime::now(),
+            pending_kills: HashMap::new(),
+            stop_replies: Vec::new(),
+        })
+    }
+
+    /// Re-read hot-reloadable settings from `path` whenever the daemon
+    /// receives `SIGHUP`, instead of ignoring the signal.
+    pub fn watch_config(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+    /// Subscribe to high-level session lifecycle events (created, attached,
+    /// detached, terminated), for programs embedding [`Server`] directly.
+    /// Lagging subscribers silently drop their oldest unread events rather
+    /// than block the daemon; see [`broadcast::Receiver::recv`].
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.broadcast_tx.subscribe()
+    }
+
+    /// Re-read [`Config`] from `config_path` and apply its hot-reloadable
+    /// fields. Fields the config format doesn't cover (the socket path,
+    /// activation mode, ...) can only be changed by restarting the daemon.
+    fn reload_config(&mut self) {
+        let Some(path) = self.config_path.clone() else {
+            info!("received SIGHUP but no config file is configured; ignoring");
+            return;
+        };
+        match Config::load(&path) {
+            Ok(new_config) => {
+                if let Some(max_sessions) = new_config.max_sessions {
+                    self.config.max_sessions = Some(max_sessions);
+                }
+                info!(
+                    "reloaded config from {} (socket path and activation mode are not reloadable)",
+                    path.display()
+                );
+            }
+            Err(err) => warn!("failed to reload config from {}: {err}", path.display()),
+        }
+    }
+
+    /// Overwrite the state file with the current live session table.
+    fn persist_state(&self) {
+        let sessions: Vec<SessionSummary> = self
+            .sessions
+            .iter()
+            .map(|(id, handle)| SessionSummary {
+                id: *id,
+                alias: handle.alias.clone(),
+                created_at: handle.created_at,
+                attached: handle.attached_peers > 0,
+                cwd: handle.cwd.clone(),
+                files: summary_files(&handle.files),
+                last_detached: handle.last_detached,
+                stale: false,
+                env: handle.env.clone(),
+                capturing: handle.capturing.clone(),
+                locked: handle.lock.is_some(),
+                tags: handle.tags.clone(),
+                size: handle.size,
+                stats: handle.stats,
+                log_path: handle.log_path.clone(),
+            })
+            .collect();
+        write_state(&self.state_path, &sessions);
+    }
+
+    pub async fn run(mut self) -> Result<()> {
+        use futures_util::StreamExt;
+
+        #[cfg(not(windows))]
+        let mut signals = Signals::new([
+            signal::SIGTERM,
+            signal::SIGINT,
+            signal::SIGHUP,
+            signal::SIGUSR1,
+        ])
+        .map_err(|err| crate::error::Error::Other(err.into()))?;
+        #[cfg(windows)]
+        let mut signals: Signals = futures_util::stream::empty();
+
+        let mut idle_sweep = tokio::time::interval(IDLE_SWEEP_INTERVAL);
+        let mut kill_confirm_sweep = tokio::time::interval(KILL_CONFIRM_SWEEP_INTERVAL);
+
+        while self.run {
+            tokio::select! {
+                accepted = self.listener.accept() => {
+                    match accepted {
+                        Ok(conn) => {
+                            let channel = Channel::with_max_message_size(conn, self.config.max_message_size);
+                            self.handle_connection(channel).await
+                        }
+                        Err(err) => {
+                            warn!("accept error: {err}");
+                            self.accept_errors += 1;
+                        }
+                    }
+                }
+                event = self.events_rx.recv() => {
+                    match event {
+                        Some(event) => self.handle_event(event).await,
+                        // `self` always holds its own `events_tx` alongside
+                        // `events_rx`, so this shouldn't happen in practice;
+                        // if it ever did, `Some(event) = ...`'s pattern would
+                        // simply stop matching every iteration, spinning the
+                        // loop instead of noticing anything was wrong.
+                        None => {
+                            warn!("session event channel closed unexpectedly; shutting down");
+                            self.begin_shutdown("daemon shutting down due to an internal error")
+                                .await;
+                        }
+                    }
+                }
+                Some(signal) = signals.next() => {
+                    self.handle_signal(signal).await;
+                }
+                _ = idle_sweep.tick() => {
+                    self.sweep_idle_sessions();
+                }
+                _ = kill_confirm_sweep.tick() => {
+                    self.sweep_pending_kills().await;
+                }
+            }
+        }
+
+        let (clean, forced, failed) = self.drain_remaining_sessions().await;
+        for mut channel in self.stop_replies.drain(..) {
+            let _ = channel
+                .send(&Response::Stopped { clean, forced, failed: failed.clone() })
+                .await;
+        }
+        self.cleanup();
+        Ok(())
+    }
+
+    /// Give any session still running when the main loop exits (i.e. one
+    /// [`begin_shutdown`](Self::begin_shutdown) already asked to terminate)
+    /// up to `shutdown_deadline` to actually finish, by continuing to drain
+    /// `events_rx` — the same path [`Self::handle_event`]'s
+    /// `SessionEventKind::Terminated` arm normally uses to join a session's
+    /// task. Anything still outstanding once the deadline passes is logged
+    /// and its task aborted rather than left to block the daemon from
+    /// exiting indefinitely.
+    ///
+    /// Returns how many sessions terminated cleanly (`forced: false`) vs.
+    /// forcefully (`forced: true`) per their own `SessionEventKind::Terminated`,
+    /// plus the ids of any that had to be aborted, for `Self::run` to answer
+    /// any `Request::StopServer` callers waiting in [`Self::stop_replies`].
+    async fn drain_remaining_sessions(&mut self) -> (u64, u64, Vec<SessionId>) {
+        let mut clean = 0u64;
+        let mut forced = 0u64;
+        if self.sessions.is_empty() {
+            return (clean, forced, Vec::new());
+        }
+        let deadline = tokio::time::sleep(self.config.shutdown_deadline);
+        tokio::pin!(deadline);
+        while !self.sessions.is_empty() {
+            tokio::select! {
+                Some(event) = self.events_rx.recv() => {
+                    if let SessionEventKind::Terminated { forced: was_forced } = &event.kind {
+                        if *was_forced { forced += 1 } else { clean += 1 }
+                    }
+                    self.handle_event(event).await;
+                }
+                _ = &mut deadline => break,
+                else => break,
+            }
+        }
+        let failed: Vec<SessionId> = self.sessions.keys().copied().collect();
+        for (id, handle) in self.sessions.drain() {
+            warn!("session {id} did not terminate within the shutdown deadline; aborting its task");
+            handle.task.abort();
+        }
+        (clean, forced, failed)
+    }
+
+    /// Spawn a brand-new session task and register it, once the caller has
+    /// already sent whatever response tells the client it was created (the
+    /// response has to go out before `channel` is handed to `Session::spawn`).
+    /// Shared by `Request::NewSession` and the create branch of
+    /// `Request::AttachOrCreate`.
+    fn spawn_session(
+        &mut self,
+        id: SessionId,
+        alias: Option<String>,
+        channel: Channel,
+        env: Vec<(String, String)>,
+        cwd: String,
+        files: Vec<FileSpec>,
+    ) {
+        let log_dir = self.config.per_session_logs.then(session_log_dir);
+        let log_path = log_dir
+            .as_deref()
+            .map(|dir| crate::session::session_log_path(dir, id).to_string_lossy().into_owned());
+        let (events, task) = Session::spawn(
+            id,
+            alias.clone(),
+            channel,
+            self.config.mirror_policy(),
+            self.config.terminate_confirm_timeout,
+            self.config.exit_on_disconnect,
+            self.config.capture_dir.clone(),
+            self.config.keepalive_interval,
+            self.config.keepalive_timeout,
+            log_dir,
+            self.events_tx.clone(),
+        );
+        self.run_on_create(id, alias.as_deref());
+        self.sessions.insert(
+            id,
+            SessionHandle {
+                alias,
+                created_at: SystemTime::now(),
+                attached_peers: 1,
+                // Empty means the client couldn't read its own cwd (e.g. it
+                // was removed out from under the process); treat that the
+                // same as never having reported one.
+                cwd: (!cwd.is_empty()).then_some(cwd),
+                files: files.into_iter().map(|f| f.path).collect(),
+                env,
+                last_detached: None,
+                capturing: None,
+                lock: None,
+                tags: Vec::new(),
+                size: None,
+                idle_timeout: None,
+                stats: SessionStats::default(),
+                log_path,
+                events,
+                task,
+            },
+        );
+        info!("session {id} created");
+        self.sessions_created += 1;
+        self.persist_state();
+        let _ = self.broadcast_tx.send(SessionEvent {
+            sid: id,
+            kind: SessionEventKind::Created,
+        });
+    }
+
+    /// Fire [`ServerConfig::on_create`], if set, for a session that was just
+    /// spawned. Runs on its own task so a slow or hanging hook can't delay
+    /// handing the new session back to its client; a non-zero exit or a
+    /// failure to even spawn is logged and otherwise ignored.
+    fn run_on_create(&self, id: SessionId, alias: Option<&str>) {
+        let Some(template) = &self.config.on_create else {
+            return;
+        };
+        let command = expand_on_create_template(template, id, alias);
+        tokio::spawn(async move {
+            match tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .status()
+                .await
+            {
+                Ok(status) if !status.success() => {
+                    warn!("on_create hook {command:?} exited with {status}");
+                }
+                Ok(_) => {}
+                Err(err) => warn!("failed to run on_create hook {command:?}: {err}"),
+            }
+        });
+    }
+
+    /// A snapshot of the daemon's running counters, also exposed to clients
+    /// via `Request::Metrics` (`hxc --metrics`) and, in Prometheus textfile
+    /// form, `hxc --stats`. Attached/detached *counts* are recomputed from
+    /// the live session table rather than tracked separately, so they can't
+    /// drift from reality the way a pair of increment/decrement counters
+    /// could; `attaches_total`/`detaches_total` below are the corresponding
+    /// lifetime *event* counts, which a gauge can't give you. The rest are
+    /// monotonic totals that only make sense tracked over the daemon's
+    /// lifetime.
+    ///
+    /// Returned as a plain name -> value map rather than a fixed struct so
+    /// new counters can be added freely without a wire-format bump; see
+    /// `zen3ger/helix#synth-100`.
+    pub fn metrics(&self) -> BTreeMap<String, u64> {
+        let attached = self
+            .sessions
+            .values()
+            .filter(|h| h.attached_peers > 0)
+            .count() as u64;
+        let (bytes_sent, bytes_received) = crate::channel::byte_totals();
+        let uptime_seconds = self
+            .started_at
+            .elapsed()
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let started_at_unix = self
+            .started_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        BTreeMap::from([
+            ("sessions_created_total".into(), self.sessions_created),
+            ("sessions_live".into(), self.sessions.len() as u64),
+            ("sessions_attached".into(), attached),
+            ("sessions_detached".into(), self.sessions.len() as u64 - attached),
+            ("sessions_terminated_total".into(), self.sessions_terminated),
+            (
+                "sessions_force_terminated_total".into(),
+                self.sessions_force_terminated,
+            ),
+            ("attaches_total".into(), self.attaches_total),
+            ("detaches_total".into(), self.detaches_total),
+            // Malformed/oversized requests and stalled handshakes; see the
+            // field's own doc comment. Named `connection_errors` rather than
+            // `protocol_errors` since it also counts connections that never
+            // sent anything at all, not just ones that sent something the
+            // protocol rejected.
+            ("connection_errors_total".into(), self.connection_errors),
+            ("accept_errors_total".into(), self.accept_errors),
+            ("bytes_sent_total".into(), bytes_sent),
+            ("bytes_received_total".into(), bytes_received),
+            ("uptime_seconds".into(), uptime_seconds),
+            ("started_at_unix".into(), started_at_unix),
+        ])
+    }
+
+    /// Shared body of `Request::AttachSession` and `Request::AttachLast`,
+    /// once each has settled on a target `id`. `passphrase` is checked
+    /// against the session's stored hash, if it's locked (see
+    /// `Request::LockSession`), before anything else happens.
+    async fn attach_to(
+        &mut self,
+        channel: Channel,
+        id: SessionId,
+        takeover: bool,
+        passphrase: Option<String>,
+    ) {
+        match self.sessions.get(&id) {
+            Some(handle) => {
+                if let Some(hash) = &handle.lock {
+                    let ok = passphrase
+                        .as_deref()
+                        .is_some_and(|attempt| crate::auth::verify_passphrase(hash, attempt));
+                    if !ok {
+                        let _ = channel
+                            .send(&Response::Err("incorrect or missing passphrase".into()))
+                            .await;
+                        return;
+                    }
+                }
+            }
+            None => {
+                let _ = channel
+                    .send(&Response::Err("no such session".into()))
+                    .await;
+                return;
+            }
+        }
+        match self.sessions.get_mut(&id) {
+            Some(handle) if handle.attached_peers < self.config.max_attached_peers => {
+                let response = Response::Attached {
+                    id,
+                    alias: handle.alias.clone(),
+                };
+                if channel.send(&response).await.is_err() {
+                    return;
+                }
+                handle.attached_peers += 1;
+                handle.last_detached = None;
+                self.attaches_total += 1;
+                let _ = handle.events.send(ServerEvent::Attach {
+                    channel,
+                    takeover: false,
+                    alias: handle.alias.clone(),
+                    cwd: handle.cwd.clone(),
+                    files: handle.files.clone(),
+                    size: handle.size,
+                });
+            }
+            Some(handle) if takeover && self.config.allow_takeover => {
+                let response = Response::Attached {
+                    id,
+                    alias: handle.alias.clone(),
+                };
+                if channel.send(&response).await.is_err() {
+                    return;
+                }
+                // Net peer count is unchanged: the session evicts its
+                // oldest peer to make room for this one.
+                handle.last_detached = None;
+                let _ = handle.events.send(ServerEvent::Attach {
+                    channel,
+                    takeover: true,
+                    alias: handle.alias.clone(),
+                    cwd: handle.cwd.clone(),
+                    files: handle.files.clone(),
+                    size: handle.size,
+                });
+            }
+            Some(_) => {
+                let _ = channel
+                    .send(&Response::Err("session is occupied".into()))
+                    .await;
+            }
+            None => {
+                let _ = channel
+                    .send(&Response::Err("no such session".into()))
+                    .await;
+            }
+        }
+    }
+
+    /// Resolve a `Request::SendToSession`-style reference: a numeric id if
+    /// `sid_or_alias` parses as one and names a live session, falling back to
+    /// an exact alias match (same precedence `Request::AttachOrCreate` uses
+    /// for its own alias lookups).
+    fn resolve_session_ref(&self, sid_or_alias: &str) -> Option<SessionId> {
+        if let Ok(id) = sid_or_alias.parse::<SessionId>() {
+            if self.sessions.contains_key(&id) {
+                return Some(id);
+            }
+        }
+        self.sessions
+            .iter()
+            .find(|(_, handle)| handle.alias.as_deref() == Some(sid_or_alias))
+            .map(|(id, _)| *id)
+    }
+
+    /// Entry point for a freshly accepted connection: assigns it a
+    /// `connection` tracing span (see [`request_kind`]) so every log line
+    /// this connection's first exchange produces can be grepped out
+    /// together, then hands off to [`Self::handle_connection_body`].
+    async fn handle_connection(&mut self, channel: Channel) {
+        let connection_id = self.next_connection_id;
+        self.next_connection_id += 1;
+        let span = tracing::info_span!("connection", connection_id, request = tracing::field::Empty);
+        self.handle_connection_body(channel).instrument(span).await;
+    }
+
+    /// Read and answer requests off `channel` until either the peer
+    /// disconnects or a request that needs the whole connection to itself
+    /// (see [`Self::dispatch_request`]) is handled. Several plain
+    /// request/response requests (`hxc --list`, `--alias`, `--kill`, etc.)
+    /// can therefore share one connection instead of paying a fresh connect
+    /// per operation — see `zen3ger/helix#synth-103`.
+    async fn handle_connection_body(&mut self, mut channel: Channel) {
+        loop {
+            let request = match tokio::time::timeout(
+                self.config.handshake_timeout,
+                channel.recv::<Request>(),
+            )
+            .await
+            {
+                Ok(Ok(Some(request))) => request,
+                Ok(Ok(None)) => return,
+                Ok(Err(err)) => {
+                    tracing::warn!("error reading request: {err}");
+                    self.connection_errors += 1;
+                    // A malformed/oversized request (e.g. `Error::MessageTooLarge`)
+                    // deserves a reply the peer can act on, not just a silently
+                    // closed connection it has to guess the reason for.
+                    let _ = channel.send(&Response::Err(err.to_string())).await;
+                    return;
+                }
+                Err(_) => {
+                    tracing::warn!(
+                        "closing connection that sent no request within {:?}",
+                        self.config.handshake_timeout
+                    );
+                    self.connection_errors += 1;
+                    return;
+                }
+            };
+            tracing::Span::current().record("request", request_kind(&request));
+
+            // The same `handshake_timeout` that bounds each `recv` also
+            // bounds sending back its response, applied fresh per request
+            // rather than once for the whole connection: a peer that stops
+            // reading right after connecting (or mid-way through a longer
+            // session of requests) shouldn't be able to wedge a
+            // `channel.send` open indefinitely either.
+            match tokio::time::timeout(
+                self.config.handshake_timeout,
+                self.dispatch_request(channel, request),
+            )
+            .await
+            {
+                // Still safe to read another request off the same channel.
+                Ok(Some(next)) => channel = next,
+                // Handed off to something else that will answer it later (a
+                // spawned session/attach/watch task, or
+                // `Request::KillSession`'s entry in `pending_kills`), or the
+                // connection is otherwise done with.
+                Ok(None) => return,
+                Err(_) => {
+                    tracing::warn!(
+                        "closing connection that didn't finish an exchange within {:?}",
+                        self.config.handshake_timeout
+                    );
+                    self.connection_errors += 1;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Handle one request. Returns `channel` back if it's still open and
+    /// safe to read a further request from — every arm that only ever sends
+    /// a single synchronous reply does this, letting
+    /// [`Self::handle_connection_body`] loop several requests over one
+    /// connection. Requests that instead hand `channel` to a session, an
+    /// attach, a background watch/wait task, or a deferred reply queue
+    /// (`pending_kills`/`stop_replies`) return `None`: those need exclusive
+    /// ownership of the channel for as long as they run, or answer out of
+    /// band later, so there's nothing left here to read further requests
+    /// from.
+    async fn dispatch_request(
+        &mut self,
+        mut channel: Channel,
+        request: Request,
+    ) -> Option<Channel> {
+        match request {
+            Request::NewSession { env, cwd, files } => {
+                if let Some(max) = self.config.max_sessions {
+                    if self.sessions.len() >= max {
+                        let _ = channel
+                            .send(&Response::Err(format!(
+                                "session limit reached ({max} max)"
+                            )))
+                            .await;
+                        return None;
+                    }
+                }
+                let id = self.next_id;
+                self.next_id += 1;
+                if channel.send(&Response::SessionCreated { id }).await.is_err() {
+                    return None;
+                }
+                self.spawn_session(id, None, channel, env, cwd, files);
+                None
+            }
+            Request::ListSessions { sort, all, tag } => {
+                let mut sessions: Vec<SessionSummary> = self
+                    .sessions
+                    .iter()
+                    .filter(|(_, handle)| match &tag {
+                        Some(tag) => handle.tags.contains(tag),
+                        None => true,
+                    })
+                    .map(|(id, handle)| SessionSummary {
+                        id: *id,
+                        alias: handle.alias.clone(),
+                        created_at: handle.created_at,
+                        attached: handle.attached_peers > 0,
+                        cwd: handle.cwd.clone(),
+                        files: summary_files(&handle.files),
+                        last_detached: handle.last_detached,
+                        stale: false,
+                        env: handle.env.clone(),
+                        capturing: handle.capturing.clone(),
+                        locked: handle.lock.is_some(),
+                        tags: handle.tags.clone(),
+                        size: handle.size,
+                        stats: handle.stats,
+                        log_path: handle.log_path.clone(),
+                    })
+                    .collect();
+                if all && tag.is_none() {
+                    // Acknowledged: the caller now knows about these, so drop them
+                    // from memory rather than repeating them on every future list.
+                    // Stale entries predate the daemon's last restart and never
+                    // carried tags to begin with, so a tag filter simply excludes
+                    // them rather than trying to match against one.
+                    sessions.extend(self.stale.drain(..));
+                }
+                sort_sessions(&mut sessions, sort);
+                let _ = channel.send(&Response::Sessions(sessions)).await;
+                Some(channel)
+            }
+            Request::AttachSession {
+                id,
+                takeover,
+                passphrase,
+            } => {
+                self.attach_to(channel, id, takeover, passphrase).await;
+                None
+            }
+            Request::AttachLast { takeover } => {
+                let target = self
+                    .sessions
+                    .iter()
+                    .filter(|(_, handle)| handle.attached_peers == 0 && handle.lock.is_none())
+                    .max_by_key(|(_, handle)| handle.last_detached.unwrap_or(handle.created_at))
+                    .map(|(id, _)| *id);
+                match target {
+                    Some(id) => self.attach_to(channel, id, takeover, None).await,
+                    None => {
+                        let _ = channel
+                            .send(&Response::Err("no detached sessions".into()))
+                            .await;
+                    }
+                }
+                None
+            }
+            Request::LockSession {
+                id,
+                passphrase_hash,
+            } => {
+                match self.sessions.get_mut(&id) {
+                    Some(handle) if handle.attached_peers == 0 => {
+                        handle.lock = passphrase_hash;
+                        let _ = channel.send(&Response::Ok).await;
+                        self.persist_state();
+                    }
+                    Some(_) => {
+                        let _ = channel
+                            .send(&Response::Err("session must be detached to lock".into()))
+                            .await;
+                    }
+                    None => {
+                        let _ = channel
+                            .send(&Response::Err("no such session".into()))
+                            .await;
+                    }
+                }
+                Some(channel)
+            }
+            Request::AttachOrCreate {
+                alias,
+                env,
+                cwd,
+                files,
+            } => {
+                let existing = self
+                    .sessions
+                    .iter()
+                    .find(|(_, handle)| handle.alias.as_deref() == Some(alias.as_str()))
+                    .map(|(id, _)| *id);
+                match existing {
+                    Some(id) => {
+                        let Some(handle) = self.sessions.get(&id) else {
+                            let _ = channel.send(&Response::Err("no such session".into())).await;
+                            return None;
+                        };
+                        if handle.lock.is_some() {
+                            let _ = channel.send(&Response::Err("session is locked".into())).await;
+                            return None;
+                        }
+                        if handle.attached_peers >= self.config.max_attached_peers {
+                            let _ = channel
+                                .send(&Response::Err("session is occupied".into()))
+                                .await;
+                            return None;
+                        }
+                        let response = Response::AttachedOrCreated {
+                            id,
+                            created: false,
+                        };
+                        if channel.send(&response).await.is_err() {
+                            return None;
+                        }
+                        // `channel` is already committed to this session by the
+                        // response just sent above, so a session that vanished
+                        // during that await can only drop it rather than fall
+                        // back to creating a fresh one.
+                        let Some(handle) = self.sessions.get_mut(&id) else {
+                            warn!("session {id} disappeared before it could be attached");
+                            return None;
+                        };
+                        handle.attached_peers += 1;
+                        handle.last_detached = None;
+                        self.attaches_total += 1;
+                        let _ = handle.events.send(ServerEvent::Attach {
+                            channel,
+                            takeover: false,
+                            alias: handle.alias.clone(),
+                            cwd: handle.cwd.clone(),
+                            files: handle.files.clone(),
+                            size: handle.size,
+                        });
+                    }
+                    None => {
+                        if let Some(max) = self.config.max_sessions {
+                            if self.sessions.len() >= max {
+                                let _ = channel
+                                    .send(&Response::Err(format!(
+                                        "session limit reached ({max} max)"
+                                    )))
+                                    .await;
+                                return None;
+                            }
+                        }
+                        let id = self.next_id;
+                        self.next_id += 1;
+                        let response = Response::AttachedOrCreated { id, created: true };
+                        if channel.send(&response).await.is_err() {
+                            return None;
+                        }
+                        self.spawn_session(id, Some(alias), channel, env, cwd, files);
+                    }
+                }
+                None
+            }
+            Request::TagSession { id, add, remove } => {
+                match self.sessions.get_mut(&id) {
+                    Some(handle) => {
+                        handle.tags.retain(|tag| !remove.contains(tag));
+                        for tag in add {
+                            if !handle.tags.contains(&tag) {
+                                handle.tags.push(tag);
+                            }
+                        }
+                        let _ = channel.send(&Response::Ok).await;
+                        self.persist_state();
+                    }
+                    None => {
+                        let _ = channel
+                            .send(&Response::Err("no such session".into()))
+                            .await;
+                    }
+                }
+                Some(channel)
+            }
+            Request::KillSession { id, force } => {
+                match self.sessions.get(&id) {
+                    Some(handle) => {
+                        let _ = handle.events.send(ServerEvent::Terminate(force));
+                        // Answered once the session actually dies (see the
+                        // `Terminated` arm of `handle_event`) rather than here,
+                        // so the caller can't observe the session as still alive
+                        // right after `Response::Ok` comes back.
+                        self.pending_kills
+                            .entry(id)
+                            .or_default()
+                            .push((channel, SystemTime::now()));
+                    }
+                    None => {
+                        let _ = channel
+                            .send(&Response::Err("no such session".into()))
+                            .await;
+                    }
+                }
+                None
+            }
+            Request::KillSessions { ids, force } => {
+                let results = ids
+                    .into_iter()
+                    .map(|id| KillResult {
+                        id,
+                        error: match self.sessions.get(&id) {
+                            Some(handle) => {
+                                let _ = handle.events.send(ServerEvent::Terminate(force));
+                                None
+                            }
+                            None => Some("no such session".into()),
+                        },
+                    })
+                    .collect();
+                let _ = channel.send(&Response::KillResults(results)).await;
+                Some(channel)
+            }
+            Request::SwapSessions(a, b) => {
+                if a == b {
+                    let _ = channel.send(&Response::Ok).await;
+                    return Some(channel);
+                }
+                if !self.sessions.contains_key(&a) || !self.sessions.contains_key(&b) {
+                    let _ = channel
+                        .send(&Response::Err("no such session".into()))
+                        .await;
+                    return Some(channel);
+                }
+                let Some(handle_a) = self.sessions.remove(&a) else {
+                    let _ = channel.send(&Response::Err("no such session".into())).await;
+                    return Some(channel);
+                };
+                let Some(handle_b) = self.sessions.remove(&b) else {
+                    self.sessions.insert(a, handle_a);
+                    let _ = channel.send(&Response::Err("no such session".into())).await;
+                    return Some(channel);
+                };
+                let _ = handle_a.events.send(ServerEvent::Reassign(b));
+                let _ = handle_b.events.send(ServerEvent::Reassign(a));
+                self.sessions.insert(b, handle_a);
+                self.sessions.insert(a, handle_b);
+                info!("swapped sessions {a} and {b}");
+                self.persist_state();
+                let _ = channel.send(&Response::Ok).await;
+                Some(channel)
+            }
+            Request::StopServer => {
+                // `begin_shutdown` itself only signals every session and
+                // waits out `shutdown_grace_period`, well within
+                // `handshake_timeout`; the reply is held in
+                // `stop_replies` and sent once `Self::drain_remaining_sessions`
+                // (called from `Self::run` after the main loop exits) knows
+                // every session has actually finished, rather than racing it.
+                self.begin_shutdown("daemon shutting down").await;
+                self.stop_replies.push(channel);
+                None
+            }
+            Request::Version => {
+                let _ = channel
+                    .send(&Response::Version {
+                        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+                        proto_version: PROTO_VERSION,
+                    })
+                    .await;
+                Some(channel)
+            }
+            Request::WatchSessions => {
+                if channel.send(&Response::Ok).await.is_err() {
+                    return None;
+                }
+                // Handed off to its own task rather than looped over here,
+                // since `handle_connection` runs inline in `Server::run`'s
+                // select loop and would otherwise stall every other
+                // connection and event for as long as the watcher stays
+                // open. The task isn't tracked in `self` anywhere else (it
+                // isn't a session), so it cleans itself up simply by exiting
+                // once a send fails, i.e. once the client disconnects.
+                let events = self.subscribe();
+                tokio::spawn(watch_sessions(channel, events));
+                None
+            }
+            Request::WaitSession(id) => {
+                // Subscribe before checking existence, so a termination
+                // racing this request can't slip in between the check and
+                // the subscription and be missed.
+                let events = self.subscribe();
+                if !self.sessions.contains_key(&id) {
+                    let _ = channel.send(&Response::Err("no such session".into())).await;
+                    return None;
+                }
+                tokio::spawn(wait_session(channel, events, id));
+                None
+            }
+            Request::SendToSession { sid_or_alias, payload } => {
+                let Some(id) = self.resolve_session_ref(&sid_or_alias) else {
+                    let _ = channel
+                        .send(&Response::Err("no such session".into()))
+                        .await;
+                    return Some(channel);
+                };
+                let Some(handle) = self.sessions.get(&id) else {
+                    let _ = channel.send(&Response::Err("no such session".into())).await;
+                    return Some(channel);
+                };
+                if handle.attached_peers == 0 {
+                    let _ = channel
+                        .send(&Response::Err("session is detached".into()))
+                        .await;
+                    return Some(channel);
+                }
+                let _ = handle.events.send(ServerEvent::Deliver(payload));
+                let _ = channel.send(&Response::Ok).await;
+                Some(channel)
+            }
+            Request::Metrics => {
+                let _ = channel.send(&Response::Metrics(self.metrics())).await;
+                Some(channel)
+            }
+            Request::SetLogLevel(verbosity) => {
+                crate::logging::set_level(verbosity);
+                let _ = channel.send(&Response::Ok).await;
+                Some(channel)
+            }
+            Request::SetSessionTimeout { id, timeout } => {
+                match self.sessions.get_mut(&id) {
+                    Some(handle) => {
+                        handle.idle_timeout = timeout;
+                        let _ = channel.send(&Response::Ok).await;
+                    }
+                    None => {
+                        let _ = channel
+                            .send(&Response::Err("no such session".into()))
+                            .await;
+                    }
+                }
+                Some(channel)
+            }
+        }
+    }
+
+    async fn handle_event(&mut self, event: SessionEvent) {
+        let _ = self.broadcast_tx.send(event.clone());
+
+        match event.kind {
+            // Never actually routed through here; the server emits it directly
+            // in `spawn_session` since no session task exists yet.
+            SessionEventKind::Created => {}
+            SessionEventKind::Attached => {}
+            SessionEventKind::ClientDetached { remaining } => {
+                if let Some(handle) = self.sessions.get_mut(&event.sid) {
+                    handle.attached_peers = remaining;
+                    if remaining == 0 {
+                        handle.last_detached = Some(SystemTime::now());
+                    }
+                }
+                self.detaches_total += 1;
+                self.persist_state();
+            }
+            SessionEventKind::CwdChanged(cwd) => {
+                if let Some(handle) = self.sessions.get_mut(&event.sid) {
+                    handle.cwd = Some(cwd);
+                }
+                self.persist_state();
+            }
+            SessionEventKind::FilesChanged(files) => {
+                if let Some(handle) = self.sessions.get_mut(&event.sid) {
+                    handle.files = files;
+                }
+                self.persist_state();
+            }
+            SessionEventKind::AliasChanged(alias) => {
+                if let Some(handle) = self.sessions.get_mut(&event.sid) {
+                    handle.alias = Some(alias);
+                }
+                self.persist_state();
+            }
+            SessionEventKind::CaptureChanged(path) => {
+                if let Some(handle) = self.sessions.get_mut(&event.sid) {
+                    handle.capturing = path;
+                }
+                self.persist_state();
+            }
+            SessionEventKind::SizeChanged { rows, cols } => {
+                if let Some(handle) = self.sessions.get_mut(&event.sid) {
+                    handle.size = Some((rows, cols));
+                }
+                self.persist_state();
+            }
+            // Doesn't call `persist_state`: these change on every request a
+            // session handles, and the state file only needs to reflect
+            // fields a restart would want to recover, not live counters.
+            SessionEventKind::StatsUpdated(stats) => {
+                if let Some(handle) = self.sessions.get_mut(&event.sid) {
+                    handle.stats = stats;
+                }
+            }
+            SessionEventKind::Terminated { forced } => {
+                if let Some(handle) = self.sessions.remove(&event.sid) {
+                    let _ = handle.task.await;
+                }
+                if forced {
+                    self.sessions_force_terminated += 1;
+                } else {
+                    self.sessions_terminated += 1;
+                }
+                info!("session {} terminated (forced: {forced})", event.sid);
+                self.persist_state();
+                if let Some(waiters) = self.pending_kills.remove(&event.sid) {
+                    for (mut channel, _) in waiters {
+                        let _ = channel.send(&Response::Ok).await;
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(not(windows))]
+    async fn handle_signal(&mut self, signal: i32) {
+        match signal {
+            signal::SIGTERM | signal::SIGINT => {
+                info!("received shutdown signal");
+                self.begin_shutdown("daemon shutting down").await;
+            }
+            signal::SIGHUP => self.reload_config(),
+            signal::SIGUSR1 => self.dump_state(),
+            _ => {}
+        }
+    }
+
+    #[cfg(windows)]
+    async fn handle_signal(&mut self, _signal: i32) {}
+
+    /// Log the full state of every running session, untruncated. Unlike
+    /// [`SessionSummary::files`] (trimmed for `hxc --list`), this is meant to
+    /// be read straight out of the daemon's log file for debugging. See
+    /// `SIGUSR1` in [`Self::handle_signal`].
+    fn dump_state(&self) {
+        for line in session_dump_lines(&self.sessions) {
+            info!("{line}");
+        }
+    }
+
+    /// Terminate every session [`expired_idle_sessions`] reports, on the
+    /// [`IDLE_SWEEP_INTERVAL`] tick in [`Self::run`]. Graceful (`force:
+    /// false`), the same as an ordinary `hxc --kill` without `-f`.
+    fn sweep_idle_sessions(&mut self) {
+        for id in expired_idle_sessions(&self.sessions, SystemTime::now()) {
+            info!("session {id} idle-timed-out; terminating");
+            if let Some(handle) = self.sessions.get(&id) {
+                let _ = handle.events.send(ServerEvent::Terminate(false));
+            }
+        }
+    }
+
+    /// Answer any `Request::KillSession` reply still waiting in
+    /// [`Self::pending_kills`] once it has waited longer than
+    /// `ServerConfig::terminate_confirm_timeout` for its session to
+    /// terminate, on the
+    /// [`KILL_CONFIRM_SWEEP_INTERVAL`] tick in [`Self::run`]. A cooperative
+    /// session is answered promptly instead, from the `Terminated` arm of
+    /// [`Self::handle_event`] — this only catches the stuck ones.
+    async fn sweep_pending_kills(&mut self) {
+        let now = SystemTime::now();
+        let timeout = self.config.terminate_confirm_timeout;
+        for waiters in self.pending_kills.values_mut() {
+            let mut still_waiting = Vec::new();
+            for (mut channel, requested_at) in waiters.drain(..) {
+                match now.duration_since(requested_at) {
+                    Ok(elapsed) if elapsed >= timeout => {
+                        let _ = channel
+                            .send(&Response::Err(
+                                "kill requested but session still shutting down".into(),
+                            ))
+                            .await;
+                    }
+                    _ => still_waiting.push((channel, requested_at)),
+                }
+            }
+            *waiters = still_waiting;
+        }
+        self.pending_kills.retain(|_, waiters| !waiters.is_empty());
+    }
+
+    /// Notify every session's attached client of an impending shutdown, give
+    /// them a short window to see it, then terminate all sessions.
+    async fn begin_shutdown(&mut self, notice: &str) {
+        for handle in self.sessions.values() {
+            let _ = handle.events.send(ServerEvent::Notify(notice.to_string()));
+        }
+        tokio::time::sleep(self.config.shutdown_grace_period).await;
+        for handle in self.sessions.values() {
+            let _ = handle.events.send(ServerEvent::Terminate(true));
+        }
+        self.run = false;
+    }
+
+    fn cleanup(&self) {
+        if let Some(path) = &self.socket_path {
+            if let Err(err) = crate::retry::retry_eintr(|| std::fs::remove_file(path)) {
+                warn!("failed to unlink socket file {}: {err}", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::error::{ClientError, Error};
+    use std::os::unix::io::AsRawFd;
+
+    fn summary(id: SessionId, alias: &str, created_at: SystemTime) -> SessionSummary {
+        SessionSummary {
+            id,
+            alias: Some(alias.to_string()),
+            created_at,
+            attached: false,
+            cwd: None,
+            files: Vec::new(),
+            last_detached: None,
+            stale: false,
+            env: Vec::new(),
+            capturing: None,
+            locked: false,
+            tags: Vec::new(),
+            size: None,
+            stats: SessionStats::default(),
+            log_path: None,
+        }
+    }
+
+    /// Build a [`SessionHandle`] with otherwise-default fields, for
+    /// [`session_dump_lines`] tests that don't need a real session task
+    /// behind it.
+    fn session_handle(alias: Option<&str>, created_at: SystemTime, attached_peers: usize) -> SessionHandle {
+        let (events, _rx) = mpsc::unbounded_channel();
+        SessionHandle {
+            alias: alias.map(String::from),
+            created_at,
+            attached_peers,
+            cwd: None,
+            files: Vec::new(),
+            env: Vec::new(),
+            last_detached: None,
+            capturing: None,
+            lock: None,
+            tags: Vec::new(),
+            size: None,
+            idle_timeout: None,
+            stats: SessionStats::default(),
+            log_path: None,
+            events,
+            task: tokio::spawn(async {}),
+        }
+    }
+
+    #[test]
+    fn session_dump_lines_reports_zero_sessions() {
+        assert_eq!(
+            session_dump_lines(&HashMap::new()),
+            vec!["state dump: 0 session(s) running"]
+        );
+    }
+
+    #[tokio::test]
+    async fn session_dump_lines_reports_each_session_sorted_by_id() {
+        let mut sessions = HashMap::new();
+        sessions.insert(2, session_handle(Some("b"), SystemTime::UNIX_EPOCH, 0));
+        sessions.insert(1, session_handle(Some("a"), SystemTime::UNIX_EPOCH, 1));
+
+        let lines = session_dump_lines(&sessions);
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "state dump: 2 session(s) running");
+        assert!(lines[1].starts_with("session 1:"));
+        assert!(lines[1].contains("state=attached"));
+        assert!(lines[1].contains(r#"alias=Some("a")"#));
+        assert!(lines[1].contains("messages_in=0"));
+        assert!(lines[2].starts_with("session 2:"));
+        assert!(lines[2].contains("state=detached"));
+        assert!(lines[2].contains(r#"alias=Some("b")"#));
+    }
+
+    #[test]
+    fn ensure_socket_dir_creates_a_missing_directory_with_mode_0700() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("nested").join("sockets");
+        assert!(!target.exists());
+
+        ensure_socket_dir(&target).unwrap();
+
+        let metadata = std::fs::metadata(&target).unwrap();
+        assert!(metadata.is_dir());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+    }
+
+    #[test]
+    fn clean_stale_session_logs_removes_only_files_older_than_the_max_age() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let fresh = dir.path().join("1.log");
+        let stale = dir.path().join("2.log");
+        std::fs::write(&fresh, "fresh").unwrap();
+        std::fs::write(&stale, "stale").unwrap();
+
+        // `filetime`-style backdating isn't a dependency here, so backdate
+        // via `utime` directly rather than sleeping past the (multi-day)
+        // staleness threshold in a test.
+        let old = std::time::SystemTime::now() - crate::session::STALE_SESSION_LOG_MAX_AGE - Duration::from_secs(60);
+        let old_ts = old.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as libc::time_t;
+        let path = std::ffi::CString::new(stale.as_os_str().as_bytes()).unwrap();
+        let times = [
+            libc::timeval { tv_sec: old_ts, tv_usec: 0 },
+            libc::timeval { tv_sec: old_ts, tv_usec: 0 },
+        ];
+        unsafe { libc::utimes(path.as_ptr(), times.as_ptr()) };
+
+        clean_stale_session_logs(dir.path());
+
+        assert!(fresh.exists(), "a freshly-written log shouldn't be cleaned up");
+        assert!(!stale.exists(), "a log untouched for longer than the max age should be removed");
+    }
+
+    #[test]
+    fn clean_stale_session_logs_tolerates_a_missing_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        clean_stale_session_logs(&dir.path().join("does-not-exist"));
+    }
+
+    #[test]
+    fn ensure_socket_dir_rejects_a_directory_owned_by_someone_else() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        // Actually reassign ownership rather than mocking the uid check, so
+        // this exercises the real `stat` call `ensure_socket_dir` makes.
+        // `chown` only succeeds for the superuser, so skip on a machine
+        // where this test can't set up its own premise.
+        let path = std::ffi::CString::new(dir.path().as_os_str().as_bytes()).unwrap();
+        let other_uid = 65534; // conventionally "nobody"
+        let chowned = unsafe { libc::chown(path.as_ptr(), other_uid, libc::gid_t::MAX) };
+        if chowned != 0 {
+            eprintln!("skipping: not privileged to chown {:?}", dir.path());
+            return;
+        }
+
+        let err = ensure_socket_dir(dir.path()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn sigusr1_dump_state_is_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("dump.sock");
+        let mut server = Server::new(Some(socket), ServerConfig::default()).unwrap();
+        server
+            .sessions
+            .insert(1, session_handle(Some("work"), SystemTime::now(), 1));
+
+        server.handle_signal(signal::SIGUSR1).await;
+
+        assert_eq!(server.sessions.len(), 1);
+        assert_eq!(server.sessions[&1].alias.as_deref(), Some("work"));
+    }
+
+    #[tokio::test]
+    async fn expired_idle_sessions_never_reaps_a_session_with_no_override() {
+        let mut sessions = HashMap::new();
+        let mut never = session_handle(Some("forever"), SystemTime::now(), 0);
+        never.last_detached = Some(SystemTime::now() - Duration::from_secs(3600));
+        sessions.insert(1, never);
+
+        assert_eq!(expired_idle_sessions(&sessions, SystemTime::now()), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn expired_idle_sessions_reaps_one_past_its_shortened_override() {
+        let mut sessions = HashMap::new();
+        let mut short = session_handle(Some("ephemeral"), SystemTime::now(), 0);
+        short.idle_timeout = Some(Duration::from_secs(60));
+        short.last_detached = Some(SystemTime::now() - Duration::from_secs(120));
+        sessions.insert(1, short);
+
+        let mut patient = session_handle(Some("not-yet"), SystemTime::now(), 0);
+        patient.idle_timeout = Some(Duration::from_secs(3600));
+        patient.last_detached = Some(SystemTime::now() - Duration::from_secs(120));
+        sessions.insert(2, patient);
+
+        assert_eq!(expired_idle_sessions(&sessions, SystemTime::now()), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn expired_idle_sessions_ignores_an_attached_session_regardless_of_override() {
+        let mut sessions = HashMap::new();
+        let mut attached = session_handle(Some("busy"), SystemTime::now(), 1);
+        attached.idle_timeout = Some(Duration::from_secs(1));
+        attached.last_detached = Some(SystemTime::now() - Duration::from_secs(3600));
+        sessions.insert(1, attached);
+
+        assert_eq!(expired_idle_sessions(&sessions, SystemTime::now()), Vec::new());
+    }
+
+    #[tokio::test]
+    async fn sweep_idle_sessions_terminates_only_the_expired_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("sweep.sock");
+        let mut server = Server::new(Some(socket), ServerConfig::default()).unwrap();
+
+        let mut expired = session_handle(Some("ephemeral"), SystemTime::now(), 0);
+        expired.idle_timeout = Some(Duration::from_secs(60));
+        expired.last_detached = Some(SystemTime::now() - Duration::from_secs(120));
+        let (expired_events, mut expired_rx) = mpsc::unbounded_channel();
+        expired.events = expired_events;
+        server.sessions.insert(1, expired);
+
+        let mut patient = session_handle(Some("forever"), SystemTime::now(), 0);
+        patient.last_detached = Some(SystemTime::now() - Duration::from_secs(120));
+        let (patient_events, mut patient_rx) = mpsc::unbounded_channel();
+        patient.events = patient_events;
+        server.sessions.insert(2, patient);
+
+        server.sweep_idle_sessions();
+
+        assert!(matches!(
+            expired_rx.try_recv(),
+            Ok(ServerEvent::Terminate(false))
+        ));
+        assert!(patient_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn state_survives_a_write_read_roundtrip_marked_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hxd.state.json");
+        let sessions = vec![summary(1, "alice", SystemTime::UNIX_EPOCH)];
+
+        write_state(&path, &sessions);
+        let loaded = read_state(&path);
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].id, 1);
+        assert!(loaded[0].stale);
+    }
+
+    #[tokio::test]
+    async fn from_listener_serves_a_pre_bound_socket_and_never_unlinks_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("activated.sock");
+        let listener = UnixSeqpacketListener::bind(&socket).unwrap();
+
+        let server = Server::from_listener(listener, ServerConfig::default()).unwrap();
+        assert!(server.socket_path.is_none());
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        client.new_session().await.unwrap();
+
+        // A server that doesn't own the socket path must not have unlinked it
+        // as part of starting up.
+        assert!(socket.exists());
+    }
+
+    #[tokio::test]
+    async fn from_listener_drives_a_full_new_list_kill_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("injected.sock");
+        let listener = UnixSeqpacketListener::bind(&socket).unwrap();
+
+        let server = Server::from_listener(listener, ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let session = client.new_session().await.unwrap();
+        let id = session.id;
+
+        let mut lister = Client::connect(Some(socket.clone())).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert_eq!(sessions.iter().map(|s| s.id).collect::<Vec<_>>(), vec![id]);
+
+        let mut killer = Client::connect(Some(socket.clone())).await.unwrap();
+        killer.kill_session(id, true).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert!(sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn kill_session_wait_returns_only_once_the_session_is_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let session = client.new_session().await.unwrap();
+        let id = session.id;
+
+        let mut killer = Client::connect(Some(socket.clone())).await.unwrap();
+        killer
+            .kill_session_wait(id, true, Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        // No settling sleep here, unlike the plain `kill_session` case above:
+        // `kill_session_wait` is the whole point.
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert!(sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn kill_session_replies_only_once_the_session_has_terminated() {
+        // Unlike `from_listener_drives_a_full_new_list_kill_cycle` above,
+        // this asserts the session is already gone with no settling sleep:
+        // `Response::Ok` is now only sent from the `Terminated` arm of
+        // `Server::handle_event`, once the session has actually died.
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let session = client.new_session().await.unwrap();
+        let id = session.id;
+
+        let mut killer = Client::connect(Some(socket.clone())).await.unwrap();
+        killer.kill_session(id, true).await.unwrap();
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert!(sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn kill_session_reports_a_timeout_if_the_session_never_terminates() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("stuck.sock");
+        let listener = UnixSeqpacketListener::bind(&socket).unwrap();
+        let config = ServerConfig {
+            terminate_confirm_timeout: Duration::from_millis(50),
+            ..ServerConfig::default()
+        };
+        let mut server = Server::from_listener(listener, config).unwrap();
+
+        let (events_tx, _events_rx) = mpsc::unbounded_channel();
+        // Simulates a session whose task ignores termination and never
+        // emits `SessionEventKind::Terminated`, rather than a real
+        // `Session` (which would need a wedged child process to reproduce
+        // the same thing).
+        let stuck_task = tokio::spawn(std::future::pending::<()>());
+        server.sessions.insert(
+            1,
+            SessionHandle {
+                alias: None,
+                created_at: SystemTime::now(),
+                attached_peers: 0,
+                cwd: None,
+                files: Vec::new(),
+                env: Vec::new(),
+                last_detached: None,
+                capturing: None,
+                lock: None,
+                tags: Vec::new(),
+                size: None,
+                idle_timeout: None,
+                stats: SessionStats::default(),
+                log_path: None,
+                events: events_tx,
+                task: stuck_task,
+            },
+        );
+
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut killer = Client::connect(Some(socket)).await.unwrap();
+        let err = killer.kill_session(1, true).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Session(ClientError::Server(ref m)) if m == "kill requested but session still shutting down"
+        ));
+    }
+
+    #[tokio::test]
+    async fn metrics_reflect_sessions_created_and_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("metrics.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut requester = Client::connect(Some(socket.clone())).await.unwrap();
+        let before = requester.metrics().await.unwrap();
+        assert_eq!(before["sessions_created_total"], 0);
+        assert_eq!(before["sessions_live"], 0);
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let session = client.new_session().await.unwrap();
+        let id = session.id;
+
+        let mut requester = Client::connect(Some(socket.clone())).await.unwrap();
+        let during = requester.metrics().await.unwrap();
+        assert_eq!(during["sessions_created_total"], 1);
+        assert_eq!(during["sessions_live"], 1);
+        assert_eq!(during["sessions_attached"], 1);
+        assert_eq!(during["sessions_detached"], 0);
+        assert_eq!(during["attaches_total"], 1);
+
+        let mut killer = Client::connect(Some(socket.clone())).await.unwrap();
+        killer
+            .kill_session_wait(id, true, Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        let mut requester = Client::connect(Some(socket)).await.unwrap();
+        let after = requester.metrics().await.unwrap();
+        assert_eq!(after["sessions_created_total"], 1);
+        assert_eq!(after["sessions_live"], 0);
+        assert_eq!(after["sessions_force_terminated_total"], 1);
+        assert_eq!(after["sessions_terminated_total"], 0);
+    }
+
+    #[tokio::test]
+    async fn metrics_reports_uptime_seconds() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("uptime.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut requester = Client::connect(Some(socket)).await.unwrap();
+        let metrics = requester.metrics().await.unwrap();
+        assert!(metrics.contains_key("uptime_seconds"));
+        assert!(metrics.contains_key("started_at_unix"));
+        assert!(metrics["started_at_unix"] > 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn bind_abstract_serves_sessions_over_the_abstract_namespace() {
+        // Include the pid so repeated/parallel `cargo test` runs don't
+        // collide on the same abstract name.
+        let name = format!("hxd-test-server-{}", std::process::id());
+
+        let server = Server::bind_abstract(&name, ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect_abstract(&name).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        session.detach().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn subscribe_observes_the_full_session_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let mut events = server.subscribe();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        let id = session.id;
+        session.terminate().await.unwrap();
+        session.run().await.unwrap();
+
+        let mut kinds = Vec::new();
+        for _ in 0..2 {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(event.sid, id);
+            kinds.push(event.kind);
+        }
+        assert!(matches!(kinds[0], SessionEventKind::Created));
+        assert!(matches!(kinds[1], SessionEventKind::Terminated { forced: false }));
+    }
+
+    /// A duplicate `Terminated` (e.g. a forced kill racing the session's own
+    /// exit, both reporting the same id) must not panic: the second
+    /// delivery finds nothing left in `self.sessions` to remove, same as
+    /// this test's id, which was never inserted at all.
+    #[tokio::test]
+    async fn duplicate_terminated_event_is_a_graceful_no_op() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let mut server = Server::new(Some(socket), ServerConfig::default()).unwrap();
+
+        let event = SessionEvent {
+            sid: 1,
+            kind: SessionEventKind::Terminated { forced: false },
+        };
+        server.handle_event(event.clone()).await;
+        server.handle_event(event).await;
+    }
+
+    #[tokio::test]
+    async fn wait_session_reports_after_the_session_terminates() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        let id = session.id;
+        session.detach().await.unwrap();
+
+        let waiter = Client::connect(Some(socket.clone())).await.unwrap();
+        let wait_task = tokio::spawn(async move { waiter.wait_session(id).await.unwrap() });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut killer = Client::connect(Some(socket.clone())).await.unwrap();
+        killer.kill_sessions(vec![id], true).await.unwrap();
+
+        let (code, forced) = tokio::time::timeout(Duration::from_secs(1), wait_task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(code, 1);
+        assert!(forced);
+    }
+
+    #[tokio::test]
+    async fn wait_session_rejects_a_session_that_does_not_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let err = client.wait_session(9999).await.unwrap_err();
+        assert!(matches!(err, Error::Session(ClientError::SessionNotFound)));
+    }
+
+    #[tokio::test]
+    async fn send_to_session_rejects_an_unknown_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut client = Client::connect(Some(socket.clone())).await.unwrap();
+        let err = client
+            .send_to_session("9999".into(), ":write-all".into())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Session(ClientError::SessionNotFound)));
+    }
+
+    #[tokio::test]
+    async fn send_to_session_rejects_a_detached_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        let id = session.id;
+        session.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut sender = Client::connect(Some(socket.clone())).await.unwrap();
+        let err = sender
+            .send_to_session(id.to_string(), ":write-all".into())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Session(ClientError::Server(ref m)) if m == "session is detached"
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_to_session_resolves_an_alias_to_an_attached_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let mut events = server.subscribe();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        session.set_alias("work".into()).await.unwrap();
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .expect("session never reported the alias change")
+                .unwrap();
+            if matches!(event.kind, SessionEventKind::AliasChanged(ref a) if a == "work") {
+                break;
+            }
+        }
+
+        let mut sender = Client::connect(Some(socket.clone())).await.unwrap();
+        sender
+            .send_to_session("work".into(), ":write-all".into())
+            .await
+            .unwrap();
+
+        session.terminate().await.unwrap();
+        let code = session.run().await.unwrap();
+        assert_eq!(code, crate::client::exit_code::NORMAL);
+    }
+
+    #[tokio::test]
+    async fn watch_sessions_streams_the_session_list_deltas() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let watcher_client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut watcher = watcher_client.watch_sessions().await.unwrap();
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        let id = session.id;
+        session.set_alias("scratch".to_string()).await.unwrap();
+        session.terminate().await.unwrap();
+        session.run().await.unwrap();
+
+        let mut deltas = Vec::new();
+        for _ in 0..3 {
+            let delta = tokio::time::timeout(Duration::from_secs(1), watcher.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            deltas.push(delta);
+        }
+        assert!(matches!(deltas[0], proto::SessionListDelta::Created { id: got } if got == id));
+        assert!(
+            matches!(&deltas[1], proto::SessionListDelta::Aliased { id: got, alias } if *got == id && alias == "scratch")
+        );
+        assert!(matches!(deltas[2], proto::SessionListDelta::Terminated { id: got } if got == id));
+    }
+
+    #[tokio::test]
+    async fn a_new_session_reports_its_captured_env_in_list_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        // `PATH` is one of the allowlisted vars and always set for a running
+        // process, so it's a reliable way to check the round trip without
+        // depending on any var this test process doesn't control.
+        let path = std::env::var("PATH").unwrap();
+        assert!(sessions[0]
+            .env
+            .iter()
+            .any(|(key, value)| key == "PATH" && value == &path));
+
+        session.detach().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_new_session_records_the_creating_clients_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        let expected = std::env::current_dir().unwrap().to_string_lossy().into_owned();
+        assert_eq!(sessions[0].cwd.as_deref(), Some(expected.as_str()));
+
+        session.detach().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn on_create_runs_for_every_new_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let marker = dir.path().join("created.txt");
+        let config = ServerConfig {
+            on_create: Some(format!("echo {{sid}} > {}", marker.display())),
+            ..ServerConfig::default()
+        };
+        let server = Server::new(Some(socket.clone()), config).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket)).await.unwrap();
+        let session = client.new_session().await.unwrap();
+
+        // The hook runs on its own task, so give it a moment to finish
+        // rather than racing the `sh -c` invocation.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let contents = std::fs::read_to_string(&marker).unwrap();
+        assert_eq!(contents.trim(), session.id.to_string());
+    }
+
+    #[test]
+    fn expand_on_create_template_substitutes_sid_and_alias() {
+        assert_eq!(
+            expand_on_create_template("echo {sid} {alias}", 3, Some("work")),
+            "echo 3 work"
+        );
+        assert_eq!(expand_on_create_template("echo {sid} {alias}", 3, None), "echo 3 ");
+    }
+
+    #[tokio::test]
+    async fn set_alias_is_reflected_in_list_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let mut events = server.subscribe();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        session.set_alias("scratch".into()).await.unwrap();
+
+        // Wait for the rename to actually land before listing, rather than
+        // racing the session task on a sleep.
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .expect("session never reported the alias change")
+                .unwrap();
+            if matches!(event.kind, SessionEventKind::AliasChanged(ref a) if a == "scratch") {
+                break;
+            }
+        }
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].alias.as_deref(), Some("scratch"));
+    }
+
+    #[tokio::test]
+    async fn swapping_two_sessions_exchanges_their_ids_and_aliases() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client_a = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session_a = client_a.new_session().await.unwrap();
+        session_a.set_alias("first".into()).await.unwrap();
+
+        let client_b = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session_b = client_b.new_session().await.unwrap();
+        session_b.set_alias("second".into()).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut swapper = Client::connect(Some(socket.clone())).await.unwrap();
+        swapper
+            .swap_sessions(session_a.id, session_b.id)
+            .await
+            .unwrap();
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        let swapped_a = sessions.iter().find(|s| s.id == session_b.id).unwrap();
+        let swapped_b = sessions.iter().find(|s| s.id == session_a.id).unwrap();
+        assert_eq!(swapped_a.alias.as_deref(), Some("first"));
+        assert_eq!(swapped_b.alias.as_deref(), Some("second"));
+    }
+
+    #[tokio::test]
+    async fn swapping_a_session_with_itself_is_a_no_op() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let session = client.new_session().await.unwrap();
+
+        let mut swapper = Client::connect(Some(socket)).await.unwrap();
+        swapper
+            .swap_sessions(session.id, session.id)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn set_capture_is_reflected_in_list_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let capture_dir = dir.path().join("captures");
+        let config = ServerConfig {
+            capture_dir: Some(capture_dir.clone()),
+            ..ServerConfig::default()
+        };
+        let server = Server::new(Some(socket.clone()), config).unwrap();
+        let mut events = server.subscribe();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        let id = session.id;
+        session.set_capture(true).await.unwrap();
+
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+                .await
+                .expect("session never reported capture turning on")
+                .unwrap();
+            if matches!(event.kind, SessionEventKind::CaptureChanged(Some(_))) {
+                break;
+            }
+        }
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        let summary = sessions.iter().find(|s| s.id == id).unwrap();
+        assert_eq!(
+            summary.capturing.as_deref(),
+            Some(capture_dir.join(format!("session-{id}.log")).to_str().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn a_silent_client_is_detached_once_the_keepalive_deadline_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let config = ServerConfig {
+            keepalive_interval: Some(Duration::from_millis(30)),
+            keepalive_timeout: Duration::from_millis(30),
+            ..ServerConfig::default()
+        };
+        let server = Server::new(Some(socket.clone()), config).unwrap();
+        let mut events = server.subscribe();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket)).await.unwrap();
+        let session = client.new_session().await.unwrap();
+        let id = session.id;
+        // Never drive `session.run()`, so it never answers the daemon's
+        // pings: the connection is open but silent, like a client whose
+        // machine vanished.
+
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(2), events.recv())
+                .await
+                .expect("session never reported the silent client as detached")
+                .unwrap();
+            if event.sid == id && matches!(event.kind, SessionEventKind::ClientDetached { remaining: 0 })
+            {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn read_state_tolerates_a_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("does-not-exist.json");
+        assert!(read_state(&path).is_empty());
+    }
+
+    #[tokio::test]
+    async fn sighup_reload_applies_a_new_max_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let config_path = dir.path().join("hxd.toml");
+        std::fs::write(&config_path, "").unwrap();
+
+        let mut server = Server::new(Some(socket.clone()), ServerConfig::default())
+            .unwrap()
+            .watch_config(config_path.clone());
+        assert_eq!(server.config.max_sessions, None);
+
+        std::fs::write(&config_path, "max_sessions = 1\n").unwrap();
+        server.reload_config();
+        assert_eq!(server.config.max_sessions, Some(1));
+
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let first = Client::connect(Some(socket.clone()))
+            .await
+            .unwrap()
+            .new_session()
+            .await
+            .unwrap();
+        assert_eq!(first.id, 1);
+
+        let rejected = Client::connect(Some(socket.clone()))
+            .await
+            .unwrap()
+            .new_session()
+            .await;
+        assert!(rejected.is_err());
+    }
+
+    #[test]
+    fn sort_sessions_orders_by_key_with_id_tiebreak() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let t1 = t0 + Duration::from_secs(1);
+
+        let mut sessions = vec![
+            summary(3, "alice", t1),
+            summary(1, "charlie", t0),
+            summary(2, "bob", t0),
+        ];
+
+        sort_sessions(&mut sessions, SortBy::Id);
+        assert_eq!(
+            sessions.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+
+        sort_sessions(&mut sessions, SortBy::CreatedAt);
+        // 1 and 2 share t0 and must fall back to id; 3 sorts last with t1.
+        assert_eq!(
+            sessions.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+
+        sort_sessions(&mut sessions, SortBy::Alias);
+        assert_eq!(
+            sessions.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![3, 2, 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn takeover_detaches_previous_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let config = ServerConfig {
+            allow_takeover: true,
+            ..ServerConfig::default()
+        };
+        let server = Server::new(Some(socket.clone()), config).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut first = client.new_session().await.unwrap();
+        let id = first.id;
+
+        let second = Client::connect(Some(socket.clone())).await.unwrap();
+        let _second = second.attach_session(id, true).await.unwrap();
+
+        let code = first.run().await.unwrap();
+        assert_eq!(code, crate::client::exit_code::DETACHED);
+    }
+
+    #[tokio::test]
+    async fn a_client_reported_exit_code_propagates_end_to_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        session.terminate_with_code(42).await.unwrap();
+
+        let code = session.run().await.unwrap();
+        assert_eq!(code, 42);
+    }
+
+    #[tokio::test]
+    async fn a_second_client_can_mirror_an_attached_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let config = ServerConfig {
+            max_attached_peers: 2,
+            ..ServerConfig::default()
+        };
+        let server = Server::new(Some(socket.clone()), config).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut first = client.new_session().await.unwrap();
+        let id = first.id;
+
+        let second = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut second = second.attach_session(id, false).await.unwrap();
+
+        let killer = Client::connect(Some(socket.clone())).await.unwrap();
+        killer.kill_session(id, false).await.unwrap();
+
+        let first_code = first.run().await.unwrap();
+        let second_code = second.run().await.unwrap();
+        assert_eq!(first_code, crate::client::exit_code::NORMAL);
+        assert_eq!(second_code, crate::client::exit_code::NORMAL);
+    }
+
+    #[tokio::test]
+    async fn a_third_client_is_rejected_once_max_attached_peers_is_reached() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let config = ServerConfig {
+            max_attached_peers: 2,
+            ..ServerConfig::default()
+        };
+        let server = Server::new(Some(socket.clone()), config).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let first = client.new_session().await.unwrap();
+        let id = first.id;
+
+        let second = Client::connect(Some(socket.clone())).await.unwrap();
+        let _second = second.attach_session(id, false).await.unwrap();
+
+        let third = Client::connect(Some(socket.clone())).await.unwrap();
+        let rejected = third.attach_session(id, false).await;
+        assert!(rejected.is_err());
+    }
+
+    #[tokio::test]
+    async fn last_detached_is_set_once_fully_detached_and_cleared_on_reattach() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        let id = session.id;
+        session.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut lister = Client::connect(Some(socket.clone())).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        let summary = sessions.iter().find(|s| s.id == id).unwrap();
+        assert!(!summary.attached);
+        assert!(summary.last_detached.is_some());
+
+        let reattach = Client::connect(Some(socket.clone())).await.unwrap();
+        let _reattached = reattach.attach_session(id, false).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut lister = Client::connect(Some(socket.clone())).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        let summary = sessions.iter().find(|s| s.id == id).unwrap();
+        assert!(summary.attached);
+        assert!(summary.last_detached.is_none());
+    }
+
+    #[tokio::test]
+    async fn attach_last_picks_the_most_recently_detached_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let older = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut older = older.new_session().await.unwrap();
+        let older_id = older.id;
+        older.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let newer = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut newer = newer.new_session().await.unwrap();
+        let newer_id = newer.id;
+        newer.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let attacher = Client::connect(Some(socket.clone())).await.unwrap();
+        let (session, _alias) = attacher.attach_last(false).await.unwrap();
+        assert_eq!(session.id, newer_id);
+        assert_ne!(session.id, older_id);
+    }
+
+    #[tokio::test]
+    async fn attach_last_fails_with_no_detached_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let attacher = Client::connect(Some(socket.clone())).await.unwrap();
+        let err = attacher.attach_last(false).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Session(crate::error::ClientError::NoDetachedSessions)
+        ));
+    }
+
+    #[tokio::test]
+    async fn locked_session_accepts_the_correct_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let owner = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = owner.new_session().await.unwrap();
+        let id = session.id;
+        session.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut locker = Client::connect(Some(socket.clone())).await.unwrap();
+        locker
+            .lock_session(id, Some(crate::auth::hash_passphrase("hunter2")))
+            .await
+            .unwrap();
+
+        let attacher = Client::connect(Some(socket.clone())).await.unwrap();
+        attacher
+            .attach_session(id, false, Some("hunter2".into()))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn locked_session_rejects_an_incorrect_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let owner = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = owner.new_session().await.unwrap();
+        let id = session.id;
+        session.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut locker = Client::connect(Some(socket.clone())).await.unwrap();
+        locker
+            .lock_session(id, Some(crate::auth::hash_passphrase("hunter2")))
+            .await
+            .unwrap();
+
+        let attacher = Client::connect(Some(socket.clone())).await.unwrap();
+        let err = attacher
+            .attach_session(id, false, Some("wrong".into()))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Session(crate::error::ClientError::WrongPassphrase)
+        ));
+    }
+
+    #[tokio::test]
+    async fn locked_session_rejects_a_missing_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let owner = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = owner.new_session().await.unwrap();
+        let id = session.id;
+        session.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut locker = Client::connect(Some(socket.clone())).await.unwrap();
+        locker
+            .lock_session(id, Some(crate::auth::hash_passphrase("hunter2")))
+            .await
+            .unwrap();
+
+        let attacher = Client::connect(Some(socket.clone())).await.unwrap();
+        let err = attacher.attach_session(id, false, None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Session(crate::error::ClientError::WrongPassphrase)
+        ));
+    }
+
+    #[tokio::test]
+    async fn attach_last_skips_a_locked_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // Detach the one that will stay unlocked first, so it's the *older*
+        // detach — if the locked filter didn't work, `attach_last` would
+        // wrongly prefer the more-recently-detached locked session instead.
+        let unlocked_owner = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut unlocked = unlocked_owner.new_session().await.unwrap();
+        let unlocked_id = unlocked.id;
+        unlocked.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let locked_owner = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut locked = locked_owner.new_session().await.unwrap();
+        let locked_id = locked.id;
+        locked.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut locker = Client::connect(Some(socket.clone())).await.unwrap();
+        locker
+            .lock_session(locked_id, Some(crate::auth::hash_passphrase("hunter2")))
+            .await
+            .unwrap();
+
+        let attacher = Client::connect(Some(socket.clone())).await.unwrap();
+        let (session, _alias) = attacher.attach_last(false).await.unwrap();
+        assert_eq!(session.id, unlocked_id);
+    }
+
+    #[tokio::test]
+    async fn attach_or_create_creates_a_session_the_first_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let (session, created) = client.attach_or_create("work".into()).await.unwrap();
+        assert!(created);
+
+        let mut lister = Client::connect(Some(socket.clone())).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        let summary = sessions.iter().find(|s| s.id == session.id).unwrap();
+        assert_eq!(summary.alias.as_deref(), Some("work"));
+    }
+
+    #[tokio::test]
+    async fn attach_or_create_reattaches_to_an_existing_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let first = Client::connect(Some(socket.clone())).await.unwrap();
+        let (mut first_session, created) = first.attach_or_create("work".into()).await.unwrap();
+        assert!(created);
+        first_session.detach().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let second = Client::connect(Some(socket.clone())).await.unwrap();
+        let (second_session, created) = second.attach_or_create("work".into()).await.unwrap();
+        assert!(!created);
+        assert_eq!(second_session.id, first_session.id);
+    }
+
+    #[tokio::test]
+    async fn attach_or_create_rejects_an_already_attached_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let first = Client::connect(Some(socket.clone())).await.unwrap();
+        let (_first_session, _created) = first.attach_or_create("work".into()).await.unwrap();
+
+        let second = Client::connect(Some(socket.clone())).await.unwrap();
+        let err = second.attach_or_create("work".into()).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Session(crate::error::ClientError::Occupied)
+        ));
+    }
+
+    #[tokio::test]
+    async fn tag_session_adds_and_removes_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let session = client.new_session().await.unwrap();
+        let id = session.id;
+
+        let mut tagger = Client::connect(Some(socket.clone())).await.unwrap();
+        tagger
+            .tag_session(id, vec!["project:foo".into(), "wip".into()], vec![])
+            .await
+            .unwrap();
+
+        let mut lister = Client::connect(Some(socket.clone())).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        let mut tags = sessions[0].tags.clone();
+        tags.sort();
+        assert_eq!(tags, vec!["project:foo".to_string(), "wip".to_string()]);
+
+        let mut tagger = Client::connect(Some(socket.clone())).await.unwrap();
+        tagger
+            .tag_session(id, vec![], vec!["wip".into()])
+            .await
+            .unwrap();
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert_eq!(sessions[0].tags, vec!["project:foo".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn tag_session_rejects_an_unknown_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut tagger = Client::connect(Some(socket)).await.unwrap();
+        let err = tagger.tag_session(99, vec!["wip".into()], vec![]).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Session(crate::error::ClientError::SessionNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn list_sessions_filters_by_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let tagged = client.new_session().await.unwrap();
+        let tagged_id = tagged.id;
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let _untagged = client.new_session().await.unwrap();
+
+        let mut tagger = Client::connect(Some(socket.clone())).await.unwrap();
+        tagger
+            .tag_session(tagged_id, vec!["wip".into()], vec![])
+            .await
+            .unwrap();
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister
+            .list_sessions_with_tag(SortBy::Id, false, Some("wip".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(sessions.iter().map(|s| s.id).collect::<Vec<_>>(), vec![tagged_id]);
+    }
+
+    #[tokio::test]
+    async fn resize_is_reflected_in_list_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let mut session = client.new_session().await.unwrap();
+        let id = session.id;
+        session.resize(40, 120).await.unwrap();
+        // Give the session task a moment to process the request and notify
+        // the server before listing.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut lister = Client::connect(Some(socket)).await.unwrap();
+        let sessions = lister.list_sessions(SortBy::Id, false).await.unwrap();
+        assert_eq!(sessions.iter().find(|s| s.id == id).unwrap().size, Some((40, 120)));
+    }
+
+    #[tokio::test]
+    async fn kill_sessions_reports_a_result_per_id_and_does_not_abort_on_a_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let session_a = client.new_session().await.unwrap();
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let session_b = client.new_session().await.unwrap();
+        let (id_a, id_b) = (session_a.id, session_b.id);
+        let missing_id = id_a.max(id_b) + 1;
+
+        let mut client = Client::connect(Some(socket)).await.unwrap();
+        let results = client
+            .kill_sessions(vec![id_a, missing_id, id_b], false)
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].id, id_a);
+        assert!(results[0].error.is_none());
+        assert_eq!(results[1].id, missing_id);
+        assert_eq!(results[1].error.as_deref(), Some("no such session"));
+        assert_eq!(results[2].id, id_b);
+        assert!(results[2].error.is_none());
+    }
+
+    #[tokio::test]
+    async fn stalled_connection_is_closed_after_the_handshake_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let config = ServerConfig {
+            handshake_timeout: Duration::from_millis(50),
+            ..ServerConfig::default()
+        };
+        let server = Server::new(Some(socket.clone()), config).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // Connect but never send a request.
+        let conn = tokio_seqpacket::UnixSeqpacket::connect(&socket)
+            .await
+            .unwrap();
+        let mut channel = Channel::new(conn);
+
+        // The daemon should close its end once the timeout elapses, which
+        // surfaces here as a clean EOF rather than a hang.
+        let response = tokio::time::timeout(
+            Duration::from_millis(500),
+            channel.recv::<Response>(),
+        )
+        .await
+        .expect("daemon did not close the stalled connection in time")
+        .unwrap();
+        assert!(response.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_prompt_first_exchange_completes_within_a_short_handshake_timeout() {
+        // The response send is now bounded by the same `handshake_timeout`
+        // as the initial recv (see `Server::handle_connection`); a peer that
+        // actually reads its response promptly shouldn't be affected by how
+        // short that timeout is.
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let config = ServerConfig {
+            handshake_timeout: Duration::from_millis(50),
+            ..ServerConfig::default()
+        };
+        let server = Server::new(Some(socket.clone()), config).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut client = Client::connect(Some(socket)).await.unwrap();
+        let metrics = client.metrics().await.unwrap();
+        assert!(metrics.contains_key("sessions_live"));
+    }
+
+    #[tokio::test]
+    async fn an_oversized_request_gets_a_protocol_error_reply_not_a_dropped_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let conn = tokio_seqpacket::UnixSeqpacket::connect(&socket)
+            .await
+            .unwrap();
+        // Larger than the server's default `Channel` buffer (1024 bytes), so
+        // the daemon's `recv_bounded` rejects it as `Error::MessageTooLarge`
+        // rather than handing a truncated prefix to bincode.
+        let oversized = vec![0u8; 2048];
+        conn.send(&oversized).await.unwrap();
+
+        let mut channel = Channel::new(conn);
+        let response = tokio::time::timeout(Duration::from_secs(1), channel.recv::<Response>())
+            .await
+            .expect("daemon did not reply in time")
+            .unwrap();
+        assert!(matches!(response, Some(Response::Err(_))));
+    }
+
+    #[tokio::test]
+    async fn version_request_reports_the_running_daemon_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut client = Client::connect(Some(socket)).await.unwrap();
+        let (crate_version, proto_version) = client.version().await.unwrap();
+        assert_eq!(crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(proto_version, PROTO_VERSION);
+    }
+
+    #[tokio::test]
+    async fn handling_a_request_produces_no_stdout_output() {
+        // Everything server-side now goes through `log`/`tracing` (see the
+        // `connection` span in `handle_connection`), never a raw `println!`
+        // straight to the client's terminal. Rather than grep the source (a
+        // stray `println!` could always be added back later), redirect the
+        // real stdout fd for the duration of a request/response cycle and
+        // assert nothing landed in it, the same way `client::read_passphrase`
+        // already reaches for raw fds when there's no safe higher-level API.
+        let capture = tempfile::NamedTempFile::new().unwrap();
+        let saved_stdout = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        assert!(saved_stdout >= 0);
+        let redirected = unsafe { libc::dup2(capture.as_raw_fd(), libc::STDOUT_FILENO) };
+        assert!(redirected >= 0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("no-stdout.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut client = Client::connect(Some(socket)).await.unwrap();
+        let result = client.version().await;
+
+        unsafe { libc::dup2(saved_stdout, libc::STDOUT_FILENO) };
+        unsafe { libc::close(saved_stdout) };
+
+        result.unwrap();
+        let captured = std::fs::read(capture.path()).unwrap();
+        assert!(
+            captured.is_empty(),
+            "unexpected stdout output: {}",
+            String::from_utf8_lossy(&captured)
+        );
+    }
+
+    #[tokio::test]
+    async fn set_log_level_raises_the_daemon_s_active_filter_without_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("log-level.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        crate::logging::set_level(0);
+        assert!(!log::log_enabled!(log::Level::Debug));
+
+        let mut client = Client::connect(Some(socket)).await.unwrap();
+        client.set_log_level(2).await.unwrap();
+        assert!(log::log_enabled!(log::Level::Debug));
+        crate::logging::set_level(0);
+    }
+
+    #[tokio::test]
+    async fn drain_remaining_sessions_aborts_a_session_that_wont_terminate() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("stuck.sock");
+        let listener = UnixSeqpacketListener::bind(&socket).unwrap();
+        let config = ServerConfig {
+            shutdown_deadline: Duration::from_millis(50),
+            ..ServerConfig::default()
+        };
+        let mut server = Server::from_listener(listener, config).unwrap();
+
+        let (events_tx, _events_rx) = mpsc::unbounded_channel();
+        // Simulates a session whose task ignores termination and keeps
+        // running indefinitely, rather than a real `Session` (which would
+        // need a wedged child process to reproduce the same thing).
+        let stuck_task = tokio::spawn(std::future::pending::<()>());
+        server.sessions.insert(
+            1,
+            SessionHandle {
+                alias: None,
+                created_at: SystemTime::now(),
+                attached_peers: 0,
+                cwd: None,
+                files: Vec::new(),
+                env: Vec::new(),
+                last_detached: None,
+                capturing: None,
+                lock: None,
+                tags: Vec::new(),
+                size: None,
+                idle_timeout: None,
+                stats: SessionStats::default(),
+                log_path: None,
+                events: events_tx,
+                task: stuck_task,
+            },
+        );
+
+        let started = std::time::Instant::now();
+        let (clean, forced, failed) = server.drain_remaining_sessions().await;
+
+        assert!(server.sessions.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!((clean, forced), (0, 0));
+        assert_eq!(failed, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn stop_server_reports_the_number_of_sessions_it_had_to_terminate() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let _session1 = client.new_session().await.unwrap();
+        let client = Client::connect(Some(socket.clone())).await.unwrap();
+        let _session2 = client.new_session().await.unwrap();
+
+        let mut stopper = Client::connect(Some(socket)).await.unwrap();
+        let (clean, forced, failed) = stopper.stop_server().await.unwrap();
+        // `begin_shutdown` always terminates forcefully, so both sessions
+        // land in `forced` rather than `clean`.
+        assert_eq!((clean, forced), (0, 2));
+        assert!(failed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stop_server_reports_a_session_that_would_not_terminate_as_failed() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("stuck.sock");
+        let listener = UnixSeqpacketListener::bind(&socket).unwrap();
+        let config = ServerConfig {
+            shutdown_deadline: Duration::from_millis(50),
+            ..ServerConfig::default()
+        };
+        let mut server = Server::from_listener(listener, config).unwrap();
+
+        let (events_tx, _events_rx) = mpsc::unbounded_channel();
+        // Same stand-in as `drain_remaining_sessions_aborts_a_session_that_wont_terminate`:
+        // a session whose task never reports `Terminated` at all.
+        let stuck_task = tokio::spawn(std::future::pending::<()>());
+        server.sessions.insert(
+            1,
+            SessionHandle {
+                alias: None,
+                created_at: SystemTime::now(),
+                attached_peers: 0,
+                cwd: None,
+                files: Vec::new(),
+                env: Vec::new(),
+                last_detached: None,
+                capturing: None,
+                lock: None,
+                tags: Vec::new(),
+                size: None,
+                idle_timeout: None,
+                stats: SessionStats::default(),
+                log_path: None,
+                events: events_tx,
+                task: stuck_task,
+            },
+        );
+
+        let _server_task = tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut stopper = Client::connect(Some(socket)).await.unwrap();
+        let (clean, forced, failed) = stopper.stop_server().await.unwrap();
+        assert_eq!((clean, forced), (0, 0));
+        assert_eq!(failed, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn a_closed_events_channel_makes_run_shut_itself_down() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("test.sock");
+        let mut server = Server::new(Some(socket), ServerConfig::default()).unwrap();
+
+        // `events_tx` is otherwise only cloned into spawned session tasks;
+        // with none running, replacing the server's own copy drops the last
+        // sender and closes `events_rx` outright, simulating the plane
+        // going dead.
+        let (dummy_tx, _dummy_rx) = mpsc::unbounded_channel();
+        server.events_tx = dummy_tx;
+
+        // `run` should notice the closed channel and shut itself down
+        // instead of spinning forever on it.
+        tokio::time::timeout(Duration::from_secs(2), server.run())
+            .await
+            .expect("server did not shut down after its event channel closed")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn two_list_sessions_share_one_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("pipeline.sock");
+        let server = Server::new(Some(socket.clone()), ServerConfig::default()).unwrap();
+        tokio::spawn(server.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // `NewSession` is attaching and consumes its own connection, so the
+        // session is created on one `Client` before a second, dedicated
+        // `Client` issues two non-attaching `ListSessions` requests back to
+        // back over the same connection.
+        let setup = Client::connect(Some(socket.clone())).await.unwrap();
+        let id = setup.new_session().await.unwrap().id;
+
+        let mut client = Client::connect(Some(socket)).await.unwrap();
+        let first = client.list_sessions(SortBy::Id, false).await.unwrap();
+        let second = client.list_sessions(SortBy::Id, false).await.unwrap();
+
+        assert_eq!(first.iter().map(|s| s.id).collect::<Vec<_>>(), vec![id]);
+        assert_eq!(second.iter().map(|s| s.id).collect::<Vec<_>>(), vec![id]);
+    }
+}
@@ -0,0 +1,1767 @@
+//! A single hosted session, running as its own task on the daemon.
+
+use crate::channel::{Channel, DetachableChannel, MirrorPolicy, PeerId};
+use crate::proto::{ServerEvent, SessionId, SessionRequest, SessionResponse, SessionStats};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+/// Caps how large a session's capture file (see [`CaptureLog`]) grows before
+/// it's rotated by truncating and starting over, rather than left to grow
+/// unboundedly for a long-lived session.
+const CAPTURE_MAX_BYTES: u64 = 8 * 1024 * 1024;
+
+/// How long a per-session log file (see [`SessionLog`]) may sit unmodified
+/// before [`crate::server::Server::with_listener`] deletes it on daemon
+/// startup as belonging to a session that's long gone.
+pub const STALE_SESSION_LOG_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Where a per-session log file for `id` lives under `dir` (see
+/// [`SessionLog`]), shared by [`SessionLog::open`] and
+/// `server::SessionHandle::log_path` so the two can never disagree.
+pub fn session_log_path(dir: &Path, id: SessionId) -> PathBuf {
+    dir.join(format!("{id}.log"))
+}
+
+/// A session's output capture file, open for the lifetime of
+/// [`SessionRequest::SetCapture`] being on. See [`Session::capture`].
+struct CaptureLog {
+    file: std::fs::File,
+    path: PathBuf,
+    written: u64,
+}
+
+impl CaptureLog {
+    /// Open (creating if needed) `dir/session-<id>.log`.
+    fn open(dir: &Path, id: SessionId) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("session-{id}.log"));
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self { file, path, written })
+    }
+
+    /// Append `bytes`, rotating (truncating back to empty) first if that
+    /// would push the file past [`CAPTURE_MAX_BYTES`].
+    fn write(&mut self, bytes: &[u8]) {
+        if self.written + bytes.len() as u64 > CAPTURE_MAX_BYTES {
+            if self.file.set_len(0).is_ok() && self.file.seek(SeekFrom::Start(0)).is_ok() {
+                self.written = 0;
+            }
+        }
+        if self.file.write_all(bytes).is_ok() {
+            self.written += bytes.len() as u64;
+        }
+    }
+}
+
+/// A session's own dedicated log file, opened for the session's entire
+/// lifetime when `ServerConfig::per_session_logs` is on (see
+/// [`Session::log`]). Unlike [`CaptureLog`], which records the terminal's
+/// actual output, this holds the session task's own diagnostic messages
+/// (keepalive timeouts, malformed requests, ...) so one misbehaving session
+/// doesn't drown its neighbors out of the shared daemon log.
+struct SessionLog {
+    file: std::fs::File,
+}
+
+impl SessionLog {
+    /// Open (creating if needed) [`session_log_path`], appending to whatever
+    /// a previous run of the same session id already left there.
+    fn open(dir: &Path, id: SessionId) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = session_log_path(dir, id);
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { file })
+    }
+
+    /// Append one timestamped line, in the same format as
+    /// `logging::file_dispatch`'s sink so the two logs read the same way.
+    fn write_line(&mut self, level: log::Level, args: std::fmt::Arguments) {
+        let _ = writeln!(
+            self.file,
+            "{} [{level}] {args}",
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
+        );
+    }
+}
+
+/// An event emitted by a running [`Session`] task back to the
+/// [`crate::server::Server`] that owns it.
+#[derive(Debug, Clone)]
+pub struct SessionEvent {
+    pub sid: SessionId,
+    pub kind: SessionEventKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionEventKind {
+    /// A new session was created. Emitted by the server directly rather than
+    /// routed through a session task, since the session doesn't exist yet
+    /// when it happens.
+    Created,
+    /// A client attached to the session (a fresh one, a takeover, or an
+    /// additional mirror).
+    Attached,
+    /// A peer detached, leaving `remaining` other peers still attached. The
+    /// session only counts as fully detached once this hits zero.
+    ClientDetached { remaining: usize },
+    Terminated { forced: bool },
+    /// The session reported a new working directory via `SetCwd`.
+    CwdChanged(String),
+    /// The session reported a new list of open files via `FilesChanged`.
+    FilesChanged(Vec<String>),
+    /// The session was renamed via `SetAlias`.
+    AliasChanged(String),
+    /// Output capture was turned on (with the resulting file path) or off
+    /// via `SetCapture`.
+    CaptureChanged(Option<String>),
+    /// The client's terminal was resized, reported via `Resize`.
+    SizeChanged { rows: u16, cols: u16 },
+    /// The session's latency/throughput counters changed. Sent after every
+    /// request the session handles; see [`Session::notify_stats`].
+    StatsUpdated(SessionStats),
+}
+
+/// A running session. Owns the [`DetachableChannel`] to whichever client(s)
+/// are currently attached (if any) and reacts to [`ServerEvent`]s from the
+/// server.
+pub struct Session {
+    pub id: SessionId,
+    channel: DetachableChannel,
+    events: mpsc::UnboundedReceiver<ServerEvent>,
+    to_server: mpsc::UnboundedSender<SessionEvent>,
+    /// How long a graceful termination waits for a `TerminateAck` before
+    /// giving up and proceeding anyway. See [`Self::confirm_termination`].
+    terminate_confirm_timeout: Duration,
+    /// Whether an unexpected disconnect that leaves the session with no
+    /// attached peers should terminate it (the historical behavior) rather
+    /// than simply detach it so a client can reattach later. See
+    /// [`Self::handle_disconnect`].
+    exit_on_disconnect: bool,
+    /// How many `SessionResponse::AttachAck` handshakes this session has
+    /// sent so far. See [`Self::send_attach_ack`].
+    attach_seq: u64,
+    /// Where a capture file for this session may be opened, if the daemon is
+    /// configured with `ServerConfig::capture_dir`. `None` means capture is
+    /// unavailable regardless of `SetCapture` requests.
+    capture_dir: Option<PathBuf>,
+    /// The session's currently open capture file, if `SetCapture(true)` has
+    /// been requested and succeeded.
+    capture: Option<CaptureLog>,
+    /// Whether the attached client is currently stopped (`SIGTSTP`) and
+    /// isn't reading from its socket. Output is dropped rather than queued
+    /// while this is set; see `SessionRequest::Suspended`/`Resumed`.
+    suspended: bool,
+    /// How often to probe an attached client with a `SessionResponse::Ping`
+    /// to detect one that's gone silently (e.g. its machine vanished)
+    /// rather than through a clean disconnect or `Detach`. `None` disables
+    /// the keepalive entirely.
+    keepalive_interval: Option<Duration>,
+    /// How long a `Ping` is given to draw a `SessionRequest::Pong` before
+    /// the client is treated as gone. Only meaningful when
+    /// `keepalive_interval` is set.
+    keepalive_timeout: Duration,
+    /// When the next keepalive event (sending a fresh ping, or, once one is
+    /// outstanding, its reply deadline) is due. `None` while keepalive is
+    /// disabled or there's no attached peer to probe.
+    keepalive_deadline: Option<Instant>,
+    /// Whether a `Ping` was sent and hasn't yet been answered with a `Pong`.
+    /// While set, `keepalive_deadline` is that ping's reply deadline rather
+    /// than the time for the next one.
+    ping_outstanding: bool,
+    /// Request latency and message throughput counters, mirrored to the
+    /// server (and from there, `SessionSummary::stats`) via
+    /// [`Self::notify_stats`] after every request.
+    stats: SessionStats,
+    /// This session's own dedicated log file (see [`SessionLog`]), open for
+    /// its entire lifetime if the daemon was started with
+    /// `ServerConfig::per_session_logs` and the file opened successfully.
+    /// `None` routes [`Self::log`] calls to the shared daemon log instead.
+    log: Option<SessionLog>,
+}
+
+impl Session {
+    /// Spawn a session task, returning the sender the server uses to route
+    /// [`ServerEvent`]s to it and a handle to the task itself. `policy`
+    /// governs how many clients may be attached (mirrored) at once and how
+    /// backpressure to a slow one is handled. `terminate_confirm_timeout`
+    /// bounds how long a non-forced termination waits for the attached
+    /// client to acknowledge before proceeding anyway. `exit_on_disconnect`
+    /// controls what happens when a peer goes away without an explicit
+    /// `Detach`; see [`Self::handle_disconnect`]. `capture_dir` is where a
+    /// per-session output capture file may be opened, if the session is ever
+    /// asked to turn capture on; `None` disables capture outright regardless
+    /// of any `SetCapture` request. `keepalive_interval` (`None` disables
+    /// the feature outright) and `keepalive_timeout` bound how often an
+    /// attached client is probed and how long it has to answer; see
+    /// [`Self::handle_keepalive_tick`].
+    ///
+    /// `alias` is the session's alias at spawn time (if any), purely for the
+    /// `session` tracing span below — the session itself doesn't track its
+    /// own alias (see `server::SessionHandle::alias`), since it can change
+    /// after spawn via [`crate::proto::SessionRequest::SetAlias`] without the
+    /// session needing to know.
+    ///
+    /// `session_log_dir` is where this session's own dedicated log file (see
+    /// [`SessionLog`]) is opened, if the daemon is configured with
+    /// `ServerConfig::per_session_logs`; `None` keeps the session's
+    /// diagnostic messages in the shared daemon log, same as before that
+    /// option existed. A failure to open the file is logged and otherwise
+    /// treated the same as `None`, rather than failing the whole spawn.
+    pub fn spawn(
+        id: SessionId,
+        alias: Option<String>,
+        channel: Channel,
+        policy: MirrorPolicy,
+        terminate_confirm_timeout: Duration,
+        exit_on_disconnect: bool,
+        capture_dir: Option<PathBuf>,
+        keepalive_interval: Option<Duration>,
+        keepalive_timeout: Duration,
+        session_log_dir: Option<PathBuf>,
+        to_server: mpsc::UnboundedSender<SessionEvent>,
+    ) -> (mpsc::UnboundedSender<ServerEvent>, JoinHandle<()>) {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let log = session_log_dir.as_deref().and_then(|dir| match SessionLog::open(dir, id) {
+            Ok(log) => Some(log),
+            Err(err) => {
+                log::warn!("session {id}: failed to open per-session log file in {}: {err}", dir.display());
+                None
+            }
+        });
+        let session = Session {
+            id,
+            channel: DetachableChannel::new(channel, policy),
+            events: events_rx,
+            to_server,
+            terminate_confirm_timeout,
+            exit_on_disconnect,
+            attach_seq: 0,
+            capture_dir,
+            capture: None,
+            suspended: false,
+            keepalive_interval,
+            keepalive_timeout,
+            keepalive_deadline: None,
+            ping_outstanding: false,
+            stats: SessionStats::default(),
+            log,
+        };
+        let span = tracing::info_span!("session", sid = id, alias = ?alias);
+        let handle = tokio::spawn(session.run().instrument(span));
+        (events_tx, handle)
+    }
+
+    /// Route one of the session's own diagnostic lines to its dedicated log
+    /// file if [`Self::spawn`] opened one, or the shared daemon log
+    /// otherwise. Doesn't affect the server's own `tracing`/`log` calls
+    /// about this session (e.g. `SessionEventKind::Created`'s `info!` in
+    /// `server.rs`) — those always stay in the main log.
+    fn log(&mut self, level: log::Level, args: std::fmt::Arguments) {
+        match &mut self.log {
+            Some(log) => log.write_line(level, args),
+            None => log::log!(level, "{args}"),
+        }
+    }
+
+    /// Recompute [`Self::keepalive_deadline`] for a fresh ping cycle,
+    /// clearing any outstanding one. Called whenever the client has just
+    /// proven it's alive (attaching, or answering a `Pong`) or the set of
+    /// attached peers changes.
+    fn reset_keepalive(&mut self) {
+        self.ping_outstanding = false;
+        self.keepalive_deadline = if self.channel.peer_count() > 0 {
+            self.keepalive_interval.map(|interval| Instant::now() + interval)
+        } else {
+            None
+        };
+    }
+
+    /// React to [`Self::keepalive_deadline`] elapsing: either a `Ping` was
+    /// already outstanding and went unanswered (the client is treated as
+    /// gone, same as an unexpected disconnect — see
+    /// [`Self::handle_disconnect`]), or it's time to send a fresh one and
+    /// start its reply deadline. Returns `false` once the session should
+    /// stop running entirely.
+    async fn handle_keepalive_tick(&mut self) -> bool {
+        if self.ping_outstanding {
+            let peers_before = self.channel.peer_count();
+            for peer in self.channel.detach_all() {
+                peer.shutdown();
+            }
+            self.ping_outstanding = false;
+            self.keepalive_deadline = None;
+            if peers_before == 0 {
+                return true;
+            }
+            self.log(
+                log::Level::Warn,
+                format_args!("session {}: keepalive timed out, treating the attached client as gone", self.id),
+            );
+            if self.exit_on_disconnect {
+                return self.terminate(true, 1).await;
+            }
+            self.notify_server(SessionEventKind::ClientDetached { remaining: 0 });
+            return true;
+        }
+
+        if self.channel.peer_count() == 0 {
+            self.keepalive_deadline = None;
+            return true;
+        }
+        let _ = self.send(&SessionResponse::Ping).await;
+        self.ping_outstanding = true;
+        self.keepalive_deadline = Some(Instant::now() + self.keepalive_timeout);
+        true
+    }
+
+    /// Send to every attached peer, counting it towards
+    /// [`SessionStats::messages_out`]. Thin wrapper around
+    /// [`DetachableChannel::send`]; see it for delivery semantics.
+    async fn send<T: serde::Serialize>(&mut self, msg: &T) -> crate::error::Result<()> {
+        let result = self.channel.send(msg).await;
+        if result.is_ok() {
+            self.stats.messages_out += 1;
+        }
+        result
+    }
+
+    /// Like [`Self::send`], but buffers for the next peer to attach if none
+    /// is currently attached. Thin wrapper around
+    /// [`DetachableChannel::send_important`].
+    async fn send_important<T: serde::Serialize>(&mut self, msg: &T) -> crate::error::Result<()> {
+        let result = self.channel.send_important(msg).await;
+        if result.is_ok() {
+            self.stats.messages_out += 1;
+        }
+        result
+    }
+
+    /// Send to a single peer, counting it towards
+    /// [`SessionStats::messages_out`]. Thin wrapper around
+    /// [`DetachableChannel::send_to`].
+    async fn send_to<T: serde::Serialize>(&mut self, peer: PeerId, msg: &T) -> crate::error::Result<()> {
+        let result = self.channel.send_to(peer, msg).await;
+        if result.is_ok() {
+            self.stats.messages_out += 1;
+        }
+        result
+    }
+
+    /// Tell the server about the current [`SessionStats`], so
+    /// `SessionSummary::stats`/the SIGUSR1 dump stay up to date. Called
+    /// after every request the session handles.
+    fn notify_stats(&self) {
+        self.notify_server(SessionEventKind::StatsUpdated(self.stats));
+    }
+
+    async fn run(mut self) {
+        self.reset_keepalive();
+        loop {
+            let peers_before = self.channel.peer_count();
+            tokio::select! {
+                event = self.events.recv() => {
+                    match event {
+                        Some(event) => {
+                            if !self.handle_event(event).await {
+                                break;
+                            }
+                        }
+                        None => break,
+                    }
+                }
+                request = self.channel.recv::<SessionRequest>() => {
+                    match request {
+                        Ok(Some((peer, request))) => {
+                            self.stats.messages_in += 1;
+                            let started = Instant::now();
+                            let keep_going = self.handle_request(peer, request).await;
+                            self.stats.record_latency(started.elapsed());
+                            self.notify_stats();
+                            if !keep_going {
+                                break;
+                            }
+                        }
+                        Ok(None) => {
+                            if !self.handle_disconnect(peers_before).await {
+                                break;
+                            }
+                        }
+                        Err(err) => {
+                            // A malformed datagram (e.g. from a mismatched
+                            // client/daemon version) shouldn't take the whole
+                            // session down; log it, tell whoever's attached,
+                            // and keep going. There's no `PeerId` to target
+                            // here since the failure happens before the
+                            // message is even decoded, so a mirrored peer
+                            // that didn't send the bad message sees the
+                            // notice too.
+                            self.log(log::Level::Warn, format_args!("session {}: error receiving request: {err}", self.id));
+                            let _ = self
+                                .send(&SessionResponse::Err(format!("malformed request: {err}")))
+                                .await;
+                        }
+                    }
+                }
+                _ = tokio::time::sleep_until(self.keepalive_deadline.unwrap()), if self.keepalive_deadline.is_some() => {
+                    if !self.handle_keepalive_tick().await {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// React to `recv` returning `None`: either nothing has ever attached
+    /// yet (`peers_before` was already zero, so there's nothing to do but
+    /// keep waiting) or a peer just disconnected without sending an explicit
+    /// `Detach`. In the latter case, a session that still has other mirrored
+    /// peers just reports the departure; one that's now fully unattended
+    /// either detaches (so a client can reattach later) or, if
+    /// `exit_on_disconnect` is set, terminates outright as it always used to
+    /// before mirroring made "detached" a state worth returning to. Returns
+    /// `false` once the session should stop running entirely.
+    async fn handle_disconnect(&mut self, peers_before: usize) -> bool {
+        let peers_after = self.channel.peer_count();
+        if peers_after >= peers_before {
+            return true;
+        }
+        self.reset_keepalive();
+        if peers_after == 0 && self.exit_on_disconnect {
+            return self.terminate(true, 1).await;
+        }
+        self.notify_server(SessionEventKind::ClientDetached { remaining: peers_after });
+        true
+    }
+
+    /// Handle an internal event routed from the server. Returns `false` once
+    /// the session should stop running entirely.
+    async fn handle_event(&mut self, event: ServerEvent) -> bool {
+        match event {
+            ServerEvent::Attach {
+                channel,
+                takeover,
+                alias,
+                cwd,
+                files,
+                size,
+            } => {
+                if takeover {
+                    if let Some(evicted) = self.channel.evict_oldest() {
+                        let _ = evicted.send(&SessionResponse::Detached).await;
+                        evicted.shutdown();
+                    }
+                }
+                match self.channel.attach(channel).await {
+                    Ok(()) => {
+                        let peer = self.channel.peer_count() - 1;
+                        self.send_attach_ack(peer, alias, cwd, files, size).await;
+                    }
+                    Err(mut channel) => {
+                        // The server's own occupancy check should have
+                        // prevented this; treat it as a race and reject
+                        // cleanly rather than silently dropping the
+                        // connection.
+                        let _ = channel
+                            .send(&SessionResponse::Err("session is full".into()))
+                            .await;
+                        channel.shutdown();
+                    }
+                }
+                self.reset_keepalive();
+                self.notify_server(SessionEventKind::Attached);
+                true
+            }
+            ServerEvent::Detach => {
+                // Unlike a client-initiated detach, the client has no other way to
+                // learn it was cut loose here, so tell it explicitly rather than
+                // letting it discover the closed channel as an "unexpected
+                // disconnect".
+                for peer in self.channel.detach_all() {
+                    let _ = peer.send(&SessionResponse::Detached).await;
+                    peer.shutdown();
+                }
+                self.reset_keepalive();
+                self.notify_server(SessionEventKind::ClientDetached { remaining: 0 });
+                true
+            }
+            ServerEvent::Notify(message) => {
+                let _ = self.send(&SessionResponse::Notice(message)).await;
+                true
+            }
+            ServerEvent::Terminate(forced) => {
+                if !forced {
+                    self.confirm_termination().await;
+                }
+                // A detached session can still be killed (e.g. `hxc -k`); the
+                // client that eventually reattaches should still learn it's
+                // gone rather than seeing the connection simply vanish.
+                self.terminate(forced, if forced { 1 } else { 0 }).await
+            }
+            ServerEvent::Reassign(new_id) => {
+                self.id = new_id;
+                true
+            }
+            ServerEvent::Output(bytes) => {
+                if let Some(capture) = &mut self.capture {
+                    capture.write(&bytes);
+                }
+                if !self.suspended {
+                    let _ = self.send(&SessionResponse::Output(bytes)).await;
+                }
+                true
+            }
+            ServerEvent::Deliver(command) => {
+                let _ = self.send(&SessionResponse::Command(command)).await;
+                true
+            }
+        }
+    }
+
+    /// Terminate the session: notify the attached client (if any) and the
+    /// server, and report the run loop should stop. `code` is the exit
+    /// status of the process hosted in the session, once a real editor
+    /// process is wired up; for now it's just 0 for a graceful termination
+    /// or 1 for a forced/errored one. Always returns `false`, matching the
+    /// `handle_*` convention of reporting whether the run loop should
+    /// continue.
+    async fn terminate(&mut self, forced: bool, code: i32) -> bool {
+        let _ = self
+            .send_important(&SessionResponse::Terminated { forced, code })
+            .await;
+        self.notify_server(SessionEventKind::Terminated { forced });
+        false
+    }
+
+    /// Send `peer` a `SessionResponse::AttachAck` describing the session's
+    /// current state, the first message it should see after
+    /// `DetachableChannel::attach` hands it a slot. Best-effort: if `peer`
+    /// has already gone away there's nothing more useful to do than drop it,
+    /// same as any other post-attach send.
+    async fn send_attach_ack(
+        &mut self,
+        peer: PeerId,
+        alias: Option<String>,
+        cwd: Option<String>,
+        files: Vec<String>,
+        size: Option<(u16, u16)>,
+    ) {
+        self.attach_seq += 1;
+        let _ = self
+            .send_to(
+                peer,
+                &SessionResponse::AttachAck {
+                    sid: self.id,
+                    alias,
+                    cwd,
+                    files,
+                    size,
+                    seq: self.attach_seq,
+                },
+            )
+            .await;
+    }
+
+    /// Give an attached client a chance to confirm a graceful termination
+    /// before it actually happens: send `SessionResponse::ConfirmTerminate`
+    /// and wait up to `terminate_confirm_timeout` for its
+    /// `SessionRequest::TerminateAck`. A detached session, a client that
+    /// disconnects mid-wait, or the timeout elapsing all fall through the
+    /// same way: there's nothing left to confirm with, so termination
+    /// proceeds exactly as it would have without asking.
+    async fn confirm_termination(&mut self) {
+        if self.channel.is_detached() {
+            return;
+        }
+        if self.send(&SessionResponse::ConfirmTerminate).await.is_err() {
+            return;
+        }
+        let ack = tokio::time::timeout(
+            self.terminate_confirm_timeout,
+            self.channel.recv::<SessionRequest>(),
+        );
+        match ack.await {
+            Ok(Ok(Some((_peer, SessionRequest::TerminateAck { save })))) => {
+                self.stats.messages_in += 1;
+                self.log(log::Level::Debug, format_args!("session {}: client acked termination (save={save})", self.id));
+            }
+            Ok(Ok(other)) => {
+                self.stats.messages_in += 1;
+                self.log(
+                    log::Level::Debug,
+                    format_args!("session {}: expected a TerminateAck, got {other:?}; proceeding anyway", self.id),
+                );
+            }
+            Ok(Err(err)) => {
+                self.log(log::Level::Warn, format_args!("session {}: error waiting for TerminateAck: {err}", self.id));
+            }
+            Err(_) => self.log(
+                log::Level::Debug,
+                format_args!(
+                    "session {}: timed out after {:?} waiting for TerminateAck",
+                    self.id, self.terminate_confirm_timeout
+                ),
+            ),
+        }
+    }
+
+    /// Handle a request from an attached client. Returns `false` once the
+    /// session should stop running entirely.
+    async fn handle_request(&mut self, peer: PeerId, request: SessionRequest) -> bool {
+        match request {
+            SessionRequest::Detach => {
+                self.channel.detach_peer(peer);
+                self.reset_keepalive();
+                self.notify_server(SessionEventKind::ClientDetached {
+                    remaining: self.channel.peer_count(),
+                });
+                true
+            }
+            SessionRequest::Terminate { code } => self.terminate(false, code).await,
+            SessionRequest::SetCwd(path) => {
+                if path.is_empty() || !std::path::Path::new(&path).is_absolute() {
+                    let _ = self
+                        .send_to(
+                            peer,
+                            &SessionResponse::Err(format!(
+                                "invalid cwd (must be a non-empty absolute path): {path:?}"
+                            )),
+                        )
+                        .await;
+                } else {
+                    self.notify_server(SessionEventKind::CwdChanged(path));
+                }
+                true
+            }
+            SessionRequest::FilesChanged(files) => {
+                self.notify_server(SessionEventKind::FilesChanged(files));
+                true
+            }
+            SessionRequest::SetAlias(alias) => {
+                if alias.is_empty() {
+                    let _ = self
+                        .send_to(peer, &SessionResponse::Err("alias must not be empty".into()))
+                        .await;
+                } else {
+                    self.notify_server(SessionEventKind::AliasChanged(alias));
+                }
+                true
+            }
+            SessionRequest::SetCapture(enable) => {
+                if !enable {
+                    if self.capture.take().is_some() {
+                        self.notify_server(SessionEventKind::CaptureChanged(None));
+                    }
+                    return true;
+                }
+                let Some(dir) = self.capture_dir.clone() else {
+                    let _ = self
+                        .send_to(
+                            peer,
+                            &SessionResponse::Err("no capture directory is configured".into()),
+                        )
+                        .await;
+                    return true;
+                };
+                match CaptureLog::open(&dir, self.id) {
+                    Ok(capture) => {
+                        let path = capture.path.to_string_lossy().into_owned();
+                        self.capture = Some(capture);
+                        self.notify_server(SessionEventKind::CaptureChanged(Some(path)));
+                    }
+                    Err(err) => {
+                        let _ = self
+                            .send_to(
+                                peer,
+                                &SessionResponse::Err(format!(
+                                    "failed to open capture file: {err}"
+                                )),
+                            )
+                            .await;
+                    }
+                }
+                true
+            }
+            SessionRequest::Resize { rows, cols } => {
+                self.notify_server(SessionEventKind::SizeChanged { rows, cols });
+                true
+            }
+            SessionRequest::Suspended => {
+                self.suspended = true;
+                true
+            }
+            SessionRequest::Resumed { rows, cols } => {
+                self.suspended = false;
+                self.notify_server(SessionEventKind::SizeChanged { rows, cols });
+                true
+            }
+            // Only meaningful as a reply to `SessionResponse::ConfirmTerminate`,
+            // which `confirm_termination` waits for directly rather than
+            // routing through this match. Receiving one here means it arrived
+            // out of context (e.g. a stray retransmit, or a confused client);
+            // reject it instead of silently ignoring or panicking.
+            SessionRequest::TerminateAck { .. } => {
+                let _ = self
+                    .send_to(
+                        peer,
+                        &SessionResponse::Err("no termination is awaiting an ack".into()),
+                    )
+                    .await;
+                true
+            }
+            SessionRequest::Pong => {
+                self.reset_keepalive();
+                true
+            }
+        }
+    }
+
+    fn notify_server(&self, kind: SessionEventKind) {
+        let _ = self.to_server.send(SessionEvent { sid: self.id, kind });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_seqpacket::UnixSeqpacket;
+
+    /// The next event from `from_session`, skipping the
+    /// `SessionEventKind::StatsUpdated` that follows every request.
+    async fn next_non_stats_event(from_session: &mut mpsc::UnboundedReceiver<SessionEvent>) -> SessionEvent {
+        loop {
+            let event = from_session.recv().await.unwrap();
+            if !matches!(event.kind, SessionEventKind::StatsUpdated(_)) {
+                return event;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn notice_is_delivered_ahead_of_terminated() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        events
+            .send(ServerEvent::Notify("daemon shutting down".into()))
+            .unwrap();
+        events.send(ServerEvent::Terminate(true)).unwrap();
+
+        let first = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(first, SessionResponse::Notice(_)));
+
+        let second = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(second, SessionResponse::Terminated { forced: true, code: 1 }));
+    }
+
+    #[tokio::test]
+    async fn a_client_initiated_terminate_is_always_graceful() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (_events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::Terminate { code: 0 })
+            .await
+            .unwrap();
+
+        let terminated = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            terminated,
+            SessionResponse::Terminated { forced: false, code: 0 }
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_client_initiated_terminate_reports_the_code_it_was_given() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (_events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::Terminate { code: 7 })
+            .await
+            .unwrap();
+
+        let terminated = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            terminated,
+            SessionResponse::Terminated { forced: false, code: 7 }
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_terminate_ack_outside_a_handshake_is_rejected_but_the_session_keeps_running() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (_events, task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::TerminateAck { save: false })
+            .await
+            .unwrap();
+        let response = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(response, SessionResponse::Err(_)));
+        assert!(!task.is_finished());
+
+        // The session should still be responsive afterwards.
+        client_channel
+            .send(&SessionRequest::SetAlias("still-alive".into()))
+            .await
+            .unwrap();
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn a_garbage_datagram_is_reported_but_the_session_keeps_running() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (_events, task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        // A datagram that isn't valid bincode for any `SessionRequest`, sent
+        // on the raw socket since `Channel::send` only ever encodes valid
+        // messages.
+        client_side.send(&[0xff; 16]).await.unwrap();
+        let mut client_channel = Channel::new(client_side);
+
+        let response = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(response, SessionResponse::Err(_)));
+        assert!(!task.is_finished());
+
+        // The session should still be responsive afterwards.
+        client_channel
+            .send(&SessionRequest::SetAlias("still-alive".into()))
+            .await
+            .unwrap();
+        let event = from_session.recv().await.unwrap();
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::AliasChanged(ref alias) if alias == "still-alive"
+        ));
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn set_cwd_reports_a_valid_path_and_rejects_an_invalid_one() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (_events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::SetCwd("relative/path".into()))
+            .await
+            .unwrap();
+        let response = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            response,
+            SessionResponse::Err(ref m)
+                if m == "invalid cwd (must be a non-empty absolute path): \"relative/path\""
+        ));
+
+        client_channel
+            .send(&SessionRequest::SetCwd("/home/alice/project".into()))
+            .await
+            .unwrap();
+
+        let event = next_non_stats_event(&mut from_session).await;
+        assert_eq!(event.sid, 1);
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::CwdChanged(ref cwd) if cwd == "/home/alice/project"
+        ));
+    }
+
+    #[tokio::test]
+    async fn set_alias_reports_a_name_and_rejects_an_empty_one() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (_events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::SetAlias(String::new()))
+            .await
+            .unwrap();
+        let response = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(response, SessionResponse::Err(_)));
+
+        client_channel
+            .send(&SessionRequest::SetAlias("scratch".into()))
+            .await
+            .unwrap();
+
+        let event = next_non_stats_event(&mut from_session).await;
+        assert_eq!(event.sid, 1);
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::AliasChanged(ref alias) if alias == "scratch"
+        ));
+    }
+
+    #[tokio::test]
+    async fn files_changed_is_forwarded_including_an_empty_list() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (_events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::FilesChanged(vec![
+                "/tmp/a.rs".into(),
+                "/tmp/b.rs".into(),
+            ]))
+            .await
+            .unwrap();
+        let event = from_session.recv().await.unwrap();
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::FilesChanged(ref files) if files.len() == 2
+        ));
+
+        client_channel
+            .send(&SessionRequest::FilesChanged(Vec::new()))
+            .await
+            .unwrap();
+        let event = next_non_stats_event(&mut from_session).await;
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::FilesChanged(ref files) if files.is_empty()
+        ));
+    }
+
+    #[tokio::test]
+    async fn resize_is_forwarded_as_a_size_changed_event() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (_events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::Resize { rows: 40, cols: 120 })
+            .await
+            .unwrap();
+        let event = from_session.recv().await.unwrap();
+        assert_eq!(event.sid, 1);
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::SizeChanged { rows: 40, cols: 120 }
+        ));
+    }
+
+    #[tokio::test]
+    async fn suspended_output_is_dropped_and_resuming_reports_the_new_size() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::Suspended)
+            .await
+            .unwrap();
+        // Give the session task a moment to record the suspension before
+        // output arrives, since the two happen on different channels.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        events
+            .send(ServerEvent::Output(b"dropped while suspended".to_vec()))
+            .unwrap();
+
+        client_channel
+            .send(&SessionRequest::Resumed { rows: 50, cols: 200 })
+            .await
+            .unwrap();
+        let event = next_non_stats_event(&mut from_session).await;
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::SizeChanged { rows: 50, cols: 200 }
+        ));
+
+        events
+            .send(ServerEvent::Output(b"delivered after resuming".to_vec()))
+            .unwrap();
+        let output = client_channel.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(
+            output,
+            SessionResponse::Output(ref b) if b == b"delivered after resuming"
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_reattach_delivers_an_attach_ack_snapshotting_the_session_state() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+        drop(client_channel);
+
+        let (server_side_b, client_side_b) = UnixSeqpacket::pair().unwrap();
+        let mut client_b = Channel::new(client_side_b);
+        events
+            .send(ServerEvent::Attach {
+                channel: Channel::new(server_side_b),
+                takeover: false,
+                alias: Some("scratch".into()),
+                cwd: Some("/home/alice/project".into()),
+                files: vec!["/home/alice/project/a.rs".into()],
+                size: Some((40, 120)),
+            })
+            .unwrap();
+
+        let ack = client_b.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(
+            ack,
+            SessionResponse::AttachAck {
+                sid: 1,
+                alias: Some(ref alias),
+                cwd: Some(ref cwd),
+                ref files,
+                size: Some((40, 120)),
+                seq: 1,
+            } if alias == "scratch" && cwd == "/home/alice/project" && files.len() == 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_second_client_mirrors_the_first_and_both_see_notices() {
+        let (server_side, client_a) = UnixSeqpacket::pair().unwrap();
+        let mut client_a = Channel::new(client_a);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(2),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        let (server_side_b, client_b) = UnixSeqpacket::pair().unwrap();
+        let mut client_b = Channel::new(client_b);
+        events
+            .send(ServerEvent::Attach {
+                channel: Channel::new(server_side_b),
+                takeover: false,
+                alias: None,
+                cwd: None,
+                files: Vec::new(),
+                size: None,
+            })
+            .unwrap();
+
+        // The newly attached peer sees an `AttachAck` before anything else.
+        let ack = client_b.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(ack, SessionResponse::AttachAck { seq: 1, .. }));
+
+        events
+            .send(ServerEvent::Notify("hello".into()))
+            .unwrap();
+
+        let a_msg = client_a.recv::<SessionResponse>().await.unwrap().unwrap();
+        let b_msg = client_b.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(a_msg, SessionResponse::Notice(ref m) if m == "hello"));
+        assert!(matches!(b_msg, SessionResponse::Notice(ref m) if m == "hello"));
+    }
+
+    #[tokio::test]
+    async fn deliver_event_reaches_the_attached_client_as_a_command() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_side = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(2),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        events
+            .send(ServerEvent::Deliver(":write-all".into()))
+            .unwrap();
+
+        let msg = client_side.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(msg, SessionResponse::Command(ref c) if c == ":write-all"));
+    }
+
+    #[tokio::test]
+    async fn one_peer_detaching_leaves_the_other_attached() {
+        let (server_side, client_a) = UnixSeqpacket::pair().unwrap();
+        let mut client_a = Channel::new(client_a);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(2),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        let (server_side_b, client_b) = UnixSeqpacket::pair().unwrap();
+        let mut client_b = Channel::new(client_b);
+        events
+            .send(ServerEvent::Attach {
+                channel: Channel::new(server_side_b),
+                takeover: false,
+                alias: None,
+                cwd: None,
+                files: Vec::new(),
+                size: None,
+            })
+            .unwrap();
+        // Drain the `Attached` event from the first peer joining as a mirror,
+        // and the `AttachAck` it receives as its first message.
+        let _ = from_session.recv().await.unwrap();
+        let ack = client_b.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(ack, SessionResponse::AttachAck { .. }));
+
+        client_a.send(&SessionRequest::Detach).await.unwrap();
+        let event = from_session.recv().await.unwrap();
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::ClientDetached { remaining: 1 }
+        ));
+
+        // The second peer should be unaffected: it can still see notices.
+        events
+            .send(ServerEvent::Notify("still here".into()))
+            .unwrap();
+        let msg = client_b.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(msg, SessionResponse::Notice(ref m) if m == "still here"));
+    }
+
+    #[tokio::test]
+    async fn graceful_termination_waits_for_the_client_to_ack() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        events.send(ServerEvent::Terminate(false)).unwrap();
+
+        let confirm = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(confirm, SessionResponse::ConfirmTerminate));
+
+        client_channel
+            .send(&SessionRequest::TerminateAck { save: true })
+            .await
+            .unwrap();
+
+        let terminated = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(terminated, SessionResponse::Terminated { forced: false, code: 0 }));
+    }
+
+    #[tokio::test]
+    async fn graceful_termination_proceeds_once_the_ack_timeout_elapses() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_millis(50),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        events.send(ServerEvent::Terminate(false)).unwrap();
+
+        let confirm = client_channel
+            .recv::<SessionResponse>()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(confirm, SessionResponse::ConfirmTerminate));
+
+        // Never send a `TerminateAck`; the session should proceed anyway once
+        // the timeout elapses.
+        let terminated = tokio::time::timeout(Duration::from_secs(2), async {
+            client_channel.recv::<SessionResponse>().await
+        })
+        .await
+        .expect("session never gave up waiting for the ack")
+        .unwrap()
+        .unwrap();
+        assert!(matches!(terminated, SessionResponse::Terminated { forced: false, code: 0 }));
+    }
+
+    #[tokio::test]
+    async fn graceful_termination_of_a_detached_session_skips_the_handshake() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (events, task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(30),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        events.send(ServerEvent::Detach).unwrap();
+        drop(client_channel);
+
+        events.send(ServerEvent::Terminate(false)).unwrap();
+
+        // With no peer to ask, this should complete immediately rather than
+        // sitting on the (deliberately long) ack timeout.
+        tokio::time::timeout(Duration::from_secs(2), task)
+            .await
+            .expect("detached termination waited on a handshake with no one attached")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_abrupt_disconnect_detaches_rather_than_terminating_by_default() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (events, task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        // Drop the client end abruptly, as if the process had been killed,
+        // rather than sending a `Detach`.
+        drop(client_channel);
+
+        let event = from_session.recv().await.unwrap();
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::ClientDetached { remaining: 0 }
+        ));
+        assert!(
+            !task.is_finished(),
+            "session should still be running, just detached"
+        );
+
+        // A new client can reattach right away.
+        let (server_side_b, client_side_b) = UnixSeqpacket::pair().unwrap();
+        let mut client_b = Channel::new(client_side_b);
+        events
+            .send(ServerEvent::Attach {
+                channel: Channel::new(server_side_b),
+                takeover: false,
+                alias: None,
+                cwd: None,
+                files: Vec::new(),
+                size: None,
+            })
+            .unwrap();
+        let ack = client_b.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(ack, SessionResponse::AttachAck { .. }));
+
+        events.send(ServerEvent::Notify("hi".into())).unwrap();
+        let msg = client_b.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(msg, SessionResponse::Notice(ref m) if m == "hi"));
+
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn exit_on_disconnect_terminates_the_session_instead_of_detaching() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (_events, task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            true,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        drop(client_channel);
+
+        let event = from_session.recv().await.unwrap();
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::Terminated { forced: true }
+        ));
+
+        tokio::time::timeout(Duration::from_secs(2), task)
+            .await
+            .expect("session did not terminate after an unexpected disconnect")
+            .unwrap();
+    }
+
+    /// An explicit `Request::KillSession` immediately followed by the
+    /// client's connection dropping (the daemon closes it as part of
+    /// terminating) must report `Terminated`, never `ClientDetached` — the
+    /// disconnect here is an expected side effect of the kill, not a
+    /// separate event of its own.
+    #[tokio::test]
+    async fn kill_then_disconnect_reports_terminated_not_client_detached() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (events, task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        events.send(ServerEvent::Terminate(true)).unwrap();
+        drop(client_channel);
+
+        let event = from_session.recv().await.unwrap();
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::Terminated { forced: true }
+        ));
+        assert!(
+            from_session.try_recv().is_err(),
+            "the disconnect caused by the kill shouldn't be reported again as its own event"
+        );
+
+        tokio::time::timeout(Duration::from_secs(2), task)
+            .await
+            .expect("session did not terminate after an explicit kill")
+            .unwrap();
+    }
+
+    /// A client-initiated `Detach` (no kill involved) followed by the same
+    /// connection dropping must report only `ClientDetached`, with the
+    /// session left running — the inverse of
+    /// [`kill_then_disconnect_reports_terminated_not_client_detached`].
+    #[tokio::test]
+    async fn client_detach_then_disconnect_reports_client_detached_not_terminated() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (events, task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        events.send(ServerEvent::Detach).unwrap();
+        drop(client_channel);
+
+        let event = from_session.recv().await.unwrap();
+        assert!(matches!(
+            event.kind,
+            SessionEventKind::ClientDetached { remaining: 0 }
+        ));
+        assert!(
+            from_session.try_recv().is_err(),
+            "the disconnect caused by the detach shouldn't be reported again as its own event"
+        );
+        assert!(!task.is_finished(), "a plain detach must not terminate the session");
+    }
+
+    #[tokio::test]
+    async fn enabling_capture_tees_output_to_the_session_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            Some(dir.path().to_path_buf()),
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::SetCapture(true))
+            .await
+            .unwrap();
+        // Give the session task a moment to open the file before output
+        // arrives, since the two happen on different channels.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        events
+            .send(ServerEvent::Output(b"hello from the session".to_vec()))
+            .unwrap();
+        let output = client_channel.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(output, SessionResponse::Output(ref b) if b == b"hello from the session"));
+
+        let captured = std::fs::read_to_string(dir.path().join("session-1.log")).unwrap();
+        assert_eq!(captured, "hello from the session");
+    }
+
+    #[tokio::test]
+    async fn set_capture_is_rejected_without_a_configured_directory() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (_events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        client_channel
+            .send(&SessionRequest::SetCapture(true))
+            .await
+            .unwrap();
+        let response = client_channel.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(response, SessionResponse::Err(_)));
+    }
+
+    #[tokio::test]
+    async fn per_session_log_dir_gets_the_sessions_own_diagnostic_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let (to_server, _discard) = mpsc::unbounded_channel();
+        let (_events, task) = Session::spawn(
+            7,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            Some(dir.path().to_path_buf()),
+            to_server,
+        );
+
+        // A datagram that isn't valid bincode for any `SessionRequest` makes
+        // `run()` call `Self::log` with a warning (see
+        // `a_garbage_datagram_is_reported_but_the_session_keeps_running`),
+        // which should land in this session's own file rather than wherever
+        // the shared daemon log would otherwise go.
+        client_side.send(&[0xff; 16]).await.unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let response = client_channel.recv::<SessionResponse>().await.unwrap().unwrap();
+        assert!(matches!(response, SessionResponse::Err(_)));
+        assert!(!task.is_finished());
+
+        let logged = std::fs::read_to_string(session_log_path(dir.path(), 7)).unwrap();
+        assert!(
+            logged.contains("session 7: error receiving request"),
+            "expected the session's own log file to contain its diagnostic line, got: {logged:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn stats_track_message_throughput_and_latency_across_several_requests() {
+        let (server_side, client_side) = UnixSeqpacket::pair().unwrap();
+        let mut client_channel = Channel::new(client_side);
+        let (to_server, mut from_session) = mpsc::unbounded_channel();
+        let (_events, _task) = Session::spawn(
+            1,
+            None,
+            Channel::new(server_side),
+            MirrorPolicy::new(1),
+            Duration::from_secs(5),
+            false,
+            None,
+            None,
+            Duration::from_secs(10),
+            None,
+            to_server,
+        );
+
+        // A `TerminateAck` outside a termination handshake is always
+        // rejected with a direct error reply, so each one is exactly one
+        // inbound message and one outbound reply.
+        const REQUESTS: u64 = 5;
+        for _ in 0..REQUESTS {
+            client_channel
+                .send(&SessionRequest::TerminateAck { save: false })
+                .await
+                .unwrap();
+            let response = client_channel.recv::<SessionResponse>().await.unwrap().unwrap();
+            assert!(matches!(response, SessionResponse::Err(_)));
+        }
+
+        let mut stats = None;
+        for _ in 0..REQUESTS {
+            let event = from_session.recv().await.unwrap();
+            assert_eq!(event.sid, 1);
+            match event.kind {
+                SessionEventKind::StatsUpdated(s) => stats = Some(s),
+                other => panic!("expected StatsUpdated, got {other:?}"),
+            }
+        }
+
+        let stats = stats.unwrap();
+        assert_eq!(stats.messages_in, REQUESTS);
+        assert_eq!(stats.messages_out, REQUESTS);
+        assert_eq!(stats.latency_count, REQUESTS);
+        assert_eq!(
+            stats.latency_buckets.iter().sum::<u64>(),
+            REQUESTS,
+            "every recorded latency should land in exactly one bucket"
+        );
+    }
+}